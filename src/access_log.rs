@@ -0,0 +1,87 @@
+//! 访问日志 - 每个客户端请求一条记录，写入独立的文件，与界面日志互不影响
+
+use std::io;
+use std::path::Path;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use crate::appender::BufferedAppender;
+
+/// 访问日志格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum AccessLogFormat {
+    /// 每行一个 JSON 对象（ndjson）
+    #[default]
+    Json,
+    /// Apache combined 格式，末尾追加提供商、耗时（毫秒）和估算的Token数
+    Combined,
+}
+
+/// 一条访问日志记录
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogRecord {
+    pub timestamp: DateTime<Local>,
+    pub method: String,
+    pub path: String,
+    /// HTTP 版本，如 `HTTP/1.1`
+    pub version: String,
+    pub status: u16,
+    /// 响应体字节数（取自 Content-Length，流式响应未知时为 None）
+    pub bytes: Option<u64>,
+    /// 处理请求的提供商，请求没有到达上游时为 None
+    pub provider: Option<String>,
+    pub latency_ms: u64,
+    /// 估算的Token使用量（失败的请求为 0）
+    pub tokens: u64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessLogRecord {
+    /// 按指定格式输出一行（不含换行符）
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+            AccessLogFormat::Combined => {
+                let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+                format!(
+                    "- - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {} {} {}",
+                    self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+                    self.method,
+                    self.path,
+                    self.version,
+                    self.status,
+                    self.bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".to_string()),
+                    or_dash(&self.referer),
+                    or_dash(&self.user_agent),
+                    or_dash(&self.provider),
+                    self.latency_ms,
+                    self.tokens,
+                )
+            }
+        }
+    }
+}
+
+/// 访问日志文件（追加写入，见 `BufferedAppender`）
+#[derive(Debug)]
+pub struct AccessLog {
+    file: BufferedAppender,
+    format: AccessLogFormat,
+}
+
+impl AccessLog {
+    /// 以追加方式打开访问日志文件，不存在时创建
+    pub fn open(path: &Path, format: AccessLogFormat) -> io::Result<Self> {
+        Ok(Self { file: BufferedAppender::open(path, None)?, format })
+    }
+
+    /// 写入一条记录，写入失败时丢弃（访问日志不应影响请求处理）
+    pub fn write(&self, record: &AccessLogRecord) {
+        self.file.append_line(&record.format(self.format));
+    }
+
+    /// 把缓冲的记录写入文件
+    pub fn flush(&self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,285 @@
+//! 管理接口 - 以JSON形式暴露代理内部状态
+
+use std::sync::Arc;
+use chrono::Local;
+use hyper::{Body, Method, Request, Response};
+use hyper::header::AUTHORIZATION;
+use crate::provider::Provider;
+use crate::proxy::{log_event, ProxyState, HISTOGRAM_BUCKET_DURATION, REQUEST_LOG_CAPACITY};
+use crate::ui::{LogLevel, Logger};
+
+/// 管理接口路径前缀
+pub const ADMIN_PATH_PREFIX: &str = "/_admin";
+
+/// 默认返回的请求记录条数
+const DEFAULT_RECENT_REQUESTS: usize = 100;
+
+/// 运行状态接口路径
+pub const STATUS_PATH: &str = "/_status";
+
+/// 请求量直方图接口路径
+pub const METRICS_PATH: &str = "/_metrics";
+
+/// 仪表盘页面路径
+pub const DASHBOARD_PATH: &str = "/_dashboard";
+
+/// 仪表盘页面模板（内联CSS和JS，不依赖外部资源），`__SNAPSHOT__` 替换为状态快照JSON
+const DASHBOARD_TEMPLATE: &str = include_str!("dashboard.html");
+
+/// 判断请求路径是否属于管理接口
+pub fn is_admin_path(path: &str) -> bool {
+    path == ADMIN_PATH_PREFIX || path.starts_with("/_admin/") || path == STATUS_PATH || path == METRICS_PATH || path == DASHBOARD_PATH
+}
+
+/// 处理独立管理端口上的请求：只提供管理接口，其他路径返回 404，不转发到上游
+pub async fn handle_admin_port_request(
+    req: Request<Body>,
+    providers: Arc<Vec<Provider>>,
+    state: Arc<ProxyState>,
+    logger: Option<Arc<Logger>>,
+) -> Result<Response<Body>, std::convert::Infallible> {
+    if !is_admin_path(req.uri().path()) {
+        return Ok(json_error(404, "Not Found"));
+    }
+    Ok(handle_admin_request(&req, &providers, &state, &logger).await)
+}
+
+/// 处理管理接口请求
+pub async fn handle_admin_request(req: &Request<Body>, providers: &[Provider], state: &ProxyState, logger: &Option<Arc<Logger>>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, STATUS_PATH) => status_response(providers, state),
+        (_, STATUS_PATH) => json_error(405, "Method Not Allowed"),
+        (&Method::GET, METRICS_PATH) => metrics_response(providers, state),
+        (_, METRICS_PATH) => json_error(405, "Method Not Allowed"),
+        (&Method::GET, DASHBOARD_PATH) => dashboard_response(providers, state),
+        (_, DASHBOARD_PATH) => json_error(405, "Method Not Allowed"),
+        (&Method::GET, "/_admin/requests") => {
+            let limit = query_param(req.uri(), "limit")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(DEFAULT_RECENT_REQUESTS)
+                .min(REQUEST_LOG_CAPACITY);
+            json_response(200, &state.recent_requests(limit))
+        }
+        (_, "/_admin/requests") => json_error(405, "Method Not Allowed"),
+        (&Method::GET, "/_admin/diagnostics") => json_response(200, &serde_json::json!({
+            "provider_count": providers.len(),
+            "map_sizes": state.map_sizes(),
+            "request_log_capacity": REQUEST_LOG_CAPACITY,
+        })),
+        (_, "/_admin/diagnostics") => json_error(405, "Method Not Allowed"),
+        (&Method::POST, "/_admin/reload") => reload_response(req, state, logger).await,
+        (_, "/_admin/reload") => json_error(405, "Method Not Allowed"),
+        (&Method::GET, "/_admin/tags") => tags_response(providers),
+        (method, path) if path.starts_with("/_admin/tags/") => {
+            if method != Method::POST {
+                return json_error(405, "Method Not Allowed");
+            }
+            if let Some(response) = check_admin_token(req, state) {
+                return response;
+            }
+            match path["/_admin/tags/".len()..].rsplit_once('/') {
+                Some((tag, "enable")) => set_tag_disabled(providers, state, tag, false),
+                Some((tag, "disable")) => set_tag_disabled(providers, state, tag, true),
+                _ => json_error(404, "Not Found"),
+            }
+        }
+        (method, path) if path.starts_with("/_admin/maintenance/") => {
+            if method != Method::POST {
+                return json_error(405, "Method Not Allowed");
+            }
+            if let Some(response) = check_admin_token(req, state) {
+                return response;
+            }
+            match path["/_admin/maintenance/".len()..].rsplit_once('/') {
+                Some((name, "start")) => {
+                    let duration = query_param(req.uri(), "minutes")
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .filter(|&m| m > 0)
+                        .map(|m| std::time::Duration::from_secs(m * 60))
+                        .unwrap_or(state.maintenance_duration);
+                    set_maintenance(providers, state, name, Some(duration), logger)
+                }
+                Some((name, "end")) => set_maintenance(providers, state, name, None, logger),
+                _ => json_error(404, "Not Found"),
+            }
+        }
+        _ => json_error(404, "Not Found"),
+    }
+}
+
+/// 将提供商置于维护状态 `duration`，为 None 时提前结束维护
+fn set_maintenance(providers: &[Provider], state: &ProxyState, name: &str, duration: Option<std::time::Duration>, logger: &Option<Arc<Logger>>) -> Response<Body> {
+    let Some(provider) = providers.iter().find(|p| p.name == name) else {
+        return json_error(404, &format!("Provider '{}' not configured", name));
+    };
+    let manager = &state.interactive_manager;
+    if let Some(duration) = duration {
+        manager.start_maintenance(provider.state_key(), duration);
+        log_event(state, logger, LogLevel::Info, format!("🔧 服务商 {} 进入维护，{} 分钟后自动恢复", provider.name, duration.as_secs() / 60));
+    } else if manager.end_maintenance(provider.state_key()) {
+        log_event(state, logger, LogLevel::Info, format!("🔧 服务商 {} 已结束维护", provider.name));
+    }
+    json_response(200, &serde_json::json!({
+        "provider": provider.name,
+        "maintenance_remaining_secs": manager.maintenance_remaining(provider.state_key()).map(|d| d.as_secs()),
+    }))
+}
+
+/// 重新读取配置文件并替换提供商列表，返回新增、删除和变化的提供商
+async fn reload_response(req: &Request<Body>, state: &ProxyState, logger: &Option<Arc<Logger>>) -> Response<Body> {
+    if let Some(response) = check_admin_token(req, state) {
+        return response;
+    }
+    let Some(store) = state.provider_store.as_ref() else {
+        return json_error(503, "Reload unavailable");
+    };
+    match store.reload(state).await {
+        Ok(diff) => {
+            let reload_msg = if diff.is_empty() {
+                "🔄 配置已重载，提供商没有变化".to_string()
+            } else {
+                format!("🔄 配置已重载: 新增 {:?}，删除 {:?}，变化 {:?}", diff.added, diff.removed, diff.changed)
+            };
+            log_event(state, logger, LogLevel::Info, reload_msg);
+            if !diff.reset.is_empty() {
+                log_event(state, logger, LogLevel::Info, format!("♻️  Token或地址已更新，已重置熔断和冷却状态: {}", diff.reset.join(", ")));
+            }
+            json_response(200, &diff)
+        }
+        Err(e) => {
+            log_event(state, logger, LogLevel::Warning, format!("🔄 配置重载失败，继续使用原配置: {}", e));
+            json_error(400, &e)
+        }
+    }
+}
+
+/// 校验管理令牌（`Authorization: Bearer <令牌>`），未配置令牌或令牌不匹配时返回错误响应
+fn check_admin_token(req: &Request<Body>, state: &ProxyState) -> Option<Response<Body>> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Some(json_error(403, "Admin token not configured (start with --admin-token)"));
+    };
+    let provided = req.headers().get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    if provided != Some(expected) {
+        return Some(json_error(401, "Unauthorized"));
+    }
+    None
+}
+
+/// 列出所有标签及其包含的提供商
+fn tags_response(providers: &[Provider]) -> Response<Body> {
+    let mut tags: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+    for provider in providers {
+        for tag in &provider.tags {
+            tags.entry(tag.as_str()).or_default().push(provider.name.as_str());
+        }
+    }
+    json_response(200, &tags)
+}
+
+/// 批量启用或禁用带有指定标签的提供商，并保存禁用状态
+fn set_tag_disabled(providers: &[Provider], state: &ProxyState, tag: &str, disabled: bool) -> Response<Body> {
+    let affected = state.interactive_manager.set_tag_disabled(providers, tag, disabled);
+    if affected.is_empty() {
+        return json_error(404, &format!("No providers tagged '{}'", tag));
+    }
+    let persist_error = state.interactive_manager.save_disabled_state().err();
+    json_response(200, &serde_json::json!({
+        "tag": tag,
+        "disabled": disabled,
+        "providers": affected,
+        "persist_error": persist_error,
+    }))
+}
+
+/// 提供商池的汇总统计、每个提供商的耗时分位数，以及最近一小时每分钟的请求总数和失败数（从最早到当前排列）
+fn metrics_response(providers: &[Provider], state: &ProxyState) -> Response<Body> {
+    let buckets = state.request_histogram.snapshot();
+    let latency: serde_json::Map<String, serde_json::Value> = providers.iter()
+        .map(|p| (p.name.clone(), serde_json::json!(state.get_latency_percentiles(p.state_key()))))
+        .collect();
+    json_response(200, &serde_json::json!({
+        "bucket_seconds": HISTOGRAM_BUCKET_DURATION.as_secs(),
+        "total": buckets.iter().map(|b| b.total).sum::<u64>(),
+        "failures": buckets.iter().map(|b| b.failures).sum::<u64>(),
+        "buckets": buckets,
+        "pool": state.pool_stats(providers),
+        "latency": latency,
+        "coalesced_requests": state.coalescer.as_ref().map_or(0, |c| c.coalesced_count()),
+        "cache": state.response_cache.as_ref().map(|cache| cache.stats()),
+    }))
+}
+
+/// 运行状态：启动时间、运行时长、监听端口和网络状态（不包含任何token信息）
+fn status_response(providers: &[Provider], state: &ProxyState) -> Response<Body> {
+    let Some(server_info) = state.server_info.as_ref() else {
+        return json_error(503, "Server info unavailable");
+    };
+    let network = server_info.get_network_status();
+    let status = serde_json::json!({
+        "start_time": server_info.start_time.to_rfc3339(),
+        "uptime_seconds": server_info.uptime().num_seconds(),
+        "port": server_info.port,
+        "rate_limit": server_info.rate_limit,
+        "provider_count": providers.len(),
+        "network": {
+            "is_online": network.is_online,
+            "latency_ms": network.latency_ms,
+            "dns_working": network.dns_working,
+            "status": network.status_text(),
+            "error": network.error_message,
+        },
+    });
+    json_response(200, &status)
+}
+
+/// 浏览器查看的仪表盘：把当前快照嵌入页面，由内联脚本渲染提供商表格，页面每5秒自动刷新
+fn dashboard_response(providers: &[Provider], state: &ProxyState) -> Response<Body> {
+    let snapshot = serde_json::json!({
+        "generated_at": Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        "rate_limit": state.rate_limit,
+        "pool": state.pool_stats(providers),
+        "providers": state.provider_snapshots(providers),
+    });
+    // 嵌入 <script> 中的JSON不能出现 `</`，否则提供商名称可以提前结束脚本标签
+    let snapshot = snapshot.to_string().replace("</", "<\\/");
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .body(Body::from(DASHBOARD_TEMPLATE.replace("__SNAPSHOT__", &snapshot)))
+        .unwrap_or_else(|_| Response::new(Body::from("Internal Error")))
+}
+
+/// 从查询字符串中读取指定参数
+fn query_param(uri: &hyper::Uri, key: &str) -> Option<String> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.to_string())
+}
+
+/// 构建JSON响应
+fn json_response<T: serde::Serialize>(status: u16, value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| Response::new(Body::from("Internal Error"))),
+        Err(e) => json_error(500, &format!("序列化失败: {}", e)),
+    }
+}
+
+/// 构建JSON错误响应
+fn json_error(status: u16, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "error": message }).to_string();
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap_or_else(|_| Response::new(Body::from("Internal Error")))
+}
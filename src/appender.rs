@@ -0,0 +1,52 @@
+//! 缓冲追加文件 - 访问日志和 Token 使用记录共用的写入方式
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// 后台把缓冲的记录写入文件的间隔
+pub const APPEND_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 内存缓冲区大小，按每行约 200 字节计可容纳数百条记录
+const APPEND_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// 以追加方式写入的缓冲文件，多个请求并发写入时按行加锁
+///
+/// 记录先写入内存缓冲区，由服务器的后台任务按 `APPEND_FLUSH_INTERVAL` 定期写入文件，关闭服务器时写入剩余的记录。
+/// 两次写入之间的记录超过缓冲区大小时，由正在写入的请求在持有锁期间写入文件。
+#[derive(Debug)]
+pub struct BufferedAppender {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl BufferedAppender {
+    /// 以追加方式打开文件，不存在时创建；新建文件（或文件为空）且给出 `header` 时先写入该行
+    pub fn open(path: &Path, header: Option<&str>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::with_capacity(APPEND_BUFFER_CAPACITY, file);
+        if let Some(header) = header {
+            if writer.get_ref().metadata()?.len() == 0 {
+                writeln!(writer, "{}", header)?;
+                writer.flush()?;
+            }
+        }
+        Ok(Self { writer: Mutex::new(writer) })
+    }
+
+    /// 追加一行（不含换行符），写入失败时丢弃，记录文件不应影响请求处理
+    pub fn append_line(&self, line: &str) {
+        let mut writer = self.lock();
+        let _ = writer.write_all(line.as_bytes()).and_then(|_| writer.write_all(b"\n"));
+    }
+
+    /// 把缓冲的记录写入文件
+    pub fn flush(&self) -> io::Result<()> {
+        self.lock().flush()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BufWriter<File>> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
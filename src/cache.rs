@@ -0,0 +1,233 @@
+//! 响应缓存 - 在短时间内复用匹配路径的 GET 成功响应，不访问上游也不占用速率配额
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use hyper::header::{HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, TRANSFER_ENCODING, VARY};
+use hyper::{Body, HeaderMap, Method, Response, StatusCode, Uri};
+use hyper::body::Bytes;
+
+/// 标记响应是否来自缓存的响应头
+pub const CACHE_STATUS_HEADER: &str = "X-Cache";
+
+/// 默认最多缓存的响应数量
+pub const DEFAULT_CACHE_CAPACITY: usize = 128;
+
+/// 缓存键：路径和查询参数，以及参与区分的请求头的值
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    path_and_query: String,
+    vary_values: Vec<Option<String>>,
+}
+
+/// 缓存的响应
+struct CacheEntry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    expires_at: Instant,
+    /// 最近一次使用的序号，缓存满时淘汰最久未使用的条目
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    entries: HashMap<CacheKey, CacheEntry>,
+    tick: u64,
+}
+
+/// 命中率统计
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// 按路径前缀和有效期缓存 GET 请求的 2xx 响应（LRU，有容量上限）
+///
+/// 默认只按 `Accept-Encoding` 区分请求。上游响应的 `Vary` 包含未配置的请求头（如 `Authorization`）、
+/// 或带有 `Cache-Control: no-store`/`private` 时不缓存，避免把因身份而不同的内容返回给其他客户端。
+pub struct ResponseCache {
+    rules: Vec<(String, Duration)>,
+    vary_headers: Vec<HeaderName>,
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    /// `rules` 为路径前缀及其缓存有效期，多条规则匹配时使用最长的前缀
+    pub fn new(rules: Vec<(String, Duration)>) -> Self {
+        Self {
+            rules,
+            vary_headers: vec![hyper::header::ACCEPT_ENCODING],
+            capacity: DEFAULT_CACHE_CAPACITY,
+            entries: Mutex::new(CacheEntries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// 除 `Accept-Encoding` 外额外参与区分缓存条目的请求头（如 `Authorization`）
+    ///
+    /// 代理只在客户端接受时才转发压缩的响应，`Accept-Encoding` 始终参与区分。
+    pub fn with_vary_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        for header in headers {
+            if !self.vary_headers.contains(&header) {
+                self.vary_headers.push(header);
+            }
+        }
+        self
+    }
+
+    /// 设置最多缓存的响应数量
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn rules(&self) -> &[(String, Duration)] {
+        &self.rules
+    }
+
+    pub fn vary_headers(&self) -> &[HeaderName] {
+        &self.vary_headers
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lock().entries.len(),
+        }
+    }
+
+    /// 清空缓存的响应
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// 计算请求的缓存键和有效期，不可缓存（非 GET、带请求体或路径不匹配）时返回 None
+    pub fn key(&self, method: &Method, uri: &Uri, headers: &HeaderMap) -> Option<(CacheKey, Duration)> {
+        if method != Method::GET || headers.contains_key(TRANSFER_ENCODING) {
+            return None;
+        }
+        if headers.get(CONTENT_LENGTH).is_some_and(|v| v.as_bytes() != b"0") {
+            return None;
+        }
+        let ttl = self.rules.iter()
+            .filter(|(prefix, _)| uri.path().starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, ttl)| *ttl)?;
+        let vary_values = self.vary_headers.iter()
+            .map(|name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string))
+            .collect();
+        let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/").to_string();
+        Some((CacheKey { path_and_query, vary_values }, ttl))
+    }
+
+    /// 查找未过期的缓存响应，命中时返回带 `X-Cache: HIT` 和 `Age` 的响应
+    pub fn get(&self, key: &CacheKey) -> Option<Response<Body>> {
+        let mut cache = self.lock();
+        let now = Instant::now();
+        cache.tick += 1;
+        let tick = cache.tick;
+        let response = match cache.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > now => {
+                entry.last_used = tick;
+                let mut response = Response::new(Body::from(entry.body.clone()));
+                *response.status_mut() = entry.status;
+                *response.headers_mut() = entry.headers.clone();
+                response.headers_mut().insert(hyper::header::AGE, HeaderValue::from(now.duration_since(entry.stored_at).as_secs()));
+                response.headers_mut().insert(CACHE_STATUS_HEADER, HeaderValue::from_static("HIT"));
+                Some(response)
+            }
+            Some(_) => {
+                cache.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        let counter = if response.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        response
+    }
+
+    /// 缓存未命中时转发得到的响应：可缓存时读取完整响应体并保存，返回带 `X-Cache: MISS` 的响应
+    pub async fn store(&self, key: CacheKey, ttl: Duration, response: Response<Body>) -> Response<Body> {
+        let (mut parts, body) = response.into_parts();
+        parts.headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static("MISS"));
+        if !self.is_cacheable(parts.status, &parts.headers) {
+            return Response::from_parts(parts, body);
+        }
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(_) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from("Failed to read the upstream response body"))
+                    .unwrap_or_else(|_| Response::new(Body::from("Bad Gateway")));
+            }
+        };
+
+        let mut headers = parts.headers.clone();
+        headers.remove(CACHE_STATUS_HEADER);
+        let now = Instant::now();
+        let mut cache = self.lock();
+        cache.tick += 1;
+        let tick = cache.tick;
+        if !cache.entries.contains_key(&key) && cache.entries.len() >= self.capacity {
+            cache.entries.retain(|_, entry| entry.expires_at > now);
+            if cache.entries.len() >= self.capacity {
+                let oldest = cache.entries.iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    cache.entries.remove(&oldest);
+                }
+            }
+        }
+        cache.entries.insert(key, CacheEntry {
+            status: parts.status,
+            headers,
+            body: body.clone(),
+            stored_at: now,
+            expires_at: now + ttl,
+            last_used: tick,
+        });
+        drop(cache);
+        Response::from_parts(parts, Body::from(body))
+    }
+
+    /// 只缓存 2xx 响应，且上游没有禁止缓存、`Vary` 只包含已配置的请求头
+    fn is_cacheable(&self, status: StatusCode, headers: &HeaderMap) -> bool {
+        if !status.is_success() {
+            return false;
+        }
+        let no_store = headers.get_all(CACHE_CONTROL).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_ascii_lowercase())
+            .any(|d| d == "no-store" || d == "private" || d == "no-cache");
+        if no_store {
+            return false;
+        }
+        headers.get_all(VARY).iter()
+            .all(|v| v.to_str().is_ok_and(|v| v.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .all(|name| name != "*" && self.vary_headers.iter().any(|h| h.as_str().eq_ignore_ascii_case(name)))))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheEntries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,175 @@
+//! 请求合并 - 相同的并发幂等请求只向上游发送一次，响应分发给所有等待的客户端
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use hyper::{Body, HeaderMap, Method, Response, StatusCode, Uri};
+use hyper::body::Bytes;
+use tokio::sync::watch;
+use crate::proxy::ServedBy;
+
+/// 合并请求的键：方法、路径和查询参数、客户端接受的压缩编码以及请求体的哈希
+///
+/// 代理只在客户端接受时才转发压缩的响应，`Accept-Encoding` 不同的请求不能共用一个响应。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoalesceKey {
+    method: Method,
+    path_and_query: String,
+    accept_encoding: Option<String>,
+    body_hash: u64,
+}
+
+/// 领头请求完整读取后的响应，分发给等待相同请求的客户端
+struct SharedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    served: Option<ServedBy>,
+}
+
+impl SharedResponse {
+    /// 构建返回给客户端的响应，复用响应的请求没有向上游发送，不计Token
+    fn to_response(&self, coalesced: bool) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        if let Some(served) = &self.served {
+            let tokens = if coalesced { 0 } else { served.tokens };
+            response.extensions_mut().insert(ServedBy { provider: served.provider.clone(), tokens });
+        }
+        response
+    }
+}
+
+type InFlight = HashMap<CoalesceKey, watch::Receiver<Option<Arc<SharedResponse>>>>;
+
+/// 合并相同的并发请求
+///
+/// 只合并 GET 和 HEAD 请求，且路径必须匹配配置的前缀。正确性前提：同一时刻相同的请求
+/// 对所有客户端都应得到相同的响应（如 `/v1/models`）；领头请求的响应会被完整读入内存，
+/// 不适用于流式或大体积响应。
+pub struct RequestCoalescer {
+    paths: Vec<String>,
+    in_flight: Mutex<InFlight>,
+    coalesced: AtomicU64,
+}
+
+impl RequestCoalescer {
+    pub fn new(paths: Vec<String>) -> Self {
+        Self {
+            paths,
+            in_flight: Mutex::new(HashMap::new()),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    /// 参与合并的路径前缀
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// 因合并而没有发往上游的请求数量
+    pub fn coalesced_count(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// 计算请求的合并键，不可合并（非 GET/HEAD 或路径不匹配）时返回 None
+    pub fn key(&self, method: &Method, uri: &Uri, headers: &HeaderMap, body: &[u8]) -> Option<CoalesceKey> {
+        if method != Method::GET && method != Method::HEAD {
+            return None;
+        }
+        if !self.paths.iter().any(|prefix| uri.path().starts_with(prefix.as_str())) {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        Some(CoalesceKey {
+            method: method.clone(),
+            path_and_query: uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/").to_string(),
+            accept_encoding: headers.get(hyper::header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            body_hash: hasher.finish(),
+        })
+    }
+
+    /// 执行请求：已有相同的请求在进行中时等待它的响应，否则由 `forward` 向上游发送并分发响应
+    ///
+    /// 返回的布尔值表示响应是否来自其他请求。领头请求被取消（客户端断开）或读取响应体失败时，
+    /// 等待中的请求各自调用 `forward` 重新发送，不会拿到不完整的响应。
+    pub async fn run<F, Fut>(&self, key: CoalesceKey, forward: F) -> Result<(Response<Body>, bool), Infallible>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Response<Body>, Infallible>>,
+    {
+        // 查找和登记在同一次加锁中完成，同时到达的相同请求只会有一个成为领头请求
+        let leader = {
+            let mut in_flight = self.lock();
+            match in_flight.get(&key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    in_flight.insert(key.clone(), receiver.clone());
+                    Ok((sender, receiver))
+                }
+            }
+        };
+        let (sender, receiver) = match leader {
+            Ok(channel) => channel,
+            Err(mut receiver) => {
+                let shared = receiver.wait_for(Option::is_some).await.ok().and_then(|shared| shared.clone());
+                if let Some(shared) = shared {
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    return Ok((shared.to_response(true), true));
+                }
+                return forward().await.map(|response| (response, false));
+            }
+        };
+        let _guard = InFlightGuard { coalescer: self, key, receiver };
+
+        let (parts, body) = forward().await?.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(_) => {
+                let response = Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from("Failed to read the upstream response body"))
+                    .unwrap_or_else(|_| Response::new(Body::from("Bad Gateway")));
+                return Ok((response, false));
+            }
+        };
+        let shared = Arc::new(SharedResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+            served: parts.extensions.get::<ServedBy>().cloned(),
+        });
+        let _ = sender.send(Some(Arc::clone(&shared)));
+        Ok((shared.to_response(false), false))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, InFlight> {
+        self.in_flight.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 领头请求结束（包括被取消）时移除进行中的记录，之后相同的请求重新发往上游
+struct InFlightGuard<'a> {
+    coalescer: &'a RequestCoalescer,
+    key: CoalesceKey,
+    receiver: watch::Receiver<Option<Arc<SharedResponse>>>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.coalescer.lock();
+        // 只移除自己登记的记录
+        if in_flight.get(&self.key).is_some_and(|receiver| receiver.same_channel(&self.receiver)) {
+            in_flight.remove(&self.key);
+        }
+    }
+}
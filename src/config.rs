@@ -1,7 +1,10 @@
 //! 配置文件读取和管理功能
 
 use std::fs;
+use std::io::{IsTerminal, Read};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use dirs::home_dir;
 use colored::*;
 use crate::provider::Provider;
@@ -18,13 +21,7 @@ pub fn read_providers_config(config_path: Option<PathBuf>) -> Result<(Vec<Provid
     // 确定配置文件路径
     let (config_file, is_custom_path) = match config_path {
         Some(path) => (path, true),
-        None => {
-            // 默认路径为 ~/.claude-proxy-manager/providers.json
-            let mut path = home_dir().unwrap_or_else(|| PathBuf::from("."));
-            path.push(".claude-proxy-manager");
-            path.push("providers.json");
-            (path, false)
-        }
+        None => (default_config_path(), false),
     };
     
     println!("{} {}", "📁 读取配置文件:".cyan(), config_file.display().to_string().bright_white());
@@ -34,6 +31,12 @@ pub fn read_providers_config(config_path: Option<PathBuf>) -> Result<(Vec<Provid
         if is_custom_path {
             // 如果是用户指定的配置文件不存在，则返回错误
             return Err(format!("❌ 指定的配置文件不存在: {}", config_file.display()));
+        } else if !std::io::stdout().is_terminal() {
+            // 非交互环境（systemd、容器等）不创建模板，直接报错，避免编排系统把退出视为正常
+            return Err(format!(
+                "❌ 默认配置文件不存在: {}（非交互环境不会自动创建模板，请先创建该文件，或通过 --config / AUTO_PROXY_CONFIG 指定配置文件）",
+                config_file.display()
+            ));
         } else {
             // 如果是默认配置文件不存在，则创建目录和配置文件
             println!("{}", "⚠️  默认配置文件不存在，正在创建初始配置文件...".yellow());
@@ -46,23 +49,181 @@ pub fn read_providers_config(config_path: Option<PathBuf>) -> Result<(Vec<Provid
         }
     }
     
+    let providers = load_providers_file(&config_file)?;
+    
+    println!("{} {} 个提供商", "✅ 成功加载".green(), providers.len().to_string().bright_white());
+    
+    Ok((providers, config_file))
+}
+
+/// 读取并解析提供商配置文件（不输出任何内容，也不创建模板），用于启动和运行时重载
+///
+/// 会执行 `exec:` token命令，并拒绝空列表和缺少 `{token}` 的 auth_format。
+pub fn load_providers_file(path: &Path) -> Result<Vec<Provider>, String> {
     // 读取文件内容
-    let content = fs::read_to_string(&config_file).map_err(|e| {
-        format!("❌ 无法读取配置文件 {}: {}", config_file.display(), e)
+    let content = fs::read_to_string(path).map_err(|e| {
+        format!("❌ 无法读取配置文件 {}: {}", path.display(), e)
     })?;
     
-    // 解析JSON
-    let providers: Vec<Provider> = serde_json::from_str(&content).map_err(|e| {
-        format!("❌ 配置文件格式错误: {}", e)
+    // 先按通用JSON解析，区分语法错误和结构错误
+    let value: serde_json::Value = serde_json::from_str(&content).map_err(|e| {
+        format!("❌ 配置文件不是有效的JSON（第 {} 行第 {} 列）: {}", e.line(), e.column(), e)
+    })?;
+    let mut providers: Vec<Provider> = serde_json::from_value(value.clone()).map_err(|e| {
+        describe_structure_error(&value, &e)
     })?;
     
     if providers.is_empty() {
         return Err("❌ 配置文件中没有提供商信息".to_string());
     }
     
-    println!("{} {} 个提供商", "✅ 成功加载".green(), providers.len().to_string().bright_white());
+    // exec: 开头的token通过执行外部命令获取（如密钥管理工具）
+    for provider in providers.iter_mut() {
+        if let Some(command) = provider.token.strip_prefix(TOKEN_EXEC_PREFIX) {
+            provider.token = run_token_command(command.trim())
+                .map_err(|e| format!("❌ 提供商 {} 的token命令执行失败: {}", provider.name, e))?;
+        }
+    }
     
-    Ok((providers, config_file))
+    // auth_format 缺少 {token} 时会把token丢掉，所有请求都会认证失败，直接拒绝加载
+    if let Some(provider) = providers.iter().find(|p| !auth_format_is_valid(p)) {
+        return Err(format!("❌ 提供商 {} 的 auth_format 必须包含 {{token}}", provider.name));
+    }
+    
+    Ok(providers)
+}
+
+/// 通过外部命令获取token的前缀，例如 `"exec:op read op://vault/openai/key"`
+pub const TOKEN_EXEC_PREFIX: &str = "exec:";
+
+/// token命令的最长执行时间
+const TOKEN_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 通过系统 shell 执行token命令，返回去掉首尾空白的标准输出
+///
+/// 命令超时、以非零状态退出或输出为空时返回错误；错误信息中不包含命令输出，避免泄露token。
+fn run_token_command(command: &str) -> Result<String, String> {
+    if command.is_empty() {
+        return Err("命令为空".to_string());
+    }
+    
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("无法启动命令: {}", e))?;
+    
+    // 在单独的线程中读取输出，避免输出较多时管道写满导致命令无法退出
+    let mut stdout = child.stdout.take().ok_or_else(|| "无法读取命令输出".to_string())?;
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+    
+    let started_at = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|e| format!("等待命令结束失败: {}", e))? {
+            Some(status) => break status,
+            None if started_at.elapsed() >= TOKEN_COMMAND_TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("命令超时（{} 秒）", TOKEN_COMMAND_TIMEOUT.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    if !status.success() {
+        return Err(format!("命令异常退出（{}）", status));
+    }
+    
+    let output = reader.join()
+        .map_err(|_| "读取命令输出失败".to_string())?
+        .map_err(|e| format!("命令输出不是有效的UTF-8文本: {}", e))?;
+    let token = output.trim();
+    if token.is_empty() {
+        return Err("命令没有输出token".to_string());
+    }
+    Ok(token.to_string())
+}
+
+/// 配置文件结构说明中使用的示例
+const PROVIDER_EXAMPLE: &str = r#"[
+  {
+    "name": "provider_1",
+    "token": "sk-your-token-here",
+    "base_url": "https://api.example.com",
+    "key_type": "AUTH_TOKEN"
+  }
+]"#;
+
+/// JSON 语法正确但结构不符合提供商列表时，生成说明所需字段的错误信息
+fn describe_structure_error(value: &serde_json::Value, err: &serde_json::Error) -> String {
+    let problem = match value {
+        serde_json::Value::Array(items) => {
+            // 定位第一个无法解析的提供商，给出它的序号
+            items.iter().enumerate()
+                .find_map(|(index, item)| {
+                    if !item.is_object() {
+                        return Some(format!("第 {} 个元素不是对象", index + 1));
+                    }
+                    serde_json::from_value::<Provider>(item.clone())
+                        .err()
+                        .map(|e| format!("第 {} 个提供商字段不正确: {}", index + 1, e))
+                })
+                .unwrap_or_else(|| err.to_string())
+        }
+        serde_json::Value::Object(_) => "顶层是对象，应为提供商数组（用 [ ] 包裹）".to_string(),
+        _ => "顶层应为提供商数组".to_string(),
+    };
+    format!(
+        "❌ 配置文件结构错误: {}\n   每个提供商必须包含 name、token、base_url、key_type 字段，例如:\n{}",
+        problem, PROVIDER_EXAMPLE
+    )
+}
+
+/// 指定配置文件路径的环境变量
+pub const CONFIG_PATH_ENV: &str = "AUTO_PROXY_CONFIG";
+
+/// 确定未通过 `--config` 指定时使用的配置文件路径
+///
+/// 优先级：
+/// 1. 环境变量 `AUTO_PROXY_CONFIG`
+/// 2. `$XDG_CONFIG_HOME/auto-proxy/providers.json`（未设置时为 `~/.config/auto-proxy/providers.json`），文件存在时使用
+/// 3. 旧版路径 `~/.claude-proxy-manager/providers.json`，文件存在时使用
+/// 4. 以上文件都不存在时使用第2项的 XDG 路径（在该位置创建模板）
+pub fn default_config_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(CONFIG_PATH_ENV).filter(|v| !v.is_empty()) {
+        return PathBuf::from(path);
+    }
+    
+    let home = home_dir().unwrap_or_else(|| PathBuf::from("."));
+    // XDG 规范要求 XDG_CONFIG_HOME 为绝对路径，否则忽略
+    let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home.join(".config"));
+    let xdg_path = xdg_config_home.join("auto-proxy").join("providers.json");
+    if xdg_path.exists() {
+        return xdg_path;
+    }
+    
+    let legacy_path = home.join(".claude-proxy-manager").join("providers.json");
+    if legacy_path.exists() {
+        return legacy_path;
+    }
+    
+    xdg_path
 }
 
 /// 创建默认配置文件
@@ -104,4 +265,113 @@ fn create_default_config(config_file: &Path) -> Result<(), String> {
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// 校验单个提供商配置，返回发现的问题列表（为空表示通过）
+pub fn validate_provider(provider: &Provider) -> Vec<String> {
+    let mut problems = Vec::new();
+    
+    if provider.name.trim().is_empty() {
+        problems.push("名称为空".to_string());
+    }
+    if provider.id.as_deref().is_some_and(|id| id.trim().is_empty()) {
+        problems.push("id 为空".to_string());
+    }
+    
+    let token = provider.token.trim();
+    if token.is_empty() {
+        problems.push("token 为空".to_string());
+    } else if token == "sk-your_sk" || token == "sk-your-token-here" {
+        problems.push("token 仍是模板占位符".to_string());
+    }
+    
+    match provider.base_url.parse::<hyper::Uri>() {
+        Ok(uri) => {
+            match uri.scheme_str() {
+                Some("http") | Some("https") => {}
+                Some(other) => problems.push(format!("base_url 协议不受支持: {}", other)),
+                None => problems.push("base_url 缺少协议（http:// 或 https://）".to_string()),
+            }
+            if uri.host().is_none() {
+                problems.push("base_url 缺少主机名".to_string());
+            }
+        }
+        Err(e) => problems.push(format!("base_url 无效: {}", e)),
+    }
+    
+    if !auth_format_is_valid(provider) {
+        problems.push("auth_format 缺少 {token} 占位符".to_string());
+    }
+    
+    if let Some(percent) = provider.canary_percent {
+        if percent > 100 {
+            problems.push(format!("canary_percent 超出范围 (0-100): {}", percent));
+        }
+        if provider.shadow {
+            problems.push("shadow 与 canary_percent 不能同时设置".to_string());
+        }
+    }
+    
+    if provider.shadow && provider.is_default {
+        problems.push("shadow 与 is_default 不能同时设置".to_string());
+    }
+    
+    if provider.success_statuses.as_ref().is_some_and(|ranges| ranges.is_empty()) {
+        problems.push("success_statuses 为空，所有响应都会被视为失败".to_string());
+    }
+    
+    if provider.health_check_path.as_ref().is_some_and(|path| !path.starts_with('/')) {
+        problems.push("health_check_path 必须以 / 开头".to_string());
+    }
+    
+    if provider.health_check_statuses.as_ref().is_some_and(|ranges| ranges.is_empty()) {
+        problems.push("health_check_statuses 为空，健康检查总会失败".to_string());
+    }
+    
+    if let Some(price) = provider.cost_per_1k_tokens {
+        if !price.is_finite() || price < 0.0 {
+            problems.push(format!("cost_per_1k_tokens 必须为非负数: {}", price));
+        }
+    }
+    
+    problems
+}
+
+/// auth_format 未设置或包含 `{token}` 占位符
+fn auth_format_is_valid(provider: &Provider) -> bool {
+    match &provider.auth_format {
+        Some(format) => format.contains("{token}"),
+        None => true,
+    }
+}
+
+/// 校验全部提供商配置，有任何问题（包括名称或 ID 重复）时返回汇总的错误信息，用于启动和运行时重载
+pub fn check_providers(providers: &[Provider]) -> Result<(), String> {
+    let problems: Vec<String> = providers.iter()
+        .zip(validate_providers(providers))
+        .filter(|(_, problems)| !problems.is_empty())
+        .map(|(provider, problems)| format!("{}: {}", provider.name, problems.join("，")))
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("❌ 配置检查未通过: {}", problems.join("；")))
+    }
+}
+
+/// 校验全部提供商配置，返回每个提供商的问题列表（与输入顺序一致）
+pub fn validate_providers(providers: &[Provider]) -> Vec<Vec<String>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut seen_keys = std::collections::HashSet::new();
+    providers.iter()
+        .map(|provider| {
+            let mut problems = validate_provider(provider);
+            if !seen.insert(provider.name.as_str()) {
+                problems.push("名称与其他提供商重复".to_string());
+            } else if !seen_keys.insert(provider.state_key()) {
+                problems.push("id 与其他提供商的 id 或名称重复".to_string());
+            }
+            problems
+        })
+        .collect()
+}
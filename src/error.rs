@@ -0,0 +1,141 @@
+//! 代理转发错误类型
+
+use std::error::Error as StdError;
+use std::fmt;
+
+/// 转发请求到单个提供商时可能出现的错误
+#[derive(Debug)]
+pub enum ProxyError {
+    /// 提供商已达到速率限制，请求未发送到上游
+    RateLimited,
+    /// 请求上游超时
+    Timeout,
+    /// 客户端指定的请求总时限已用完，放弃等待上游
+    DeadlineExceeded,
+    /// 在 `--connect-timeout-ms` 内未能建立到上游的连接
+    ConnectTimeout,
+    /// 无法解析上游主机名
+    Dns(String),
+    /// 无法建立到上游的连接（拒绝连接、连接重置等）
+    Connect(String),
+    /// TLS 握手或证书校验失败
+    Tls(String),
+    /// 上游返回了非成功状态码
+    UpstreamStatus(u16),
+    /// 上游返回成功状态码，但响应体中包含错误字段（状态码, JSON指针）
+    UpstreamBodyError(u16, String),
+    /// 跟随上游重定向失败：出现循环或超过次数上限（最后的状态码, 原因）
+    Redirect(u16, String),
+    /// 读取或发送消息体时连接中断
+    BodyRead(String),
+    /// 无法根据配置构建上游请求（URL 或请求头无效）
+    InvalidRequest(String),
+}
+
+impl ProxyError {
+    /// 是否应计入提供商的健康度失败
+    ///
+    /// 速率限制是代理自身的节流，请求总时限取决于客户端，两者都不代表提供商故障。
+    pub fn counts_as_health_failure(&self) -> bool {
+        !matches!(self, ProxyError::RateLimited | ProxyError::DeadlineExceeded)
+    }
+
+    /// 在状态表中记录的状态码（0 表示网络错误，None 表示不记录）
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            ProxyError::RateLimited | ProxyError::DeadlineExceeded => None,
+            ProxyError::UpstreamStatus(code)
+            | ProxyError::UpstreamBodyError(code, _)
+            | ProxyError::Redirect(code, _) => Some(*code),
+            _ => Some(0),
+        }
+    }
+
+    /// 从 hyper 客户端错误中分类
+    pub fn from_hyper(err: hyper::Error) -> Self {
+        if err.is_connect() && is_io_timeout(&err) {
+            return ProxyError::ConnectTimeout;
+        }
+        if err.is_timeout() {
+            return ProxyError::Timeout;
+        }
+        if is_tls_error(&err) {
+            return ProxyError::Tls(err.to_string());
+        }
+        if err.is_incomplete_message() || err.is_body_write_aborted() {
+            return ProxyError::BodyRead(err.to_string());
+        }
+        if err.is_connect() && is_dns_error(&err) {
+            return ProxyError::Dns(err.to_string());
+        }
+        ProxyError::Connect(err.to_string())
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::RateLimited => write!(f, "速率限制"),
+            ProxyError::Timeout => write!(f, "网络错误: 请求超时"),
+            ProxyError::DeadlineExceeded => write!(f, "请求总时限已用完"),
+            ProxyError::ConnectTimeout => write!(f, "网络错误: 连接超时"),
+            ProxyError::Dns(msg) => write!(f, "域名解析失败: {}", msg),
+            ProxyError::Connect(msg) => write!(f, "网络错误: {}", msg),
+            ProxyError::Tls(msg) => write!(f, "TLS错误: {}", msg),
+            ProxyError::UpstreamStatus(code) => match http::StatusCode::from_u16(*code) {
+                Ok(status) => write!(f, "{}", status),
+                Err(_) => write!(f, "{}", code),
+            },
+            ProxyError::UpstreamBodyError(code, pointer) => write!(f, "{} 响应体包含错误字段 {}", code, pointer),
+            ProxyError::Redirect(code, reason) => write!(f, "{} {}", code, reason),
+            ProxyError::BodyRead(msg) => write!(f, "消息体传输中断: {}", msg),
+            ProxyError::InvalidRequest(msg) => write!(f, "无效请求: {}", msg),
+        }
+    }
+}
+
+impl StdError for ProxyError {}
+
+/// 检查错误链中是否包含 rustls 错误
+fn is_tls_error(err: &(dyn StdError + 'static)) -> bool {
+    let mut current: Option<&(dyn StdError + 'static)> = Some(err);
+    while let Some(e) = current {
+        if e.is::<rustls::Error>() {
+            return true;
+        }
+        // io::Error 的 source() 会跳过其包装的错误本身，需要单独检查
+        if let Some(io_err) = e.downcast_ref::<std::io::Error>() {
+            if io_err.get_ref().is_some_and(|inner| inner.is::<rustls::Error>()) {
+                return true;
+            }
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// 错误链中是否包含超时的 io 错误（连接器的连接超时以 `TimedOut` 报告）
+fn is_io_timeout(err: &(dyn StdError + 'static)) -> bool {
+    let mut current: Option<&(dyn StdError + 'static)> = Some(err);
+    while let Some(e) = current {
+        if e.downcast_ref::<std::io::Error>().is_some_and(|io_err| io_err.kind() == std::io::ErrorKind::TimedOut) {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// 错误链中是否包含 DNS 解析失败
+///
+/// hyper 的连接错误类型不公开，只能通过其描述（"dns error"）识别。
+fn is_dns_error(err: &(dyn StdError + 'static)) -> bool {
+    let mut current: Option<&(dyn StdError + 'static)> = Some(err);
+    while let Some(e) = current {
+        if e.to_string().starts_with("dns error") {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
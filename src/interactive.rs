@@ -1,5 +1,7 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use crossterm::{
     event::{self, Event, KeyCode, MouseEventKind, MouseButton},
     execute,
@@ -9,14 +11,21 @@ use crossterm::{
 };
 use std::io::{stdout, Write};
 use crate::provider::Provider;
-use crate::proxy::ProxyState;
-use crate::token::calculate_display_width;
+use crate::proxy::{ProxyState, NAME_COLUMN_WIDTH};
+use crate::token::fit_display_width;
+use crate::persistence::{self, PersistedState};
 use colored::*;
 
 /// 交互式服务商管理界面
 pub struct InteractiveProviderManager {
     pub disabled_providers: Arc<Mutex<HashMap<String, bool>>>,
+    /// 维护中的提供商及维护结束时间（到期自动恢复，不写入状态文件）
+    pub maintenance: Arc<Mutex<HashMap<String, Instant>>>,
     pub provider_rows: Arc<Mutex<Vec<ProviderRow>>>,
+    /// 禁用状态的持久化文件路径（未设置时仅保存在内存中）
+    pub state_path: Mutex<Option<PathBuf>>,
+    /// 配置中 `enabled: false` 的提供商（每次启动都按配置禁用，不写入状态文件）
+    config_disabled: Mutex<HashSet<String>>,
 }
 
 #[derive(Clone)]
@@ -32,39 +41,161 @@ impl InteractiveProviderManager {
     pub fn new() -> Self {
         Self {
             disabled_providers: Arc::new(Mutex::new(HashMap::new())),
+            maintenance: Arc::new(Mutex::new(HashMap::new())),
             provider_rows: Arc::new(Mutex::new(Vec::new())),
+            state_path: Mutex::new(None),
+            config_disabled: Mutex::new(HashSet::new()),
         }
     }
 
-    /// 检查服务商是否被禁用
-    pub fn is_provider_disabled(&self, provider_name: &str) -> bool {
-        // 使用 try_lock 避免死锁
-        match self.disabled_providers.try_lock() {
-            Ok(disabled) => {
-                disabled.get(provider_name).unwrap_or(&false).clone()
-            },
-            Err(_) => {
-                // 如果无法获取锁，默认为未禁用
-                false
+    /// 按配置中的 `enabled: false` 设置初始禁用状态，返回被禁用的提供商名称
+    pub fn seed_from_config(&self, providers: &[Provider]) -> Vec<String> {
+        let disabled: Vec<&Provider> = providers.iter()
+            .filter(|p| !p.is_enabled())
+            .collect();
+        for provider in &disabled {
+            self.set_provider_disabled(provider.state_key(), true);
+        }
+        if let Ok(mut config_disabled) = self.config_disabled.lock() {
+            config_disabled.extend(disabled.iter().map(|p| p.state_key().to_string()));
+        }
+        disabled.iter().map(|p| p.name.clone()).collect()
+    }
+
+    /// 重载配置后按新配置的 `enabled` 设置提供商的启用状态
+    ///
+    /// 新配置中禁用的提供商被禁用；之前因配置禁用、现在配置中启用的提供商恢复启用。
+    pub fn reseed_from_config(&self, providers: &[Provider]) {
+        let Ok(mut config_disabled) = self.config_disabled.lock() else {
+            return;
+        };
+        for provider in providers {
+            if !provider.is_enabled() {
+                config_disabled.insert(provider.state_key().to_string());
+                self.set_provider_disabled(provider.state_key(), true);
+            } else if config_disabled.remove(provider.state_key()) {
+                self.set_provider_disabled(provider.state_key(), false);
             }
         }
     }
 
-    /// 切换服务商启用/禁用状态
-    pub fn toggle_provider(&self, provider_name: &str) -> bool {
-        // 使用 try_lock 避免死锁，如果无法获取锁则返回当前状态
-        match self.disabled_providers.try_lock() {
-            Ok(mut disabled) => {
-                let current_state = disabled.get(provider_name).unwrap_or(&false).clone();
-                let new_state = !current_state;
-                disabled.insert(provider_name.to_string(), new_state);
-                new_state
-            },
-            Err(_) => {
-                // 如果无法获取锁，返回当前状态（通过另一个读取方法）
-                self.is_provider_disabled(provider_name)
+    /// 启用禁用状态持久化：从状态文件恢复禁用列表，并清理配置中已不存在的提供商
+    ///
+    /// 返回恢复后被禁用的提供商名称。
+    pub fn enable_persistence(&self, path: PathBuf, providers: &[Provider]) -> Result<Vec<String>, String> {
+        let persisted = persistence::load_state(&path)?;
+        let restored = persistence::prune_disabled(&persisted.disabled_providers, providers);
+        
+        if let Ok(mut disabled) = self.disabled_providers.lock() {
+            for name in &restored {
+                disabled.insert(name.clone(), true);
             }
         }
+        if let Ok(mut state_path) = self.state_path.lock() {
+            *state_path = Some(path.clone());
+        }
+        
+        // 有残留记录被清理时立即回写
+        if restored.len() != persisted.disabled_providers.len() {
+            self.save_disabled_state()?;
+        }
+        Ok(restored)
+    }
+
+    /// 将当前禁用列表写入状态文件
+    ///
+    /// 配置中 `enabled: false` 的提供商由配置决定，不写入状态文件，
+    /// 这样修改配置为启用后不会被旧的状态文件继续禁用。
+    pub fn save_disabled_state(&self) -> Result<(), String> {
+        let path = match self.state_path.lock() {
+            Ok(state_path) => state_path.clone(),
+            Err(_) => None,
+        };
+        let Some(path) = path else {
+            return Ok(());
+        };
+        
+        let config_disabled = match self.config_disabled.lock() {
+            Ok(config_disabled) => config_disabled.clone(),
+            Err(_) => HashSet::new(),
+        };
+        let mut disabled_providers: Vec<String> = match self.disabled_providers.lock() {
+            Ok(disabled) => disabled.iter()
+                .filter(|(name, is_disabled)| **is_disabled && !config_disabled.contains(*name))
+                .map(|(name, _)| name.clone())
+                .collect(),
+            Err(_) => return Err("禁用状态锁已损坏".to_string()),
+        };
+        disabled_providers.sort();
+        persistence::save_state(&path, &PersistedState { disabled_providers })
+    }
+
+    /// 检查服务商是否被禁用
+    ///
+    /// 使用阻塞锁：选择提供商时必须看到最新的禁用状态，
+    /// 不能因为界面正在切换而把刚禁用的提供商当作可用。
+    pub fn is_provider_disabled(&self, provider_name: &str) -> bool {
+        let disabled = self.disabled_providers.lock().unwrap_or_else(PoisonError::into_inner);
+        *disabled.get(provider_name).unwrap_or(&false)
+    }
+
+    /// 切换服务商启用/禁用状态，返回切换后的禁用状态
+    pub fn toggle_provider(&self, provider_name: &str) -> bool {
+        let mut disabled = self.disabled_providers.lock().unwrap_or_else(PoisonError::into_inner);
+        let new_state = !*disabled.get(provider_name).unwrap_or(&false);
+        disabled.insert(provider_name.to_string(), new_state);
+        new_state
+    }
+
+    /// 设置服务商的启用/禁用状态
+    pub fn set_provider_disabled(&self, provider_name: &str, disabled: bool) {
+        self.disabled_providers.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(provider_name.to_string(), disabled);
+    }
+
+    /// 将服务商置于维护状态，维护期间不参与选择，`duration` 后自动恢复
+    pub fn start_maintenance(&self, provider_name: &str, duration: Duration) {
+        if let Ok(mut maintenance) = self.maintenance.lock() {
+            maintenance.insert(provider_name.to_string(), Instant::now() + duration);
+        }
+    }
+
+    /// 提前结束服务商的维护状态，返回之前是否处于维护中
+    pub fn end_maintenance(&self, provider_name: &str) -> bool {
+        match self.maintenance.lock() {
+            Ok(mut maintenance) => maintenance.remove(provider_name)
+                .is_some_and(|until| until > Instant::now()),
+            Err(_) => false,
+        }
+    }
+
+    /// 服务商剩余的维护时间，不在维护中时返回 None（已到期的记录在这里清除）
+    pub fn maintenance_remaining(&self, provider_name: &str) -> Option<Duration> {
+        // 与禁用状态一样使用阻塞锁，避免锁竞争时把维护中的提供商当作可用
+        let mut maintenance = self.maintenance.lock().unwrap_or_else(PoisonError::into_inner);
+        let until = *maintenance.get(provider_name)?;
+        let remaining = until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero());
+        if remaining.is_none() {
+            maintenance.remove(provider_name);
+        }
+        remaining
+    }
+
+    /// 检查服务商是否处于维护中
+    pub fn is_provider_in_maintenance(&self, provider_name: &str) -> bool {
+        self.maintenance_remaining(provider_name).is_some()
+    }
+
+    /// 批量设置带有指定标签的服务商的启用/禁用状态，返回受影响的服务商名称
+    pub fn set_tag_disabled(&self, providers: &[Provider], tag: &str, disabled: bool) -> Vec<String> {
+        let tagged: Vec<&Provider> = providers.iter()
+            .filter(|p| p.has_tag(tag))
+            .collect();
+        for provider in &tagged {
+            self.set_provider_disabled(provider.state_key(), disabled);
+        }
+        tagged.iter().map(|p| p.name.clone()).collect()
     }
 
     /// 显示交互式服务商状态列表
@@ -75,7 +206,7 @@ impl InteractiveProviderManager {
         // 创建一个本地的 provider_rows 变量
         let mut local_provider_rows = Vec::new();
         
-        let mut current_y = 3;
+        let first_row_y: u16 = 3;
 
         // 显示标题
         execute!(stdout(), MoveTo(0, 0))?;
@@ -83,10 +214,10 @@ impl InteractiveProviderManager {
         println!("{}", "═".repeat(80).bright_black());
 
         // 表头
-        println!("{} {} {:<15} {:<4} {:<4} {:<8} {:<6} {:<6}", 
+        println!("{} {} {} {:<4} {:<4} {:<8} {:<6} {:<6}", 
             "状态".bright_white().bold(),
             "序号".bright_white().bold(),
-            "名称".bright_white().bold(),
+            fit_display_width("名称", NAME_COLUMN_WIDTH).bright_white().bold(),
             "健康".bright_white().bold(),
             "健康度".bright_white().bold(),
             "速率限制".bright_white().bold(),
@@ -97,11 +228,12 @@ impl InteractiveProviderManager {
 
         // 显示每个服务商
         for (index, provider) in providers.iter().enumerate() {
-            let health_score = state.get_provider_health_score(&provider.name);
-            let is_healthy = state.is_provider_healthy(&provider.name);
-            let current_requests = state.get_current_requests(&provider.name);
-            let can_request = state.can_request(&provider.name);
-            let is_disabled = self.is_provider_disabled(&provider.name);
+            let current_y = first_row_y + index as u16;
+            let health_score = state.get_provider_health_score(provider.state_key());
+            let is_healthy = state.is_provider_healthy(provider.state_key());
+            let current_requests = state.get_current_requests(provider.state_key());
+            let can_request = state.can_request(provider.state_key());
+            let is_disabled = self.is_provider_disabled(provider.state_key());
 
             let (status_icon, health_color) = match health_score {
                 90..=100 => ("🟢", "bright_green"),
@@ -111,8 +243,7 @@ impl InteractiveProviderManager {
                 _ => ("💀", "red"),
             };
 
-            let name_display_width = calculate_display_width(&provider.name);
-            let name_padding = if name_display_width < 15 { 15 - name_display_width } else { 1 };
+            let name_display = fit_display_width(&provider.name, NAME_COLUMN_WIDTH);
             
             let health_text = if health_score > 20 { "健康" } else { "异常" };
             let status_text = if is_healthy { "可用" } else { "不可用" };
@@ -131,11 +262,10 @@ impl InteractiveProviderManager {
 
             if is_disabled {
                 // 禁用的服务商显示为灰色
-                print!("{} {:<2} {}{} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
+                print!("{} {:<2} {} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
                     status_icon.bright_black(),
                     index + 1,
-                    provider.name.bright_black(),
-                    " ".repeat(name_padding),
+                    name_display.bright_black(),
                     health_text.bright_black(),
                     health_score.to_string().bright_black(),
                     current_requests.to_string().bright_black(),
@@ -145,11 +275,10 @@ impl InteractiveProviderManager {
                     toggle_button
                 );
             } else {
-                print!("{} {:<2} {}{} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
+                print!("{} {:<2} {} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
                     status_icon,
                     index + 1,
-                    provider.name.bright_cyan(),
-                    " ".repeat(name_padding),
+                    name_display.bright_cyan(),
                     if health_score > 20 { health_text.bright_green() } else { health_text.bright_red() },
                     health_score.to_string().color(health_color).bold(),
                     current_requests.to_string().bright_cyan(),
@@ -169,8 +298,6 @@ impl InteractiveProviderManager {
                 toggle_button_x,
                 toggle_button_width: 8,
             });
-
-            current_y += 1;
         }
         
         // 将本地的 provider_rows 保存到 self.provider_rows 中
@@ -193,10 +320,8 @@ impl InteractiveProviderManager {
             if event::poll(std::time::Duration::from_millis(50))? {
                 if let Ok(event) = event::read() {
                     match event {
-                        Event::Key(key) => {
-                            if key.code == KeyCode::Esc {
-                                break;
-                            }
+                        Event::Key(key) if key.code == KeyCode::Esc => {
+                            break;
                         }
                         Event::Mouse(mouse) => {
                             if let MouseEventKind::Down(MouseButton::Left) = mouse.kind {
@@ -213,7 +338,10 @@ impl InteractiveProviderManager {
                                            mouse.column < row.toggle_button_x + row.toggle_button_width {
                                                 
                                                 // 切换服务商状态
-                                                let new_disabled_state = self.toggle_provider(&row.provider_name);
+                                                let new_disabled_state = self.toggle_provider(providers[row.index].state_key());
+                                                if let Err(e) = self.save_disabled_state() {
+                                                    eprintln!("Error saving disabled state: {}", e);
+                                                }
                                                 
                                                 // 重新渲染这一行
                                                 if let Err(e) = self.refresh_provider_row(&providers[row.index], row, state, new_disabled_state) {
@@ -249,10 +377,10 @@ impl InteractiveProviderManager {
     /// 刷新单个服务商行的显示
     fn refresh_provider_row(&self, provider: &Provider, row: &ProviderRow, state: &ProxyState, is_disabled: bool) -> std::io::Result<()> {
         // 使用 try_lock 获取状态信息，避免死锁
-        let health_score = state.get_provider_health_score(&provider.name);
-        let is_healthy = state.is_provider_healthy(&provider.name);
-        let current_requests = state.get_current_requests(&provider.name);
-        let can_request = state.can_request(&provider.name);
+        let health_score = state.get_provider_health_score(provider.state_key());
+        let is_healthy = state.is_provider_healthy(provider.state_key());
+        let current_requests = state.get_current_requests(provider.state_key());
+        let can_request = state.can_request(provider.state_key());
 
         let (status_icon, health_color) = match health_score {
             90..=100 => ("🟢", "bright_green"),
@@ -262,8 +390,7 @@ impl InteractiveProviderManager {
             _ => ("💀", "red"),
         };
 
-        let name_display_width = calculate_display_width(&provider.name);
-        let name_padding = if name_display_width < 15 { 15 - name_display_width } else { 1 };
+        let name_display = fit_display_width(&provider.name, NAME_COLUMN_WIDTH);
         
         let health_text = if health_score > 20 { "健康" } else { "异常" };
         let status_text = if is_healthy { "可用" } else { "不可用" };
@@ -282,11 +409,10 @@ impl InteractiveProviderManager {
         // 使用 execute! 而不是 print!，以便更好地处理错误
         if is_disabled {
             execute!(stdout(), 
-                Print(format!("{} {:<2} {}{} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
+                Print(format!("{} {:<2} {} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
                     status_icon.bright_black(),
                     row.index + 1,
-                    provider.name.bright_black(),
-                    " ".repeat(name_padding),
+                    name_display.bright_black(),
                     health_text.bright_black(),
                     health_score.to_string().bright_black(),
                     current_requests.to_string().bright_black(),
@@ -298,11 +424,10 @@ impl InteractiveProviderManager {
             )?;
         } else {
             execute!(stdout(), 
-                Print(format!("{} {:<2} {}{} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
+                Print(format!("{} {:<2} {} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {:<6} │ {}", 
                     status_icon,
                     row.index + 1,
-                    provider.name.bright_cyan(),
-                    " ".repeat(name_padding),
+                    name_display.bright_cyan(),
                     if health_score > 20 { health_text.bright_green() } else { health_text.bright_red() },
                     health_score.to_string().color(health_color).bold(),
                     current_requests.to_string().bright_cyan(),
@@ -324,7 +449,7 @@ impl InteractiveProviderManager {
     }
     
     /// 刷新所有服务商的显示
-    pub fn refresh_providers(&self, providers: &Vec<Provider>, state: &ProxyState) -> std::io::Result<()> {
+    pub fn refresh_providers(&self, providers: &[Provider], state: &ProxyState) -> std::io::Result<()> {
         // 创建本地变量
         let mut local_rows = Vec::new();
         let mut old_positions = Vec::new();
@@ -345,10 +470,11 @@ impl InteractiveProviderManager {
         }
         
         // 重新计算行位置
-        let mut y_position = 3; // 从第3行开始显示服务商
+        let first_row_y: u16 = 3; // 从第3行开始显示服务商
         let toggle_button_x = 65; // 按钮的X位置
         
         for (index, provider) in providers.iter().enumerate() {
+            let y_position = first_row_y + index as u16;
             let row = ProviderRow {
                 index,
                 provider_name: provider.name.clone(),
@@ -357,8 +483,7 @@ impl InteractiveProviderManager {
                 toggle_button_width: 8,
             };
             
-            // 使用 try_lock 检查禁用状态
-            let is_disabled = self.is_provider_disabled(&provider.name);
+            let is_disabled = self.is_provider_disabled(provider.state_key());
             
             // 刷新单个服务商行，添加错误处理
             if let Err(e) = self.refresh_provider_row(provider, &row, state, is_disabled) {
@@ -367,7 +492,6 @@ impl InteractiveProviderManager {
             }
             
             local_rows.push(row);
-            y_position += 1;
         }
         
         // 更新 provider_rows
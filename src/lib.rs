@@ -9,6 +9,19 @@ pub mod ui;
 pub mod network;
 pub mod token;
 pub mod interactive;
+pub mod admin;
+pub mod tls;
+pub mod error;
+pub mod persistence;
+pub mod server;
+pub mod reload;
+pub mod appender;
+pub mod access_log;
+pub mod coalesce;
+pub mod cache;
+pub mod usage_csv;
+#[cfg(feature = "otlp")]
+pub mod telemetry;
 
 pub use config::*;
 pub use proxy::*;
@@ -16,4 +29,17 @@ pub use provider::*;
 pub use ui::*;
 pub use network::*;
 pub use token::*;
-pub use interactive::*;
\ No newline at end of file
+pub use interactive::*;
+pub use admin::*;
+pub use tls::*;
+pub use error::*;
+pub use persistence::*;
+pub use server::*;
+pub use reload::*;
+pub use appender::*;
+pub use access_log::*;
+pub use coalesce::*;
+pub use cache::*;
+pub use usage_csv::*;
+#[cfg(feature = "otlp")]
+pub use telemetry::*;
@@ -2,28 +2,25 @@
 //! 
 //! 这是一个支持多提供商的智能代理服务器，具有自动重试和故障转移功能。
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::convert::Infallible;
 use std::time::Duration;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::Server;
 use colored::*;
 use tokio::time::interval;
 use tokio::signal;
-use auto_proxy::{read_providers_config, handle_request, ProxyState, TerminalUI, ServerInfo, NetworkStatus};
+use auto_proxy::{ProxyServer, ProxyState, ProviderStore, TerminalUI, LoadBalanceStrategy, HealthConfig, LogThreshold, check_providers, validate_providers, export_status_table, load_server_tls_config, DEFAULT_EXPLORE_PERCENT, DEFAULT_HEALTH_CHECK_JITTER_PERCENT, UiMode, install_terminal_panic_hook, RENDER_INTERVAL, IDLE_RENDER_INTERVAL, EVENT_POLL_INTERVAL, IDLE_EVENT_POLL_INTERVAL, RetryAfter, RetryAfterScenario, TokenThreshold, SelfTestRequest, RateLimitMode, TimestampFormat, AccessLog, AccessLogFormat, UsageCsv, DEFAULT_DEGRADED_THRESHOLD, UnavailableCondition, UnavailableResponses, HeuristicTokenEstimator, TokenEstimationConfig, DEFAULT_MEDIA_TOKENS, MAX_ESTIMATION_BYTES, ResponseCache, DEFAULT_CACHE_CAPACITY};
 
 /// 命令行参数
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// 监听的端口号
     #[arg(short, long, default_value_t = 8080)]
     port: u16,
     
-    /// 配置文件路径
+    /// 配置文件路径（未指定时依次查找 $AUTO_PROXY_CONFIG、$XDG_CONFIG_HOME/auto-proxy/providers.json 和 ~/.claude-proxy-manager/providers.json）
     #[arg(short, long)]
     config: Option<PathBuf>,
     
@@ -34,6 +31,361 @@ struct Args {
     /// 禁用终端UI，使用传统日志输出
     #[arg(long)]
     no_ui: bool,
+
+    /// 终端界面布局：full 显示完整服务商表格，compact 只显示一行汇总和日志（适合窄终端）
+    #[arg(long, value_enum, default_value_t = UiMode::Full)]
+    ui: UiMode,
+
+    /// 无请求和键盘鼠标输入超过该秒数后，界面降为每秒刷新一次以节省CPU（0 表示始终全速刷新）
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    ui_idle_secs: u64,
+
+    /// 启动时在服务商表格中显示地址列（只含协议和主机，运行中可按 H 切换）
+    #[arg(long)]
+    show_hosts: bool,
+
+    /// 健康服务商占比低于该百分比时在状态栏顶部显示红色降级横幅（0 表示不显示）
+    #[arg(long, value_name = "PERCENT", default_value_t = DEFAULT_DEGRADED_THRESHOLD, value_parser = clap::value_parser!(u8).range(0..=100))]
+    degraded_threshold: u8,
+
+    /// 日志时间戳的 strftime 格式，例如 %Y-%m-%dT%H:%M:%S%.3fZ（ISO 8601，配合 --utc 使用）
+    #[arg(long, value_name = "STRFTIME", default_value = "%H:%M:%S", value_parser = parse_timestamp_pattern)]
+    timestamp_format: String,
+
+    /// 日志时间戳使用 UTC 而不是本地时区
+    #[arg(long)]
+    utc: bool,
+
+    /// Token 列显示为黄色的使用量阈值：Token数（如 500k、2m）或占全部使用量的百分比（如 40%）
+    #[arg(long, value_name = "TOKENS|PERCENT%")]
+    token_warning: Option<TokenThreshold>,
+
+    /// Token 列显示为红色的使用量阈值，格式同 --token-warning
+    #[arg(long, value_name = "TOKENS|PERCENT%")]
+    token_critical: Option<TokenThreshold>,
+
+    /// 访问日志文件：每个客户端请求追加一条记录（时间、方法、路径、状态码、字节数、供应商、耗时、Token），与界面日志分开
+    #[arg(long, value_name = "FILE")]
+    access_log: Option<PathBuf>,
+
+    /// 访问日志格式：json 每行一个 JSON 对象，combined 为 Apache combined 格式并在末尾追加供应商、耗时和Token
+    #[arg(long, value_enum, default_value_t = AccessLogFormat::Json, requires = "access_log")]
+    access_log_format: AccessLogFormat,
+
+    /// Token 使用记录文件：每个成功的请求追加一行 CSV（时间、供应商、输入/输出/总Token 估算值），新建文件时写入表头，用于按月核对用量
+    #[arg(long, value_name = "FILE")]
+    usage_csv: Option<PathBuf>,
+
+    /// OpenTelemetry Collector 的 OTLP/HTTP 地址（如 http://localhost:4318）：每个请求导出一个 span，每次上游尝试为其子 span
+    #[cfg(feature = "otlp")]
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// 日志级别：info 输出每个请求的转发日志，warn 只输出警告和错误，error 只输出错误
+    #[arg(long, value_enum, default_value_t = LogThreshold::Info)]
+    log_level: LogThreshold,
+
+    /// 是否与上游协商 HTTP/2（上游不兼容时可设为 false 强制使用 HTTP/1.1）
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    http2: bool,
+
+    /// 负载均衡策略
+    #[arg(long, value_enum, default_value_t = LoadBalanceStrategy::RoundRobin)]
+    strategy: LoadBalanceStrategy,
+
+    /// 速率限制模式：hard 跳过达到限制的供应商，soft 只把它们排在最后（负载高时可能超过每分钟限制）
+    #[arg(long, value_enum, default_value_t = RateLimitMode::Hard)]
+    rate_limit_mode: RateLimitMode,
+
+    /// 轮询抖动：首选供应商不可用时从随机位置继续查找，设为 false 时严格按顺序轮询（便于测试复现）
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    rr_jitter: bool,
+
+    /// fastest 策略随机选择非最快供应商的概率（百分比），用于持续测量其他供应商的耗时
+    #[arg(long, value_name = "PERCENT", default_value_t = DEFAULT_EXPLORE_PERCENT, value_parser = clap::value_parser!(u8).range(0..=100))]
+    explore_percent: u8,
+
+    /// 保留客户端原始 Host 头，而不是改写为目标主机（用于基于 Host 路由的网关）
+    #[arg(long)]
+    preserve_host: bool,
+
+    /// 代理监听端口使用的 TLS 证书链（PEM），与 --tls-key 同时指定时以 HTTPS 提供服务
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// 代理监听端口使用的 TLS 私钥（PEM）
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// 仅校验配置文件并退出（不启动服务器）
+    #[arg(long)]
+    check_config: bool,
+
+    /// 打印合并命令行参数和配置文件后实际生效的配置（JSON，Token已脱敏）并退出（不启动服务器）
+    #[arg(long)]
+    print_config: bool,
+
+    /// 向每个供应商发送一次自检请求，打印状态码、耗时和脱敏Token后退出（不启动服务器）
+    #[arg(long)]
+    self_test: bool,
+
+    /// 自检请求的方法
+    #[arg(long, value_name = "METHOD", default_value = "POST")]
+    self_test_method: hyper::Method,
+
+    /// 自检请求的路径，拼接在供应商的 base_url 之后
+    #[arg(long, value_name = "PATH", default_value = "/v1/messages")]
+    self_test_path: String,
+
+    /// 自检请求的 JSON 请求体
+    #[arg(long, value_name = "JSON", default_value = r#"{"model":"claude-3-5-haiku-latest","max_tokens":1,"messages":[{"role":"user","content":"ping"}]}"#)]
+    self_test_body: String,
+
+    /// 自检时每个供应商的超时秒数
+    #[arg(long, value_name = "SECS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    self_test_timeout_secs: u64,
+
+    /// 会话粘滞：按该请求头的值（如 X-Session-Id）固定选择同一个供应商
+    #[arg(long, value_name = "HEADER")]
+    sticky_header: Option<hyper::header::HeaderName>,
+
+    /// 成功响应的 JSON 中出现该指针指向的字段（如 /error）时视为失败并转移（默认只看状态码）
+    #[arg(long, value_name = "POINTER", value_parser = parse_error_pointer)]
+    error_pointer: Option<String>,
+
+    /// 按路径前缀路由到带有指定标签的供应商，格式为 `路径前缀=标签`，可重复指定
+    #[arg(long = "route", value_name = "PREFIX=TAG", value_parser = parse_tag_route)]
+    routes: Vec<(String, String)>,
+
+    /// 只允许转发匹配的请求，格式为 `方法:路径前缀`（方法可为 * 表示任意方法），可重复指定；未指定时不限制
+    #[arg(long = "allow", value_name = "METHOD:PREFIX", value_parser = parse_allow_rule)]
+    allow_rules: Vec<(Option<hyper::Method>, String)>,
+
+    /// 合并路径匹配该前缀的相同并发 GET/HEAD 请求，只向上游发送一次并把响应分发给所有客户端，可重复指定
+    #[arg(long = "coalesce", value_name = "PREFIX", value_parser = parse_path_prefix)]
+    coalesce_paths: Vec<String>,
+
+    /// 缓存路径匹配该前缀的 GET 成功响应指定秒数，格式为 `路径前缀=秒数`，可重复指定
+    #[arg(long = "cache", value_name = "PREFIX=SECS", value_parser = parse_cache_rule)]
+    cache_rules: Vec<(String, Duration)>,
+
+    /// 除 Accept-Encoding 外额外参与区分缓存条目的请求头（如 Authorization），可重复指定
+    #[arg(long = "cache-vary", value_name = "HEADER")]
+    cache_vary: Vec<hyper::header::HeaderName>,
+
+    /// 最多缓存的响应数量，超过时淘汰最久未使用的
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_CACHE_CAPACITY as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    cache_capacity: u64,
+
+    /// 管理令牌：调用 POST /_admin/reload 等修改配置的管理接口时需要携带 `Authorization: Bearer <令牌>`（未设置时这些接口不可用）
+    #[arg(long, value_name = "TOKEN")]
+    admin_token: Option<String>,
+
+    /// 在独立端口上提供管理接口（/_admin、/_status、/_metrics、/_dashboard），代理端口不再响应 `/_` 开头的路径
+    #[arg(long, value_name = "PORT")]
+    admin_port: Option<u16>,
+
+    /// 管理端口绑定的地址，默认只允许本机访问
+    #[arg(long, value_name = "IP", default_value = "127.0.0.1", requires = "admin_port")]
+    admin_host: std::net::IpAddr,
+
+    /// 503/429 响应的 Retry-After 秒数，格式为 `秒数`（所有场景）或 `场景=秒数`，场景为 unavailable、rate-limited、down、overloaded，可重复指定
+    #[arg(long = "retry-after", value_name = "[SCENARIO=]SECS", value_parser = parse_retry_after)]
+    retry_after: Vec<(Option<RetryAfterScenario>, u64)>,
+
+    /// 无法转发时返回的状态码，格式为 `情况=状态码`，情况为 no-providers（没有配置提供商）、disabled（全部被禁用）、down（全部不可用），可重复指定（默认 503）
+    #[arg(long = "unavailable-status", value_name = "CONDITION=STATUS", value_parser = parse_unavailable_status)]
+    unavailable_status: Vec<(UnavailableCondition, u16)>,
+
+    /// 无法转发时返回的响应体模板，格式为 `情况=模板`，模板中的 {message}、{status}、{condition} 会被替换，以 { 或 [ 开头时作为 JSON 返回，可重复指定（默认返回纯文本说明）
+    #[arg(long = "unavailable-body", value_name = "CONDITION=TEMPLATE", value_parser = parse_unavailable_body)]
+    unavailable_body: Vec<(UnavailableCondition, String)>,
+
+    /// 建立到上游的 TCP 连接的超时毫秒数，超时视为连接失败并转移到下一个供应商（不限制等待响应的时间，默认使用系统超时）
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout_ms: Option<u64>,
+
+    /// 整个请求（包括所有故障转移）的默认总时限毫秒数，超时返回 504；客户端可通过 X-Timeout-Ms 请求头为单个请求指定（默认不限制）
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    request_timeout_ms: Option<u64>,
+
+    /// 域名解析失败后停止选择该供应商的秒数（健康巡检期间会重新解析，成功后提前恢复）
+    #[arg(long, value_name = "SECS", default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    dns_cooldown_secs: u64,
+
+    /// 主动健康检查的间隔秒数：定期向每个未禁用的供应商发送探测请求（路径见配置中的 health_check_path），结果计入健康度（0 表示不主动检查）
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    health_check_secs: u64,
+
+    /// 主动健康检查间隔的抖动比例（百分比）：每个供应商按名称得到固定的偏移，探测错开进行，避免流量尖峰和同时失败（0 表示同时检查）
+    #[arg(long, value_name = "PERCENT", default_value_t = DEFAULT_HEALTH_CHECK_JITTER_PERCENT, value_parser = clap::value_parser!(u8).range(0..=50))]
+    health_check_jitter: u8,
+
+    /// 同时处理的代理请求数上限，超出时短暂等待，仍无空位则直接返回 429（默认不限制）
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_concurrency: Option<u64>,
+
+    /// 单个请求最多尝试的供应商数量，达到后直接返回 503（默认不限制，设为 1 表示失败后不转移）
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_failover: Option<u64>,
+
+    /// 缓冲请求体的上限（字节），超过时改为流式转发：上传不必完整读入内存，但该请求不做故障转移（默认总是完整缓冲）
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    max_buffered_body: Option<u64>,
+
+    /// 参与JSON内容分析的最大消息体字节数，超过时跳过解析只按长度估算Token，避免内嵌大图片的请求拖慢转发
+    #[arg(long, value_name = "BYTES", default_value_t = MAX_ESTIMATION_BYTES as u64)]
+    token_parse_max_bytes: u64,
+
+    /// 估算Token时每个图片等媒体内容块计入的Token数量
+    #[arg(long, value_name = "TOKENS", default_value_t = DEFAULT_MEDIA_TOKENS)]
+    media_tokens: u64,
+
+    /// 在代理中跟随上游重定向的最大次数，出现循环或超过次数视为该供应商失败（0 表示不跟随，重定向按失败处理）
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    follow_redirects: usize,
+
+    /// 上游返回 5xx 时先对同一供应商重试一次再转移（连接失败、超时等网络错误始终立即转移）
+    #[arg(long)]
+    retry_same_on_5xx: bool,
+
+    /// 在成功响应中添加 X-Proxy-Provider（处理请求的供应商名称）和 X-Proxy-Attempts（尝试次数）头，便于客户端排查
+    #[arg(long)]
+    expose_provider_header: bool,
+
+    /// 所有供应商都不健康时直接返回 503，不进入紧急模式（避免向明显故障的池继续消耗额度）
+    #[arg(long)]
+    no_emergency_mode: bool,
+
+    /// 所有供应商健康度都降到 0 时是否自动把它们恢复到最低健康度继续尝试（off 时直接返回 503，依靠空闲恢复或主动健康检查重新变为可用）
+    #[arg(long, value_name = "on|off", default_value = "on", value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    auto_recovery: bool,
+
+    /// 在界面中按 M 键将选中的供应商置于维护状态的分钟数，到期自动恢复（管理接口未指定时长时也使用该值）
+    #[arg(long, value_name = "MINUTES", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    maintenance_minutes: u64,
+
+    /// 所有供应商都达到速率限制时，等待空闲配额的最长毫秒数（0 表示立即返回 503）
+    #[arg(long, default_value_t = 0)]
+    queue_wait_ms: u64,
+
+    /// 空闲恢复周期（秒）：供应商无请求时每经过一个周期恢复一次健康度
+    #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    idle_recovery_secs: u64,
+
+    /// 每个空闲周期恢复的健康度分数
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(0..=100))]
+    idle_recovery_points: u8,
+
+    /// 连续失败后需要连续成功多少次才按失败程度大幅恢复健康度（1 表示首次成功即大幅恢复）
+    #[arg(long, value_name = "K", default_value_t = 3, value_parser = clap::value_parser!(u8).range(1..))]
+    recovery_successes: u8,
+
+    /// 预热期请求数：供应商启动或紧急恢复后的前 N 次请求失败时只扣一半健康度（0 表示不按请求数预热）
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    warmup_requests: u32,
+
+    /// 预热期时长（秒）：供应商启动或紧急恢复后的 T 秒内失败时只扣一半健康度（0 表示不按时长预热）
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    warmup_secs: u64,
+}
+
+/// 解析 `--route` 参数
+fn parse_tag_route(value: &str) -> Result<(String, String), String> {
+    let (prefix, tag) = value.split_once('=')
+        .ok_or_else(|| format!("格式应为 路径前缀=标签: {}", value))?;
+    if !prefix.starts_with('/') {
+        return Err(format!("路径前缀必须以 / 开头: {}", prefix));
+    }
+    if tag.is_empty() {
+        return Err(format!("标签不能为空: {}", value));
+    }
+    Ok((prefix.to_string(), tag.to_string()))
+}
+
+/// 解析 `--cache` 参数
+fn parse_cache_rule(value: &str) -> Result<(String, Duration), String> {
+    let (prefix, secs) = value.split_once('=')
+        .ok_or_else(|| format!("格式应为 路径前缀=秒数: {}", value))?;
+    let prefix = parse_path_prefix(prefix)?;
+    let secs = secs.parse::<u64>().ok()
+        .filter(|&secs| secs > 0)
+        .ok_or_else(|| format!("秒数无效: {}", secs))?;
+    Ok((prefix, Duration::from_secs(secs)))
+}
+
+/// 解析以 / 开头的路径前缀参数
+fn parse_path_prefix(value: &str) -> Result<String, String> {
+    if !value.starts_with('/') {
+        return Err(format!("路径前缀必须以 / 开头: {}", value));
+    }
+    Ok(value.to_string())
+}
+
+/// 解析 `--allow` 参数
+fn parse_allow_rule(value: &str) -> Result<(Option<hyper::Method>, String), String> {
+    let (method, prefix) = value.split_once(':')
+        .ok_or_else(|| format!("格式应为 方法:路径前缀: {}", value))?;
+    if !prefix.starts_with('/') {
+        return Err(format!("路径前缀必须以 / 开头: {}", prefix));
+    }
+    let method = match method {
+        "*" => None,
+        m => Some(m.to_ascii_uppercase().parse::<hyper::Method>()
+            .map_err(|_| format!("无效的请求方法: {}", m))?),
+    };
+    Ok((method, prefix.to_string()))
+}
+
+/// 校验 `--timestamp-format` 参数
+fn parse_timestamp_pattern(value: &str) -> Result<String, String> {
+    TimestampFormat::new(value, false).map(|_| value.to_string())
+}
+
+/// 解析 `--retry-after` 参数
+fn parse_retry_after(value: &str) -> Result<(Option<RetryAfterScenario>, u64), String> {
+    let (scenario, secs) = match value.split_once('=') {
+        Some((name, secs)) => {
+            let scenario = RetryAfterScenario::from_name(name)
+                .ok_or_else(|| format!("未知的场景（应为 unavailable、rate-limited、down 或 overloaded）: {}", name))?;
+            (Some(scenario), secs)
+        }
+        None => (None, value),
+    };
+    let secs = secs.parse::<u64>().map_err(|_| format!("秒数无效: {}", secs))?;
+    Ok((scenario, secs))
+}
+
+/// 解析 `--unavailable-status` / `--unavailable-body` 参数中 `=` 之前的情况名称
+fn parse_unavailable_condition(value: &str) -> Result<(UnavailableCondition, &str), String> {
+    let (name, rest) = value.split_once('=')
+        .ok_or_else(|| format!("格式应为 情况=值: {}", value))?;
+    let condition = UnavailableCondition::from_name(name)
+        .ok_or_else(|| format!("未知的情况（应为 no-providers、disabled 或 down）: {}", name))?;
+    Ok((condition, rest))
+}
+
+/// 解析 `--unavailable-status` 参数（状态码必须在 100-599 之间）
+fn parse_unavailable_status(value: &str) -> Result<(UnavailableCondition, u16), String> {
+    let (condition, status) = parse_unavailable_condition(value)?;
+    let status = status.parse::<u16>().ok()
+        .filter(|status| (100..=599).contains(status))
+        .ok_or_else(|| format!("状态码无效: {}", status))?;
+    Ok((condition, status))
+}
+
+/// 解析 `--unavailable-body` 参数
+fn parse_unavailable_body(value: &str) -> Result<(UnavailableCondition, String), String> {
+    let (condition, template) = parse_unavailable_condition(value)?;
+    Ok((condition, template.to_string()))
+}
+
+/// 解析 `--error-pointer` 参数（JSON 指针必须以 / 开头）
+fn parse_error_pointer(value: &str) -> Result<String, String> {
+    if !value.starts_with('/') {
+        return Err(format!("JSON 指针必须以 / 开头: {}", value));
+    }
+    Ok(value.to_string())
 }
 
 #[tokio::main]
@@ -42,35 +394,310 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     
     // 读取配置文件
-    let (providers, _actual_config_path) = match read_providers_config(args.config) {
-        Ok(result) => result,
+    let server = match ProxyServer::from_config(args.config.clone()) {
+        Ok(server) => server,
         Err(e) => {
             eprintln!("{} {}", "❌ 配置加载失败:".red().bold(), e);
             return Err(e.into());
         }
     };
 
-    let providers = Arc::new(providers);
-    let state = Arc::new(ProxyState::new_with_rate_limit(args.rate_limit));
-    let server_info = Arc::new(ServerInfo::new(args.port, args.rate_limit));
+    if args.check_config {
+        let all_valid = run_config_check(server.providers());
+        std::process::exit(if all_valid { 0 } else { 1 });
+    }
+
+    // 与运行时重载相同的检查：名称或 ID 重复的提供商会共用同一份健康度和速率状态
+    if let Err(e) = check_providers(server.providers()) {
+        eprintln!("{} {}", "❌ 配置加载失败:".red().bold(), e);
+        return Err(e.into());
+    }
+
+    // 加载监听端口的 TLS 证书（clap 已保证证书和私钥同时指定）
+    let tls_config = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => match load_server_tls_config(cert, key) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("{} {}", "❌ TLS证书加载失败:".red().bold(), e);
+                return Err(e.into());
+            }
+        },
+        _ => None,
+    };
+
+    // 只打印配置时不创建访问日志和 Token 使用记录文件
+    let access_log = match &args.access_log {
+        Some(_) if args.print_config => None,
+        Some(path) => match AccessLog::open(path, args.access_log_format) {
+            Ok(access_log) => Some(access_log),
+            Err(e) => {
+                eprintln!("{} {}: {}", "❌ 无法打开访问日志:".red().bold(), path.display(), e);
+                return Err(e.into());
+            }
+        },
+        None => None,
+    };
+    let usage_csv = match &args.usage_csv {
+        Some(_) if args.print_config => None,
+        Some(path) => match UsageCsv::open(path) {
+            Ok(usage_csv) => Some(usage_csv),
+            Err(e) => {
+                eprintln!("{} {}: {}", "❌ 无法打开 Token 使用记录:".red().bold(), path.display(), e);
+                return Err(e.into());
+            }
+        },
+        None => None,
+    };
+
+    let server = server
+        .with_bind_addr(SocketAddr::from(([0, 0, 0, 0], args.port)))
+        .with_admin_addr(args.admin_port.map(|port| SocketAddr::new(args.admin_host, port)))
+        .with_rate_limit(args.rate_limit)
+        .with_strategy(args.strategy)
+        .with_tls_config(tls_config)
+        .with_state_options({
+            let args = args.clone();
+            move |state| state
+                .with_http2(args.http2)
+                .with_connect_timeout(args.connect_timeout_ms.map(Duration::from_millis))
+                .with_request_timeout(args.request_timeout_ms.map(Duration::from_millis))
+                .with_preserve_host(args.preserve_host)
+                .with_round_robin_jitter(args.rr_jitter)
+                .with_explore_percent(args.explore_percent)
+                .with_log_level(args.log_level)
+                .with_queue_wait(Duration::from_millis(args.queue_wait_ms))
+                .with_dns_cooldown(Duration::from_secs(args.dns_cooldown_secs))
+                .with_health_check_interval((args.health_check_secs > 0).then(|| Duration::from_secs(args.health_check_secs)))
+                .with_health_check_jitter_percent(args.health_check_jitter)
+                .with_max_failover(args.max_failover.map(|n| n as usize))
+                .with_max_buffered_body(args.max_buffered_body.map(|n| n as usize))
+                .with_token_estimator(HeuristicTokenEstimator::new(TokenEstimationConfig {
+                    max_parse_bytes: args.token_parse_max_bytes as usize,
+                    media_tokens: args.media_tokens,
+                }))
+                .with_max_concurrency(args.max_concurrency.map(|n| n as usize))
+                .with_follow_redirects(args.follow_redirects)
+                .with_retry_same_on_5xx(args.retry_same_on_5xx)
+                .with_expose_provider_header(args.expose_provider_header)
+                .with_rate_limit_mode(args.rate_limit_mode)
+                .with_access_log(access_log)
+                .with_usage_csv(usage_csv)
+                .with_emergency_mode(!args.no_emergency_mode)
+                .with_auto_recovery(args.auto_recovery)
+                .with_maintenance_duration(Duration::from_secs(args.maintenance_minutes * 60))
+                .with_retry_after(args.retry_after.iter().fold(RetryAfter::default(), |mut retry_after, &(scenario, secs)| {
+                    retry_after.set(scenario, secs);
+                    retry_after
+                }))
+                .with_unavailable_responses({
+                    let mut responses = UnavailableResponses::default();
+                    for &(condition, status) in &args.unavailable_status {
+                        responses.get_mut(condition).status = Some(status);
+                    }
+                    for (condition, template) in &args.unavailable_body {
+                        responses.get_mut(*condition).body = Some(template.clone());
+                    }
+                    responses
+                })
+                .with_tag_routes(args.routes)
+                .with_allowed_requests(args.allow_rules)
+                .with_coalesce_paths(args.coalesce_paths)
+                .with_response_cache((!args.cache_rules.is_empty()).then(|| {
+                    ResponseCache::new(args.cache_rules)
+                        .with_vary_headers(args.cache_vary)
+                        .with_capacity(args.cache_capacity as usize)
+                }))
+                .with_sticky_header(args.sticky_header)
+                .with_error_pointer(args.error_pointer)
+                .with_admin_token(args.admin_token)
+                .with_health_config(HealthConfig {
+                    idle_recovery_secs: args.idle_recovery_secs,
+                    idle_recovery_points: args.idle_recovery_points,
+                    recovery_successes: args.recovery_successes,
+                    warmup_requests: args.warmup_requests,
+                    warmup_secs: args.warmup_secs,
+                })
+        });
+
+    if args.print_config {
+        print_effective_config(server, &args);
+        return Ok(());
+    }
+
+    if args.self_test {
+        let all_passed = run_self_test(server, &args).await;
+        std::process::exit(if all_passed { 0 } else { 1 });
+    }
+
+    // 导出器在 main 返回时 drop，把缓冲中的 span 发送出去
+    #[cfg(feature = "otlp")]
+    let _telemetry = match &args.otlp_endpoint {
+        Some(endpoint) => match auto_proxy::Telemetry::init(endpoint) {
+            Ok(telemetry) => Some(telemetry),
+            Err(e) => {
+                eprintln!("{} {}", "❌ 链路追踪初始化失败:".red().bold(), e);
+                return Err(e.into());
+            }
+        },
+        None => None,
+    };
 
     if args.no_ui {
         // 传统日志模式
-        run_traditional_mode(providers, state, server_info, args.port).await
+        run_traditional_mode(server).await
     } else {
         // 终端UI模式
-        run_ui_mode(providers, state, server_info, args.port).await
+        run_ui_mode(server, &args).await
     }
 }
 
+/// 打印实际生效的配置：服务器部分来自 `ProxyServer::effective_config`，再补充界面和访问日志等只在命令行中的设置
+fn print_effective_config(server: ProxyServer, args: &Args) {
+    let threshold = |threshold: &Option<TokenThreshold>| threshold.map(|threshold| match threshold {
+        TokenThreshold::Tokens(tokens) => tokens.to_string(),
+        TokenThreshold::Percent(percent) => format!("{}%", percent),
+    });
+
+    let mut config = server.effective_config();
+    config["access_log"] = serde_json::json!(args.access_log.as_ref().map(|path| serde_json::json!({
+        "path": path,
+        "format": args.access_log_format.to_possible_value().map(|v| v.get_name().to_string()),
+    })));
+    config["usage_csv"] = serde_json::json!(args.usage_csv);
+    #[cfg(feature = "otlp")]
+    {
+        config["otlp_endpoint"] = serde_json::json!(args.otlp_endpoint);
+    }
+    config["ui"] = serde_json::json!({
+        "enabled": !args.no_ui,
+        "mode": args.ui.to_possible_value().map(|v| v.get_name().to_string()),
+        "idle_secs": args.ui_idle_secs,
+        "show_hosts": args.show_hosts,
+        "degraded_threshold": args.degraded_threshold,
+        "timestamp_format": args.timestamp_format,
+        "utc": args.utc,
+        "token_warning": threshold(&args.token_warning),
+        "token_critical": threshold(&args.token_critical),
+    });
+
+    match serde_json::to_string_pretty(&config) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("{} {}", "❌ 配置序列化失败:".red().bold(), e),
+    }
+}
+
+/// 校验配置并打印每个提供商的检查结果，全部通过时返回 true
+fn run_config_check(providers: &[auto_proxy::Provider]) -> bool {
+    println!();
+    println!("{}", "🔍 配置检查结果:".bright_cyan().bold());
+    
+    let results = validate_providers(providers);
+    let mut invalid_count = 0;
+    for (index, (provider, problems)) in providers.iter().zip(results.iter()).enumerate() {
+        let icon = if problems.is_empty() { "✅" } else { "❌" };
+        println!("  {} {}. {} - {} (Token: {})", 
+            icon,
+            index + 1,
+            provider.name.bright_cyan(),
+            provider.base_url.bright_white(),
+            provider.masked_token().bright_yellow()
+        );
+        for problem in problems {
+            println!("       {} {}", "↳".red(), problem.red());
+        }
+        if !problems.is_empty() {
+            invalid_count += 1;
+        }
+    }
+    
+    println!();
+    if invalid_count == 0 {
+        println!("{} {} 个提供商全部通过检查", "✅".green(), providers.len());
+        true
+    } else {
+        println!("{} {} 个提供商中有 {} 个存在问题", "❌".red(), providers.len(), invalid_count);
+        false
+    }
+}
+
+/// 向每个提供商发送自检请求并打印结果，全部成功时返回 true
+async fn run_self_test(server: ProxyServer, args: &Args) -> bool {
+    let request = SelfTestRequest {
+        method: args.self_test_method.clone(),
+        path: args.self_test_path.clone(),
+        body: args.self_test_body.clone(),
+        timeout: Duration::from_secs(args.self_test_timeout_secs),
+    };
+    println!();
+    println!("{} {} {}", "🩺 自检:".bright_cyan().bold(), request.method, request.path.bright_white());
+    
+    let results = server.self_test(request).await;
+    let mut failed_count = 0;
+    for (index, (provider, result)) in results.iter().enumerate() {
+        let icon = if result.success { "✅" } else { "❌" };
+        let outcome = match &result.outcome {
+            Ok(status) if result.success => status.to_string().bright_green(),
+            Ok(status) => status.to_string().bright_red(),
+            Err(e) => e.to_string().bright_red(),
+        };
+        let disabled_note = if provider.is_enabled() { "" } else { " (配置中已禁用)" };
+        println!("  {} {}. {}{} - {} | {}ms | Token: {}",
+            icon,
+            index + 1,
+            provider.name.bright_cyan(),
+            disabled_note.bright_black(),
+            outcome,
+            result.latency_ms,
+            provider.masked_token().bright_yellow()
+        );
+        if !result.success {
+            failed_count += 1;
+        }
+    }
+    
+    println!();
+    if failed_count == 0 {
+        println!("{} {} 个提供商全部通过自检", "✅".green(), results.len());
+        true
+    } else {
+        println!("{} {} 个提供商中有 {} 个自检失败", "❌".red(), results.len(), failed_count);
+        false
+    }
+}
+
+/// 收到 SIGUSR1 时输出当前提供商池状态快照，便于在 systemd 等无界面环境下排查
+#[cfg(unix)]
+fn spawn_state_dump_on_sigusr1(store: Arc<ProviderStore>, state: Arc<ProxyState>, output: impl Fn(String) + Send + 'static) {
+    let mut signals = match signal::unix::signal(signal::unix::SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            eprintln!("{} {}", "⚠️  无法监听 SIGUSR1，状态快照不可用:".yellow(), e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            output(state.state_dump(&store.current()));
+        }
+    });
+}
+
 /// 运行传统日志模式
-async fn run_traditional_mode(
-    providers: Arc<Vec<auto_proxy::Provider>>,
-    state: Arc<ProxyState>,
-    _server_info: Arc<ServerInfo>,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_traditional_mode(server: ProxyServer) -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", "🚀 Auto Proxy 启动中...".bright_blue().bold());
+    
+    let mut handle = match server.run().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("{} {}", "❌ 服务器启动失败:".red().bold(), e);
+            return Err(e);
+        }
+    };
+    let providers = handle.providers();
+    let store = Arc::clone(handle.provider_store());
+    let state = Arc::clone(handle.state());
+    #[cfg(unix)]
+    spawn_state_dump_on_sigusr1(Arc::clone(&store), Arc::clone(&state), |dump| eprintln!("{}", dump));
     println!();
     
     // 打印提供商信息
@@ -85,115 +712,224 @@ async fn run_traditional_mode(
     }
     println!();
     
-    println!("{}", "⚡ 负载均衡模式: 轮询 + 健康度权重".bright_green());
-    println!("{} 速率限制: 每个供应商每分钟最多 {} 次请求", "🎯".cyan(), state.get_rate_limit());
-    println!("{} 健康度系统: 自动故障恢复和快速失败", "💚".green());
-    println!();
-
-    // 启动HTTP服务器
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    for provider in providers.iter().filter(|p| p.insecure_skip_verify) {
+        println!("{} {}", 
+            "⚠️  TLS证书校验已禁用:".bright_red().bold(),
+            format!("{} ({})", provider.name, provider.base_url).bright_red()
+        );
+    }
     
-    let make_svc = make_service_fn(move |_conn| {
-        let providers = Arc::clone(&providers);
-        let state = Arc::clone(&state);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle_request(req, Arc::clone(&providers), Arc::clone(&state))
-            }))
+    for provider in providers.iter().filter(|p| p.is_canary()) {
+        println!("{} {} ({}% 流量)", 
+            "🐤 金丝雀提供商:".bright_yellow(),
+            provider.name.bright_cyan(),
+            provider.canary_percent.unwrap_or(0)
+        );
+    }
+    
+    for provider in providers.iter().filter(|p| p.shadow) {
+        println!("{} {}", "🪞 影子提供商（只接收镜像请求）:".bright_yellow(), provider.name.bright_cyan());
+    }
+    
+    for provider in providers.iter().filter(|p| p.is_default) {
+        println!("{} {}", "🏠 默认提供商（处理未命中路由的请求）:".bright_yellow(), provider.name.bright_cyan());
+    }
+    
+    for (prefix, tag) in &state.tag_routes {
+        let count = state.providers_with_tag(&providers, tag).len();
+        let route_msg = format!("🏷️  路由: {} → 标签 {} ({} 个提供商)", prefix, tag, count);
+        if count == 0 {
+            println!("{}", route_msg.bright_red());
+        } else {
+            println!("{}", route_msg.bright_green());
         }
-    });
-
-    let server = Server::bind(&addr).serve(make_svc);
+    }
+    
+    for (method, prefix) in &state.allowed_requests {
+        let method = method.as_ref().map(|m| m.to_string()).unwrap_or_else(|| "*".to_string());
+        println!("{} {} {}", "🛡️  访问白名单:".bright_yellow(), method.bright_white(), prefix.bright_white());
+    }
+    
+    if let Some(coalescer) = &state.coalescer {
+        println!("{} {}", "🔗 合并相同的并发 GET/HEAD 请求:".bright_yellow(), coalescer.paths().join(", ").bright_white());
+    }
+    
+    for (prefix, ttl) in state.response_cache.iter().flat_map(|cache| cache.rules()) {
+        println!("{} {} {}", "💾 缓存 GET 响应:".bright_yellow(), prefix.bright_white(), format!("{} 秒", ttl.as_secs()).bright_white());
+    }
     
+    println!("{}", format!("⚡ 负载均衡模式: {}", state.strategy.description()).bright_green());
+    println!("{} 速率限制: 每个供应商每分钟最多 {} 次请求（{}）", "🎯".cyan(), state.get_rate_limit(), state.rate_limit_mode.description());
+    println!("{} 健康度系统: 自动故障恢复和快速失败", "💚".green());
+    if state.emergency_mode {
+        println!("{} 紧急模式: 所有供应商都不健康时仍逐个尝试一轮", "🚨".yellow());
+    } else {
+        println!("{} 紧急模式: 已关闭，所有供应商都不健康时直接返回 503", "⛔".red());
+    }
+    if !state.auto_recovery {
+        println!("{} 自动恢复: 已关闭，所有供应商健康度都为 0 时直接返回 503", "⛔".red());
+    }
+    println!();
+
+    let port = handle.local_addr().port();
+    let scheme = if handle.is_tls() { "https" } else { "http" };
     println!("{} 服务器启动成功，监听端口: {}", 
         "🌟".bright_green(), 
         port.to_string().bright_yellow().bold()
     );
     println!("{} 访问地址: {}", 
         "🔗".cyan(), 
-        format!("http://localhost:{}", port).bright_blue().underline()
+        format!("{}://localhost:{}", scheme, port).bright_blue().underline()
     );
+    if let Some(admin_addr) = handle.admin_addr() {
+        println!("{} 管理接口: {}", 
+            "🛠️".cyan(), 
+            format!("http://{}", admin_addr).bright_blue().underline()
+        );
+    }
     println!();
 
-    if let Err(e) = server.await {
+    tokio::select! {
+        result = handle.wait() => {
+            if let Err(e) = result {
+                eprintln!("{} {}", "❌ 服务器错误:".red().bold(), e);
+            }
+        }
+        _ = signal::ctrl_c() => {
+            println!("{}", "接收到 Ctrl+C 信号，正在优雅退出...".yellow());
+        }
+    }
+    if let Err(e) = handle.shutdown().await {
         eprintln!("{} {}", "❌ 服务器错误:".red().bold(), e);
     }
 
+    state.print_shutdown_summary(&store.current());
+
     Ok(())
 }
 
 /// 运行终端UI模式
-async fn run_ui_mode(
-    providers: Arc<Vec<auto_proxy::Provider>>,
-    state: Arc<ProxyState>,
-    server_info: Arc<ServerInfo>,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_ui_mode(server: ProxyServer, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    // 发生 panic 时先恢复终端，避免留下处于原始模式的终端
+    install_terminal_panic_hook();
+    
     // 初始化终端UI
     let mut terminal_ui = TerminalUI::new()?;
+    terminal_ui.set_log_level(args.log_level);
+    terminal_ui.set_mode(args.ui);
+    terminal_ui.set_show_hosts(args.show_hosts);
+    terminal_ui.set_degraded_threshold(args.degraded_threshold);
+    terminal_ui.set_token_thresholds(args.token_warning, args.token_critical);
+    if let Ok(timestamp_format) = TimestampFormat::new(&args.timestamp_format, args.utc) {
+        terminal_ui.set_timestamp_format(timestamp_format);
+    }
+    terminal_ui.set_idle_after((args.ui_idle_secs > 0).then(|| Duration::from_secs(args.ui_idle_secs)));
     terminal_ui.initialize()?;
     
     let logger = terminal_ui.logger();
-    
-    // 异步检测网络状态，不阻塞启动
-    let server_info_clone = Arc::clone(&server_info);
-    tokio::spawn(async move {
-        let network_status = NetworkStatus::detect().await;
-        server_info_clone.update_network_status(network_status.clone());
-    });
+    // 创建全局日志记录器用于代理模块和退出处理
+    let global_logger = Arc::new(logger.clone());
     
     // 记录启动日志
     logger.info("🚀 Auto Proxy 启动中...".to_string());
+    
+    let mut handle = match server.with_logger(Some(Arc::clone(&global_logger))).run().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            drop(terminal_ui);
+            eprintln!("{} {}", "❌ 服务器启动失败:".red().bold(), e);
+            return Err(e);
+        }
+    };
+    let providers = handle.providers();
+    let store = Arc::clone(handle.provider_store());
+    let state = Arc::clone(handle.state());
+    let server_info = Arc::clone(handle.server_info());
+    
     logger.info(format!("📋 已加载 {} 个提供商", providers.len()));
     
+    // 界面模式下写到 stderr 会破坏界面，快照改为写入日志面板
+    #[cfg(unix)]
+    spawn_state_dump_on_sigusr1(Arc::clone(&store), Arc::clone(&state), {
+        let logger = Arc::clone(&global_logger);
+        move |dump| dump.lines().for_each(|line| logger.info(line.to_string()))
+    });
+    
     for provider in providers.iter() {
         logger.info(format!("  - {} ({})", provider.name, provider.masked_token()));
     }
     
-    logger.info("⚡ 负载均衡模式: 轮询 + 健康度权重".to_string());
-    logger.info(format!("🎯 速率限制: 每个供应商每分钟最多 {} 次请求", server_info.rate_limit));
-    logger.info("💚 健康度系统: 自动故障恢复和快速失败".to_string());
-
-    // 为服务器和UI任务克隆引用
-    let server_providers = Arc::clone(&providers);
-    let server_state = Arc::clone(&state);
-    let ui_providers = Arc::clone(&providers);
-    let ui_state = Arc::clone(&state);
-    let ui_server_info = Arc::clone(&server_info);
-
-    // 创建全局日志记录器用于代理模块和退出处理
-    let global_logger = Arc::new(logger.clone());
-    let server_logger = Arc::clone(&global_logger);
-    let exit_logger = Arc::clone(&global_logger);
+    for provider in providers.iter().filter(|p| p.insecure_skip_verify) {
+        logger.warning(format!("⚠️ TLS证书校验已禁用: {} ({})", provider.name, provider.base_url));
+    }
+    
+    for provider in providers.iter().filter(|p| p.is_canary()) {
+        logger.info(format!("🐤 金丝雀提供商: {} ({}% 流量)", provider.name, provider.canary_percent.unwrap_or(0)));
+    }
+    
+    for provider in providers.iter().filter(|p| p.shadow) {
+        logger.info(format!("🪞 影子提供商（只接收镜像请求）: {}", provider.name));
+    }
+    
+    for provider in providers.iter().filter(|p| p.is_default) {
+        logger.info(format!("🏠 默认提供商（处理未命中路由的请求）: {}", provider.name));
+    }
     
-    // 启动HTTP服务器
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    
-    let make_svc = make_service_fn(move |_conn| {
-        let providers = Arc::clone(&server_providers);
-        let state = Arc::clone(&server_state);
-        let logger = Arc::clone(&server_logger);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                auto_proxy::handle_request_with_logger(req, Arc::clone(&providers), Arc::clone(&state), Some(Arc::clone(&logger)))
-            }))
+    for (prefix, tag) in &state.tag_routes {
+        let count = state.providers_with_tag(&providers, tag).len();
+        let route_msg = format!("🏷️ 路由: {} → 标签 {} ({} 个提供商)", prefix, tag, count);
+        if count == 0 {
+            logger.warning(route_msg);
+        } else {
+            logger.info(route_msg);
         }
-    });
-
-    let server = Server::bind(&addr).serve(make_svc);
+    }
+    
+    for (method, prefix) in &state.allowed_requests {
+        let method = method.as_ref().map(|m| m.to_string()).unwrap_or_else(|| "*".to_string());
+        logger.info(format!("🛡️ 访问白名单: {} {}", method, prefix));
+    }
+    
+    if let Some(coalescer) = &state.coalescer {
+        logger.info(format!("🔗 合并相同的并发 GET/HEAD 请求: {}", coalescer.paths().join(", ")));
+    }
+    
+    for (prefix, ttl) in state.response_cache.iter().flat_map(|cache| cache.rules()) {
+        logger.info(format!("💾 缓存 GET 响应: {} {} 秒", prefix, ttl.as_secs()));
+    }
     
+    logger.info(format!("⚡ 负载均衡模式: {}", state.strategy.description()));
+    logger.info(format!("🎯 速率限制: 每个供应商每分钟最多 {} 次请求（{}）", server_info.rate_limit, state.rate_limit_mode.description()));
+    logger.info("💚 健康度系统: 自动故障恢复和快速失败".to_string());
+    if state.emergency_mode {
+        logger.info("🚨 紧急模式: 所有供应商都不健康时仍逐个尝试一轮".to_string());
+    } else {
+        logger.warning("⛔ 紧急模式: 已关闭，所有供应商都不健康时直接返回 503".to_string());
+    }
+    if !state.auto_recovery {
+        logger.warning("⛔ 自动恢复: 已关闭，所有供应商健康度都为 0 时直接返回 503".to_string());
+    }
+
+    let port = handle.local_addr().port();
+    let scheme = if handle.is_tls() { "https" } else { "http" };
     logger.success(format!("🌟 服务器启动成功，监听端口: {}", port));
-    logger.info(format!("🔗 访问地址: http://localhost:{}", port));
+    logger.info(format!("🔗 访问地址: {}://localhost:{}", scheme, port));
+    if let Some(admin_addr) = handle.admin_addr() {
+        logger.info(format!("🛠️  管理接口: http://{}", admin_addr));
+    }
 
     // 启动UI渲染和事件处理任务
-    let ui_providers_clone = Arc::clone(&ui_providers);
-    let ui_state_clone = Arc::clone(&ui_state);
-    let ui_server_info_clone = Arc::clone(&ui_server_info);
+    let ui_store = Arc::clone(&store);
+    let ui_state_clone = Arc::clone(&state);
+    let ui_server_info_clone = Arc::clone(&server_info);
     let ui_logger = Arc::clone(&global_logger);
+    let exit_logger = Arc::clone(&global_logger);
     
-    let ui_task = tokio::spawn(async move {
-        let mut render_interval = interval(Duration::from_millis(100)); // 10 FPS渲染
-        let mut event_interval = interval(Duration::from_millis(16)); // ~60 FPS事件检查
+    let mut ui_task = tokio::spawn(async move {
+        let mut render_interval = interval(RENDER_INTERVAL);
+        let mut event_interval = interval(EVENT_POLL_INTERVAL);
+        // 空闲时降低渲染和事件检查频率，有请求或输入时立即恢复
+        let mut idle = false;
         
         // 添加信号处理以确保优雅关闭
         let ctrl_c = tokio::signal::ctrl_c();
@@ -202,14 +938,25 @@ async fn run_ui_mode(
         loop {
             tokio::select! {
                 _ = render_interval.tick() => {
-                    // 渲染UI
-                    if let Err(e) = terminal_ui.render(&ui_providers_clone, &ui_state_clone, &ui_server_info_clone) {
+                    // 渲染UI（每次使用当前的提供商列表，重载配置后立即显示）
+                    if let Err(e) = terminal_ui.render(&ui_store.current(), &ui_state_clone, &ui_server_info_clone) {
                         eprintln!("⚠️ UI渲染错误: {}", e);
                         ui_logger.error(format!("UI渲染失败: {}", e));
                         break;
                     }
                 },
                 _ = event_interval.tick() => {
+                    if terminal_ui.is_idle(&ui_state_clone) != idle {
+                        idle = !idle;
+                        let (render_period, event_period) = if idle {
+                            (IDLE_RENDER_INTERVAL, IDLE_EVENT_POLL_INTERVAL)
+                        } else {
+                            (RENDER_INTERVAL, EVENT_POLL_INTERVAL)
+                        };
+                        render_interval = interval(render_period);
+                        event_interval = interval(event_period);
+                    }
+                    
                     // 检查事件（更频繁）
                     if let Ok(key_action) = terminal_ui.check_key_input() {
                         match key_action.as_str() {
@@ -217,12 +964,60 @@ async fn run_ui_mode(
                                 ui_logger.info("用户请求退出...".to_string());
                                 break;
                             }
+                            "export" => {
+                                match export_status_table(&ui_store.current(), &ui_state_clone, &ui_server_info_clone) {
+                                    Ok(path) => ui_logger.success(format!("📄 状态已导出至 {}", path.display())),
+                                    Err(e) => ui_logger.error(format!("状态导出失败: {}", e)),
+                                }
+                            }
+                            "summary" => {
+                                // 等待按键时会阻塞，让出工作线程避免影响同一线程上的请求处理
+                                let result = tokio::task::block_in_place(|| {
+                                    terminal_ui.show_providers_summary(&ui_store.current(), &ui_state_clone)
+                                });
+                                if let Err(e) = result {
+                                    ui_logger.error(format!("显示健康状态汇总失败: {}", e));
+                                }
+                            }
+                            action if action.starts_with("maintenance:") => {
+                                // 选中的服务商在维护中时结束维护，否则进入维护
+                                let provider_key = &action["maintenance:".len()..];
+                                let provider_name = ui_store.current().iter()
+                                    .find(|p| p.state_key() == provider_key)
+                                    .map(|p| p.name.clone())
+                                    .unwrap_or_else(|| provider_key.to_string());
+                                let manager = &ui_state_clone.interactive_manager;
+                                if manager.end_maintenance(provider_key) {
+                                    ui_logger.info(format!("🔧 服务商 {} 已结束维护", provider_name));
+                                } else {
+                                    let duration = ui_state_clone.maintenance_duration;
+                                    manager.start_maintenance(provider_key, duration);
+                                    ui_logger.info(format!("🔧 服务商 {} 进入维护，{} 分钟后自动恢复", provider_name, duration.as_secs() / 60));
+                                }
+                            }
                             action if action.starts_with("toggle:") => {
                                 // 处理服务商启用/禁用切换
-                                let provider_name = &action[7..]; // 移除 "toggle:" 前缀
-                                let was_disabled = ui_state_clone.interactive_manager.toggle_provider(provider_name);
+                                let provider_key = &action[7..]; // 移除 "toggle:" 前缀
+                                let provider_name = ui_store.current().iter()
+                                    .find(|p| p.state_key() == provider_key)
+                                    .map(|p| p.name.clone())
+                                    .unwrap_or_else(|| provider_key.to_string());
+                                // 认证失败的提供商点击按钮时只重置认证状态，不切换启用状态
+                                if ui_state_clone.clear_auth_failed(provider_key) {
+                                    ui_logger.info(format!("🔑 服务商 {} 的认证失败状态已重置", provider_name));
+                                    continue;
+                                }
+                                // 维护中的提供商点击按钮时提前结束维护
+                                if ui_state_clone.interactive_manager.end_maintenance(provider_key) {
+                                    ui_logger.info(format!("🔧 服务商 {} 已结束维护", provider_name));
+                                    continue;
+                                }
+                                let was_disabled = ui_state_clone.interactive_manager.toggle_provider(provider_key);
                                 let status = if was_disabled { "禁用" } else { "启用" };
                                 ui_logger.info(format!("服务商 {} 已{}", provider_name, status));
+                                if let Err(e) = ui_state_clone.interactive_manager.save_disabled_state() {
+                                    ui_logger.warning(format!("保存禁用状态失败: {}", e));
+                                }
                             }
                             _ => {}
                         }
@@ -244,11 +1039,11 @@ async fn run_ui_mode(
 
     // 运行服务器
     let server_result = tokio::select! {
-        result = server => {
+        result = handle.wait() => {
             // 服务器正常结束或出错
             result
         },
-        _ = ui_task => {
+        _ = &mut ui_task => {
             // UI 任务结束（用户按了退出键）
             Ok(())
         }
@@ -263,9 +1058,18 @@ async fn run_ui_mode(
         eprintln!("{} {}", "❌ 服务器错误:".red().bold(), e);
     }
 
-    // 程序退出前的清理工作
+    // 程序退出前的清理工作：确保UI任务已结束（终端在 TerminalUI 析构时恢复），汇总才能输出到正常终端
+    if !ui_task.is_finished() {
+        ui_task.abort();
+        let _ = ui_task.await;
+    }
+    if let Err(e) = handle.shutdown().await {
+        eprintln!("{} {}", "❌ 服务器错误:".red().bold(), e);
+    }
     println!("🔧 正在清理终端状态...");
     
+    state.print_shutdown_summary(&store.current());
+    
     Ok(())
 }
 
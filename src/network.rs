@@ -12,6 +12,12 @@ pub struct NetworkStatus {
     pub error_message: Option<String>,
 }
 
+impl Default for NetworkStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NetworkStatus {
     pub fn new() -> Self {
         Self {
@@ -85,11 +91,9 @@ impl NetworkStatus {
         }
 
         // 3. 如果上面都失败，尝试简单的TCP连接测试
-        if !status.is_online {
-            if let Ok(_) = tokio::net::TcpStream::connect("8.8.8.8:53").await {
-                status.is_online = true;
-                status.latency_ms = Some(connectivity_start.elapsed().as_millis() as u64);
-            }
+        if !status.is_online && tokio::net::TcpStream::connect("8.8.8.8:53").await.is_ok() {
+            status.is_online = true;
+            status.latency_ms = Some(connectivity_start.elapsed().as_millis() as u64);
         }
 
         status
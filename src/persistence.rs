@@ -0,0 +1,64 @@
+//! 运行时状态持久化 - 保存用户在界面中的操作，使其在重启后仍然生效
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::provider::Provider;
+
+/// 持久化的运行时状态
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PersistedState {
+    /// 被用户禁用的提供商（配置了 `id` 的提供商记录ID，否则记录名称）
+    #[serde(default)]
+    pub disabled_providers: Vec<String>,
+}
+
+/// 获取配置文件对应的状态文件路径
+///
+/// 状态文件与配置文件位于同一目录，例如 `providers.json` 对应 `providers.state.json`，
+/// 这样使用不同配置文件启动的实例互不影响。
+pub fn state_file_path(config_path: &Path) -> PathBuf {
+    let stem = config_path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "providers".to_string());
+    config_path.with_file_name(format!("{}.state.json", stem))
+}
+
+/// 读取状态文件，文件不存在时返回空状态
+pub fn load_state(path: &Path) -> Result<PersistedState, String> {
+    if !path.exists() {
+        return Ok(PersistedState::default());
+    }
+    let content = fs::read_to_string(path).map_err(|e| {
+        format!("无法读取状态文件 {}: {}", path.display(), e)
+    })?;
+    serde_json::from_str(&content).map_err(|e| {
+        format!("状态文件格式错误 {}: {}", path.display(), e)
+    })
+}
+
+/// 写入状态文件（先写临时文件再重命名，避免写入中断导致文件损坏）
+pub fn save_state(path: &Path, state: &PersistedState) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(state).map_err(|e| {
+        format!("状态序列化失败: {}", e)
+    })?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, content).map_err(|e| {
+        format!("无法写入状态文件 {}: {}", tmp_path.display(), e)
+    })?;
+    fs::rename(&tmp_path, path).map_err(|e| {
+        format!("无法写入状态文件 {}: {}", path.display(), e)
+    })
+}
+
+/// 只保留配置中仍然存在的提供商，清理已删除提供商的残留记录
+pub fn prune_disabled(disabled: &[String], providers: &[Provider]) -> Vec<String> {
+    let known: HashSet<&str> = providers.iter().map(|p| p.state_key()).collect();
+    let mut seen = HashSet::new();
+    disabled.iter()
+        .filter(|name| known.contains(name.as_str()))
+        .filter(|name| seen.insert(name.as_str()))
+        .cloned()
+        .collect()
+}
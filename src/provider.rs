@@ -1,7 +1,7 @@
 //! 提供商相关的数据结构和功能
 
 use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// 速率限制器
@@ -102,6 +102,36 @@ impl RateLimiter {
     }
 }
 
+/// 熔断阈值：连续失败超过该次数时健康度直接降到0
+pub const CIRCUIT_BREAK_FAILURES: u8 = 10;
+
+/// 健康度系统配置
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HealthConfig {
+    /// 空闲恢复周期（秒）：距上次更新每经过一个周期恢复一次健康度
+    pub idle_recovery_secs: u64,
+    /// 每个空闲周期恢复的健康度分数
+    pub idle_recovery_points: u8,
+    /// 连续失败后需要连续成功多少次才按失败程度大幅恢复健康度（1 表示首次成功即大幅恢复）
+    pub recovery_successes: u8,
+    /// 预热期请求数：新建或重置后的前 N 次请求失败时只扣一半健康度（0 表示不按请求数预热）
+    pub warmup_requests: u32,
+    /// 预热期时长（秒）：新建或重置后 T 秒内失败时只扣一半健康度（0 表示不按时长预热）
+    pub warmup_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            idle_recovery_secs: 300,
+            idle_recovery_points: 5,
+            recovery_successes: 3,
+            warmup_requests: 0,
+            warmup_secs: 0,
+        }
+    }
+}
+
 /// 供应商健康度追踪器
 #[derive(Debug)]
 pub struct ProviderHealth {
@@ -111,25 +141,66 @@ pub struct ProviderHealth {
     consecutive_failures: AtomicU8,
     /// 连续成功次数
     consecutive_successes: AtomicU8,
+    /// 尚未兑现大幅恢复的失败次数（连续成功达到 `recovery_successes` 次前保留）
+    pending_failure_streak: AtomicU8,
     /// 最后更新时间
     last_updated: AtomicU64,
+    /// 预热期开始时间（新建或最近一次重置的时间）
+    warmup_started: AtomicU64,
+    /// 预热期开始后记录的请求数
+    warmup_count: AtomicU32,
+    /// 健康度系统配置
+    config: HealthConfig,
+}
+
+impl Default for ProviderHealth {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProviderHealth {
     pub fn new() -> Self {
+        Self::with_config(HealthConfig::default())
+    }
+    
+    pub fn with_config(config: HealthConfig) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
         Self {
             health_score: AtomicU8::new(100), // 初始健康度100%
             consecutive_failures: AtomicU8::new(0),
             consecutive_successes: AtomicU8::new(0),
-            last_updated: AtomicU64::new(
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-            ),
+            pending_failure_streak: AtomicU8::new(0),
+            last_updated: AtomicU64::new(now),
+            warmup_started: AtomicU64::new(now),
+            warmup_count: AtomicU32::new(0),
+            config,
         }
     }
     
+    /// 是否处于预热期：请求数和时长中任一已配置的上限尚未达到（都为 0 时没有预热期）
+    pub fn in_warmup(&self) -> bool {
+        let by_requests = self.config.warmup_requests > 0
+            && self.warmup_count.load(Ordering::Relaxed) < self.config.warmup_requests;
+        let by_time = self.config.warmup_secs > 0 && {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(std::time::Duration::from_secs(0))
+                .as_secs();
+            now.saturating_sub(self.warmup_started.load(Ordering::Relaxed)) < self.config.warmup_secs
+        };
+        by_requests || by_time
+    }
+    
+    /// 重新开始预热期（健康度被重置时调用）
+    fn restart_warmup(&self, now: u64) {
+        self.warmup_started.store(now, Ordering::Relaxed);
+        self.warmup_count.store(0, Ordering::Relaxed);
+    }
+    
     /// 记录成功请求
     pub fn record_success(&self) {
         let now = SystemTime::now()
@@ -138,16 +209,27 @@ impl ProviderHealth {
             .as_secs();
             
         self.last_updated.store(now, Ordering::Relaxed);
+        self.warmup_count.fetch_add(1, Ordering::Relaxed);
         let previous_failures = self.consecutive_failures.load(Ordering::Relaxed);
         self.consecutive_failures.store(0, Ordering::Relaxed);
         let successes = self.consecutive_successes.fetch_add(1, Ordering::Relaxed).saturating_add(1);
         
-        // 成功时的恢复速度：之前失败越多，恢复越快
+        // 记录待恢复的失败次数，中间夹杂失败时保留较严重的一次
+        let pending_failures = self.pending_failure_streak.load(Ordering::Relaxed).max(previous_failures);
+        
+        // 成功时的恢复速度：之前失败越多，恢复越快；但必须先连续成功 recovery_successes 次，
+        // 避免严重故障后偶然成功一次就被判定为健康并立即承接大量请求
         let current_health = self.health_score.load(Ordering::Relaxed);
+        let proven = successes >= self.config.recovery_successes.max(1);
+        if pending_failures > 0 && !proven {
+            self.pending_failure_streak.store(pending_failures, Ordering::Relaxed);
+        } else {
+            self.pending_failure_streak.store(0, Ordering::Relaxed);
+        }
         if current_health < 100 {
-            let recovery = if previous_failures > 0 {
+            let recovery = if pending_failures > 0 && proven {
                 // 从失败中恢复：根据之前失败次数调整恢复速度
-                match previous_failures {
+                match pending_failures {
                     1..=2 => 10,   // 轻微失败后快速恢复
                     3..=4 => 15,   // 中度失败后中等恢复
                     5..=10 => 25,  // 严重失败后大幅恢复
@@ -173,6 +255,9 @@ impl ProviderHealth {
         self.last_updated.store(now, Ordering::Relaxed);
         self.consecutive_successes.store(0, Ordering::Relaxed);
         let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+        // 先判断再计数，预热期的最后一次请求仍按预热处理
+        let warming_up = self.in_warmup();
+        self.warmup_count.fetch_add(1, Ordering::Relaxed);
         
         // 指数级健康度下降：从慢到快
         let current_health = self.health_score.load(Ordering::Relaxed);
@@ -181,9 +266,11 @@ impl ProviderHealth {
             2 => 10,  // 第2次失败：开始加重
             3 => 20,  // 第3次失败：显著下降
             4 => 35,  // 第4次失败：大幅下降
-            5..=10 => 50, // 第5-10次：严重惩罚
-            _ => current_health, // 超过10次：直接降到0
+            5..=CIRCUIT_BREAK_FAILURES => 50, // 第5-10次：严重惩罚
+            _ => current_health, // 超过熔断阈值：直接降到0
         };
+        // 预热期内只扣一半，启动时的短暂波动不会让正常的提供商立即被熔断
+        let penalty = if warming_up { penalty / 2 } else { penalty };
         
         let new_health = current_health.saturating_sub(penalty);
         self.health_score.store(new_health, Ordering::Relaxed);
@@ -195,15 +282,24 @@ impl ProviderHealth {
             .duration_since(UNIX_EPOCH)
             .unwrap_or(std::time::Duration::from_secs(0))
             .as_secs();
+        self.health_score_at(now)
+    }
+    
+    /// 获取在 `now`（Unix 时间戳，秒）时的健康度分数，先结算到该时刻为止的空闲恢复
+    pub fn health_score_at(&self, now: u64) -> u8 {
         let last_updated = self.last_updated.load(Ordering::Relaxed);
         
-        // 如果超过5分钟没有更新，逐步恢复健康度
-        if now.saturating_sub(last_updated) > 300 { // 5分钟
+        // 空闲期间按周期逐步恢复健康度，空闲多个周期时累计恢复
+        let window = self.config.idle_recovery_secs.max(1);
+        let intervals = now.saturating_sub(last_updated) / window;
+        if intervals > 0 {
             let current_health = self.health_score.load(Ordering::Relaxed);
             if current_health < 100 {
-                let recovery = std::cmp::min(5, 100 - current_health); // 每5分钟恢复5分
-                self.health_score.store(current_health + recovery, Ordering::Relaxed);
-                self.last_updated.store(now, Ordering::Relaxed);
+                let recovery = intervals.saturating_mul(self.config.idle_recovery_points as u64);
+                let new_health = (current_health as u64).saturating_add(recovery).min(100) as u8;
+                self.health_score.store(new_health, Ordering::Relaxed);
+                // 保留不足一个周期的剩余时间，避免频繁查询时丢失恢复进度
+                self.last_updated.store(last_updated + intervals * window, Ordering::Relaxed);
             }
         }
         
@@ -232,6 +328,7 @@ impl ProviderHealth {
                 .unwrap()
                 .as_secs();
             self.last_updated.store(now, Ordering::Relaxed);
+            self.restart_warmup(now);
         }
     }
     
@@ -246,22 +343,195 @@ impl ProviderHealth {
 pub struct Provider {
     /// 提供商名称
     pub name: String,
+    /// 稳定ID（可选）：设置后运行时状态按ID记录，修改名称不会丢失健康度、用量等累计状态
+    #[serde(default)]
+    pub id: Option<String>,
     /// 认证令牌
     pub token: String,
     /// 基础URL
     pub base_url: String,
     /// 密钥类型
     pub key_type: String,
+    /// 跳过TLS证书校验（仅用于自签名证书的内部后端）
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// 静态权重（用于 weighted-static 策略，0 表示仅作备用）
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// 金丝雀流量百分比（0-100），设置后该提供商不参与常规选择，只接收约此比例的请求
+    #[serde(default)]
+    pub canary_percent: Option<u8>,
+    /// 分组标签（如 "chat"、"embedding"、"premium"），用于批量操作和按路径路由
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 支持的模型列表（不设置时支持所有模型）
+    #[serde(default)]
+    pub models: Option<Vec<String>>,
+    /// Authorization 头格式模板，`{token}` 会被替换为token（不设置时为 `Bearer {token}`）
+    #[serde(default)]
+    pub auth_format: Option<String>,
+    /// 每千Token的价格（用于估算费用，不设置时不统计费用）
+    #[serde(default)]
+    pub cost_per_1k_tokens: Option<f64>,
+    /// 启动时是否启用（不设置时为启用，`false` 表示启动后处于禁用状态，可在界面中临时启用）
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// 影子提供商：不参与常规选择，只异步接收每个请求的镜像副本（响应不返回给客户端）
+    #[serde(default)]
+    pub shadow: bool,
+    /// 默认提供商：存在默认提供商时，没有命中任何 `--route` 规则的请求只在默认提供商中选择
+    #[serde(default)]
+    pub is_default: bool,
+    /// 视为成功的状态码（如 `[200, "202-206"]`，不设置时为 2xx）
+    #[serde(default)]
+    pub success_statuses: Option<Vec<StatusCodeRange>>,
+    /// 主动健康检查的路径（如 `/v1/models`），拼接在 base_url 之后用 GET 请求；不设置时对 base_url 发送 HEAD
+    #[serde(default)]
+    pub health_check_path: Option<String>,
+    /// 健康检查视为成功的状态码（不设置时与 `success_statuses` 相同）
+    #[serde(default)]
+    pub health_check_statuses: Option<Vec<StatusCodeRange>>,
+}
+
+/// 状态码范围（闭区间），配置中写作单个状态码 `206` 或字符串 `"200-299"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "StatusCodeSpec", into = "String")]
+pub struct StatusCodeRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl StatusCodeRange {
+    /// 是否包含指定状态码
+    pub fn contains(&self, status: u16) -> bool {
+        (self.start..=self.end).contains(&status)
+    }
+}
+
+/// 配置文件中状态码范围的原始写法
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatusCodeSpec {
+    Code(u16),
+    Text(String),
+}
+
+impl TryFrom<StatusCodeSpec> for StatusCodeRange {
+    type Error = String;
+
+    fn try_from(spec: StatusCodeSpec) -> Result<Self, Self::Error> {
+        let parse = |text: &str| text.trim().parse::<u16>().map_err(|_| format!("无效的状态码: {}", text.trim()));
+        let (start, end) = match spec {
+            StatusCodeSpec::Code(code) => (code, code),
+            StatusCodeSpec::Text(text) => match text.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                None => {
+                    let code = parse(&text)?;
+                    (code, code)
+                }
+            },
+        };
+        if !(100..=599).contains(&start) || !(100..=599).contains(&end) {
+            return Err(format!("状态码超出范围 (100-599): {}-{}", start, end));
+        }
+        if start > end {
+            return Err(format!("状态码范围起点大于终点: {}-{}", start, end));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl From<StatusCodeRange> for String {
+    fn from(range: StatusCodeRange) -> Self {
+        if range.start == range.end {
+            range.start.to_string()
+        } else {
+            format!("{}-{}", range.start, range.end)
+        }
+    }
+}
+
+fn default_weight() -> u32 {
+    1
 }
 
 impl Provider {
     /// 获取屏蔽后的token用于日志显示
+    ///
+    /// 按字符而不是字节截取，避免多字节字符导致 panic；显示的字符最多占token的一半，
+    /// 8个字符及以下的token完全屏蔽。
     pub fn masked_token(&self) -> String {
-        if self.token.len() > 8 {
-            format!("{}****{}", &self.token[..4], &self.token[self.token.len()-4..])
-        } else {
-            "****".to_string()
+        let chars: Vec<char> = self.token.chars().collect();
+        let visible = match chars.len() {
+            0..=8 => return "****".to_string(),
+            9..=16 => 2,
+            _ => 4,
+        };
+        let prefix: String = chars[..visible].iter().collect();
+        let suffix: String = chars[chars.len() - visible..].iter().collect();
+        format!("{}****{}", prefix, suffix)
+    }
+    
+    /// base_url 的协议和主机（含端口），省略路径，用于在界面中确认提供商指向的地址
+    pub fn base_origin(&self) -> String {
+        match self.base_url.parse::<http::Uri>() {
+            Ok(uri) => match (uri.scheme_str(), uri.authority()) {
+                (Some(scheme), Some(authority)) => format!("{}://{}", scheme, authority.as_str().rsplit('@').next().unwrap_or("")),
+                _ => "--".to_string(),
+            },
+            Err(_) => "--".to_string(),
         }
     }
     
+    /// 是否带有指定标签
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+    
+    /// 按 `auth_format` 模板生成 Authorization 头的值
+    pub fn authorization_value(&self) -> String {
+        match &self.auth_format {
+            Some(format) => format.replace("{token}", &self.token),
+            None => format!("Bearer {}", self.token),
+        }
+    }
+    
+    /// 是否支持请求的模型
+    pub fn supports_model(&self, model: &str) -> bool {
+        match &self.models {
+            Some(models) => models.iter().any(|m| m == model),
+            None => true,
+        }
+    }
+    
+    /// 运行时状态表使用的键：设置了 `id` 时为ID，否则为名称
+    pub fn state_key(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.name)
+    }
+    
+    /// 配置中是否启用（`enabled` 未设置时视为启用）
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+    
+    /// 上游状态码是否视为成功（未配置 `success_statuses` 时为 2xx）
+    pub fn is_success_status(&self, status: hyper::StatusCode) -> bool {
+        match &self.success_statuses {
+            Some(ranges) => ranges.iter().any(|range| range.contains(status.as_u16())),
+            None => status.is_success(),
+        }
+    }
+    
+    /// 健康检查的状态码是否视为成功（未配置 `health_check_statuses` 时按 `success_statuses` 判断）
+    pub fn is_health_check_success(&self, status: u16) -> bool {
+        match &self.health_check_statuses {
+            Some(ranges) => ranges.iter().any(|range| range.contains(status)),
+            None => hyper::StatusCode::from_u16(status).is_ok_and(|status| self.is_success_status(status)),
+        }
+    }
+    
+    /// 是否为金丝雀提供商
+    pub fn is_canary(&self) -> bool {
+        self.canary_percent.is_some()
+    }
 }
\ No newline at end of file
@@ -1,16 +1,489 @@
 //! 代理请求处理功能
 
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::convert::Infallible;
 use hyper::{Body, Client, Request, Response};
-use hyper_rustls::HttpsConnectorBuilder;
-use http::header::{HeaderValue, AUTHORIZATION, HOST};
+use http::header::{HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, LOCATION, PROXY_AUTHORIZATION, TRANSFER_ENCODING};
 use colored::*;
-use crate::provider::{Provider, RateLimiter, ProviderHealth};
-use crate::token::{TokenCalculator, calculate_display_width};
+use crate::provider::{Provider, RateLimiter, ProviderHealth, HealthConfig};
+use crate::cache::ResponseCache;
+use crate::coalesce::RequestCoalescer;
+use crate::token::{HeuristicTokenEstimator, RequestAnalysis, TokenEstimator, fit_display_width};
 use crate::interactive::InteractiveProviderManager;
-use std::collections::HashMap;
+use crate::tls::build_https_connector;
+use crate::error::ProxyError;
+use crate::access_log::{AccessLog, AccessLogRecord};
+use crate::usage_csv::UsageCsv;
+use crate::ui::{LogLevel, LogThreshold, ServerInfo};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tracing::Instrument;
+
+/// 请求记录环形缓冲区的最大容量
+pub const REQUEST_LOG_CAPACITY: usize = 500;
+
+/// 每个提供商保留的健康度历史采样数量
+pub const HEALTH_HISTORY_CAPACITY: usize = 60;
+
+/// 计算成功率时每个提供商保留的最近请求结果数量
+pub const SUCCESS_RATE_WINDOW: usize = 100;
+
+/// 计算耗时分位数时每个提供商保留的最近耗时样本数量
+pub const LATENCY_SAMPLE_CAPACITY: usize = 256;
+
+/// fastest 策略默认的探索概率（百分比）
+pub const DEFAULT_EXPLORE_PERCENT: u8 = 10;
+
+/// 默认的主动健康检查间隔抖动（百分比）
+pub const DEFAULT_HEALTH_CHECK_JITTER_PERCENT: u8 = 20;
+
+/// 域名解析失败后默认的冷却时长
+pub const DEFAULT_DNS_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// 纯文本表格（健康汇总、退出汇总、交互式管理）中提供商名称列的显示宽度，过长的名称截断为 `…`
+pub(crate) const NAME_COLUMN_WIDTH: usize = 15;
+
+/// 默认的提供商维护时长
+pub const DEFAULT_MAINTENANCE_DURATION: Duration = Duration::from_secs(30 * 60);
+
+/// 健康巡检重新解析域名的超时时间
+const DNS_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 主动健康检查单个提供商的超时时间
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 并发达到上限时等待空位的最长时间，超过后返回 429
+const CONCURRENCY_WAIT: Duration = Duration::from_millis(200);
+
+/// 滚动平均耗时的平滑系数（新样本的权重）
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+/// 请求量直方图的桶数（每桶一分钟）
+pub const HISTOGRAM_BUCKETS: usize = 60;
+
+/// 请求量直方图每个桶覆盖的时长
+pub const HISTOGRAM_BUCKET_DURATION: Duration = Duration::from_secs(60);
+
+/// 指定提供商的请求头（调试用），不会转发给上游
+pub const PIN_PROVIDER_HEADER: &str = "x-proxy-provider";
+
+/// 客户端指定整个请求（包括所有故障转移）总时限的请求头（毫秒），不会转发给上游
+pub const DEADLINE_HEADER: &str = "x-timeout-ms";
+
+/// 开启 `expose_provider_header` 时，成功响应中标注实际处理请求的提供商（与指定提供商的请求头同名）
+pub const SERVED_BY_HEADER: &str = "x-proxy-provider";
+
+/// 开启 `expose_provider_header` 时，成功响应中标注本次请求尝试过的提供商数量
+pub const ATTEMPTS_HEADER: &str = "x-proxy-attempts";
+
+/// 单次请求的结果记录
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestRecord {
+    /// 请求完成时间
+    pub timestamp: DateTime<Local>,
+    /// 请求方法
+    pub method: String,
+    /// 请求路径
+    pub path: String,
+    /// 处理该请求的提供商
+    pub provider: String,
+    /// 提供商的稳定ID（配置中未设置时不输出）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_id: Option<String>,
+    /// 响应状态码（0 表示网络错误）
+    pub status: u16,
+    /// 上游耗时（毫秒）
+    pub latency_ms: u64,
+    /// 估算的Token使用量（失败时为0）
+    pub tokens: u64,
+}
+
+impl RequestRecord {
+    /// 对应提供商在状态表中的键（与 `Provider::state_key` 一致）
+    pub fn state_key(&self) -> &str {
+        self.provider_id.as_deref().unwrap_or(&self.provider)
+    }
+}
+
+/// 负载均衡策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LoadBalanceStrategy {
+    /// 轮询 + 健康度权重
+    #[default]
+    RoundRobin,
+    /// 按配置的静态权重平滑加权轮询（weight 为 0 的提供商仅作备用）
+    WeightedStatic,
+    /// 优先选择滚动平均耗时最低的健康提供商，按探索概率随机选择其他提供商
+    Fastest,
+    /// 优先选择累计Token使用量最低的健康提供商，使各账户的消耗趋于均衡
+    BalanceTokens,
+}
+
+impl LoadBalanceStrategy {
+    /// 策略的显示名称
+    pub fn description(&self) -> &'static str {
+        match self {
+            LoadBalanceStrategy::RoundRobin => "轮询 + 健康度权重",
+            LoadBalanceStrategy::WeightedStatic => "静态权重平滑轮询 + 健康度过滤",
+            LoadBalanceStrategy::Fastest => "最低延迟优先 + 随机探索",
+            LoadBalanceStrategy::BalanceTokens => "最少Token使用量优先",
+        }
+    }
+}
+
+/// 达到每分钟速率限制后的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RateLimitMode {
+    /// 达到限制的提供商不再被选择
+    #[default]
+    Hard,
+    /// 达到限制的提供商排在最后，其他提供商都不可用时仍会被选择（负载高时可能超过限制）
+    Soft,
+}
+
+impl RateLimitMode {
+    /// 模式的显示名称
+    pub fn description(&self) -> &'static str {
+        match self {
+            RateLimitMode::Hard => "超出后跳过",
+            RateLimitMode::Soft => "软限制，超出后降低优先级",
+        }
+    }
+}
+
+/// 代理自己返回 503/429 的场景，用于分别设置 Retry-After
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryAfterScenario {
+    /// 提供商都被禁用、都认证失败，或本次请求尝试的提供商都失败
+    Unavailable,
+    /// 紧急模式下所有提供商都达到速率限制
+    RateLimited,
+    /// 紧急模式下所有提供商都请求失败
+    Down,
+    /// 并发请求数超过 `--max-concurrency`（429）
+    Overloaded,
+}
+
+impl RetryAfterScenario {
+    /// 按命令行中使用的名称查找场景
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unavailable" => Some(Self::Unavailable),
+            "rate-limited" => Some(Self::RateLimited),
+            "down" => Some(Self::Down),
+            "overloaded" => Some(Self::Overloaded),
+            _ => None,
+        }
+    }
+}
+
+/// 各场景下 503/429 响应的 Retry-After 秒数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RetryAfter {
+    pub unavailable: u64,
+    pub rate_limited: u64,
+    pub down: u64,
+    pub overloaded: u64,
+}
+
+impl Default for RetryAfter {
+    fn default() -> Self {
+        Self {
+            unavailable: 30,
+            rate_limited: 60,
+            down: 120,
+            overloaded: 1,
+        }
+    }
+}
+
+impl RetryAfter {
+    /// 设置指定场景的秒数（场景为 None 时设置所有场景）
+    pub fn set(&mut self, scenario: Option<RetryAfterScenario>, secs: u64) {
+        match scenario {
+            Some(RetryAfterScenario::Unavailable) => self.unavailable = secs,
+            Some(RetryAfterScenario::RateLimited) => self.rate_limited = secs,
+            Some(RetryAfterScenario::Down) => self.down = secs,
+            Some(RetryAfterScenario::Overloaded) => self.overloaded = secs,
+            None => {
+                self.unavailable = secs;
+                self.rate_limited = secs;
+                self.down = secs;
+                self.overloaded = secs;
+            }
+        }
+    }
+}
+
+/// 代理无法转发请求的三种情况，可以分别自定义响应的状态码和响应体
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnavailableCondition {
+    /// 没有配置任何提供商
+    NoProviders,
+    /// 所有提供商都被用户禁用
+    Disabled,
+    /// 所有提供商都不可用（自动恢复或紧急模式关闭、剩余提供商都无法选择，或本次尝试全部失败）
+    Down,
+}
+
+impl UnavailableCondition {
+    /// 按命令行中使用的名称查找情况
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "no-providers" => Some(Self::NoProviders),
+            "disabled" => Some(Self::Disabled),
+            "down" => Some(Self::Down),
+            _ => None,
+        }
+    }
+
+    /// 命令行中使用的名称，也是响应体模板中 `{condition}` 的值
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NoProviders => "no-providers",
+            Self::Disabled => "disabled",
+            Self::Down => "down",
+        }
+    }
+}
+
+/// 某种情况下自定义的响应，未设置的部分使用默认值（503 和纯文本说明）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UnavailableResponse {
+    pub status: Option<u16>,
+    /// 响应体模板，`{message}`、`{status}` 和 `{condition}` 替换为默认说明、状态码和情况名称；
+    /// 以 `{` 或 `[` 开头时作为 JSON 返回，`{message}` 按 JSON 字符串转义
+    pub body: Option<String>,
+}
+
+/// 各种情况下自定义的响应
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UnavailableResponses {
+    pub no_providers: UnavailableResponse,
+    pub disabled: UnavailableResponse,
+    pub down: UnavailableResponse,
+}
+
+impl UnavailableResponses {
+    /// 指定情况的自定义响应
+    pub fn get(&self, condition: UnavailableCondition) -> &UnavailableResponse {
+        match condition {
+            UnavailableCondition::NoProviders => &self.no_providers,
+            UnavailableCondition::Disabled => &self.disabled,
+            UnavailableCondition::Down => &self.down,
+        }
+    }
+
+    /// 指定情况的自定义响应（可修改）
+    pub fn get_mut(&mut self, condition: UnavailableCondition) -> &mut UnavailableResponse {
+        match condition {
+            UnavailableCondition::NoProviders => &mut self.no_providers,
+            UnavailableCondition::Disabled => &mut self.disabled,
+            UnavailableCondition::Down => &mut self.down,
+        }
+    }
+
+    /// 按自定义设置生成响应，`message` 为默认的说明文字，`retry_after` 只在状态码为 503 或 429 时添加
+    pub fn respond(&self, condition: UnavailableCondition, message: &str, retry_after: u64) -> Response<Body> {
+        let custom = self.get(condition);
+        let status = custom.status.unwrap_or(503);
+        let mut builder = Response::builder().status(status);
+        if status == 503 || status == 429 {
+            builder = builder.header("Retry-After", retry_after);
+        }
+        let body = match &custom.body {
+            Some(template) => {
+                let is_json = template.trim_start().starts_with(['{', '[']);
+                let message = if is_json {
+                    let quoted = serde_json::Value::from(message).to_string();
+                    quoted[1..quoted.len() - 1].to_string()
+                } else {
+                    message.to_string()
+                };
+                builder = builder.header(CONTENT_TYPE, if is_json { "application/json" } else { "text/plain; charset=utf-8" });
+                template
+                    .replace("{message}", &message)
+                    .replace("{status}", &status.to_string())
+                    .replace("{condition}", condition.name())
+            }
+            None => message.to_string(),
+        };
+        builder.body(Body::from(body))
+            .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable")))
+    }
+}
+
+/// 单个提供商的耗时分位数（毫秒），基于最近 `LATENCY_SAMPLE_CAPACITY` 次非网络错误请求的耗时计算
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LatencyPercentiles {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    /// 参与计算的请求数
+    pub samples: usize,
+}
+
+/// 单个提供商的累计请求结果（速率限制跳过的不计入）
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RequestCounts {
+    pub success: u64,
+    pub failure: u64,
+}
+
+impl RequestCounts {
+    /// 总请求数
+    pub fn total(&self) -> u64 {
+        self.success + self.failure
+    }
+
+    /// 成功率（百分比），没有请求时返回 None
+    pub fn success_rate(&self) -> Option<f32> {
+        let total = self.total();
+        (total > 0).then(|| self.success as f32 / total as f32 * 100.0)
+    }
+}
+
+/// 单个提供商当前状态的快照（不包含Token和地址，状态快照和仪表盘共用）
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderSnapshot {
+    pub name: String,
+    /// 可用状态：`disabled`、`maintenance`、`auth_failed`、`healthy` 或 `unhealthy`
+    pub status: &'static str,
+    pub health_score: u8,
+    /// 当前速率窗口内的请求数
+    pub current_requests: usize,
+    pub rate_limit: usize,
+    pub tokens: u64,
+    pub counts: RequestCounts,
+    pub last_status: Option<u16>,
+    pub last_latency_ms: Option<u64>,
+    pub average_latency_ms: Option<f64>,
+    pub p95_latency_ms: Option<u64>,
+}
+
+/// 整个提供商池的汇总统计（界面状态栏、管理接口和退出汇总共用）
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PoolStats {
+    /// 健康的提供商数量
+    pub healthy: usize,
+    /// 提供商总数
+    pub total: usize,
+    /// 平均健康度（0-100）
+    pub avg_health: u32,
+    /// 所有提供商的Token使用量总和（估算值）
+    pub total_tokens: u64,
+    /// 累计请求数
+    pub total_requests: u64,
+    /// 累计失败请求数
+    pub failed_requests: u64,
+    /// 故障转移率（百分比）
+    pub failover_rate: f32,
+}
+
+impl PoolStats {
+    /// 成功率（百分比），没有请求时返回 None
+    pub fn success_rate(&self) -> Option<f32> {
+        RequestCounts {
+            success: self.total_requests - self.failed_requests,
+            failure: self.failed_requests,
+        }.success_rate()
+    }
+
+    /// 健康提供商占比低于阈值（百分比）时视为降级，阈值为 0 时从不降级
+    pub fn is_degraded(&self, threshold_percent: u8) -> bool {
+        self.total > 0 && self.healthy * 100 < self.total * threshold_percent as usize
+    }
+}
+
+/// 直方图中一个桶的请求统计
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HistogramBucket {
+    /// 距当前的分钟数（0 表示当前正在累计的桶）
+    pub minutes_ago: usize,
+    pub total: u64,
+    pub failures: u64,
+}
+
+/// 最近一小时按分钟统计的请求总数和失败数（环形缓冲区，由定时器推进）
+pub struct RequestHistogram {
+    totals: [AtomicU64; HISTOGRAM_BUCKETS],
+    failures: [AtomicU64; HISTOGRAM_BUCKETS],
+    current: AtomicUsize,
+}
+
+impl Default for RequestHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestHistogram {
+    pub fn new() -> Self {
+        Self {
+            totals: std::array::from_fn(|_| AtomicU64::new(0)),
+            failures: std::array::from_fn(|_| AtomicU64::new(0)),
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// 在当前桶中记录一次请求
+    pub fn record(&self, succeeded: bool) {
+        let index = self.current.load(Ordering::Relaxed);
+        self.totals[index].fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            self.failures[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 推进到下一个桶，并清零其中一小时前的数据
+    pub fn advance(&self) {
+        let next = (self.current.load(Ordering::Relaxed) + 1) % HISTOGRAM_BUCKETS;
+        self.totals[next].store(0, Ordering::Relaxed);
+        self.failures[next].store(0, Ordering::Relaxed);
+        self.current.store(next, Ordering::Relaxed);
+    }
+
+    /// 所有桶的统计，从最早到当前排列
+    pub fn snapshot(&self) -> Vec<HistogramBucket> {
+        let current = self.current.load(Ordering::Relaxed);
+        (0..HISTOGRAM_BUCKETS)
+            .rev()
+            .map(|minutes_ago| {
+                let index = (current + HISTOGRAM_BUCKETS - minutes_ago) % HISTOGRAM_BUCKETS;
+                HistogramBucket {
+                    minutes_ago,
+                    total: self.totals[index].load(Ordering::Relaxed),
+                    failures: self.failures[index].load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
+
+/// 各个按提供商存储的状态表的条目数量（用于诊断内存占用）
+#[derive(Debug, Clone, Serialize)]
+pub struct StateMapSizes {
+    pub rate_limiters: usize,
+    pub provider_health: usize,
+    pub last_status_codes: usize,
+    pub token_usage: usize,
+    pub weighted_current: usize,
+    pub last_latencies: usize,
+    pub latency_averages: usize,
+    pub latency_samples: usize,
+    pub health_history: usize,
+    pub recent_outcomes: usize,
+    pub healthy_flags: usize,
+    pub input_token_usage: usize,
+    pub request_counts: usize,
+    pub auth_failed: usize,
+    pub disabled_providers: usize,
+    pub maintenance: usize,
+    pub request_log: usize,
+}
 
 /// 代理状态管理
 pub struct ProxyState {
@@ -20,14 +493,134 @@ pub struct ProxyState {
     pub rate_limiters: std::sync::Mutex<HashMap<String, RateLimiter>>,
     /// 每个提供商的健康度追踪器
     pub provider_health: std::sync::Mutex<HashMap<String, ProviderHealth>>,
+    /// 健康度系统配置
+    pub health_config: HealthConfig,
     /// 每个提供商的最后响应状态码
     pub last_status_codes: std::sync::Mutex<HashMap<String, u16>>,
     /// 每个提供商的成功Token使用量统计
     pub token_usage: std::sync::Mutex<HashMap<String, u64>>,
+    /// 每个提供商的累计请求成功/失败次数
+    pub request_counts: std::sync::Mutex<HashMap<String, RequestCounts>>,
+    /// 每个提供商的成功请求中输入部分的Token使用量
+    pub input_token_usage: std::sync::Mutex<HashMap<String, u64>>,
     /// 全局速率限制值
     pub rate_limit: usize,
     /// 交互式管理器
     pub interactive_manager: Arc<InteractiveProviderManager>,
+    /// 最近请求记录（有界环形缓冲区）
+    pub request_log: std::sync::Mutex<VecDeque<RequestRecord>>,
+    /// 是否允许与上游协商 HTTP/2
+    pub http2_enabled: bool,
+    /// 建立到上游的 TCP 连接的超时时间（None 表示使用系统默认值）
+    pub connect_timeout: Option<Duration>,
+    /// 是否保留客户端原始 Host 头（默认改写为目标主机）
+    pub preserve_host: bool,
+    /// 经负载均衡处理的请求总数
+    pub balanced_requests: AtomicU64,
+    /// 发生过故障转移的请求数
+    pub failover_requests: AtomicU64,
+    /// 故障转移事件总数（每跳过一个失败的提供商计一次）
+    pub failover_events: AtomicU64,
+    /// 最近一小时每分钟经负载均衡处理的请求数和失败数
+    pub request_histogram: RequestHistogram,
+    /// 最近一次收到或完成代理请求的时间（管理接口请求不计入），界面据此判断是否空闲
+    pub last_activity: std::sync::Mutex<Instant>,
+    /// 负载均衡策略
+    pub strategy: LoadBalanceStrategy,
+    /// 首选提供商不可用时是否从随机位置继续查找（关闭时严格按顺序轮询）
+    pub round_robin_jitter: bool,
+    /// 平滑加权轮询的当前权重
+    pub weighted_current: std::sync::Mutex<HashMap<String, i64>>,
+    /// 每个提供商最近一次请求的耗时（毫秒）
+    pub last_latencies: std::sync::Mutex<HashMap<String, u64>>,
+    /// 每个提供商成功请求耗时的滚动平均值（毫秒，指数加权）
+    pub latency_averages: std::sync::Mutex<HashMap<String, f64>>,
+    /// 每个提供商最近 `LATENCY_SAMPLE_CAPACITY` 次请求的耗时（毫秒，网络错误不计入），用于计算分位数
+    pub latency_samples: std::sync::Mutex<HashMap<String, VecDeque<u64>>>,
+    /// fastest 策略随机选择其他提供商的概率（百分比）
+    pub explore_percent: u8,
+    /// 每个提供商上次观察到的健康状态，用于只在状态变化时输出日志
+    pub healthy_flags: std::sync::Mutex<HashMap<String, bool>>,
+    /// 每个提供商最近的健康度采样（每次请求成功或失败后记录一次）
+    pub health_history: std::sync::Mutex<HashMap<String, VecDeque<u8>>>,
+    /// 每个提供商最近 `SUCCESS_RATE_WINDOW` 次请求的结果（true 为成功），用于计算滚动成功率
+    pub recent_outcomes: std::sync::Mutex<HashMap<String, VecDeque<bool>>>,
+    /// 认证失败（上游返回 401/403）的提供商，重载配置或手动重置前不再被选择
+    pub auth_failed: std::sync::Mutex<HashSet<String>>,
+    /// 域名解析失败的提供商及最近一次失败的时间，冷却期内不再被选择
+    pub dns_failures: std::sync::Mutex<HashMap<String, Instant>>,
+    /// 域名解析失败后停止选择提供商的时长（健康巡检会在此期间重新解析，成功后提前恢复）
+    pub dns_cooldown: Duration,
+    /// 主动健康检查的间隔（None 表示不主动检查，只根据真实请求评估健康度）
+    pub health_check_interval: Option<Duration>,
+    /// 主动健康检查间隔的抖动比例（百分比），每个提供商按名称哈希得到固定的偏移，避免同时探测
+    pub health_check_jitter_percent: u8,
+    /// 单个请求最多尝试的提供商数量（None 表示不限制，1 表示失败后不转移）
+    pub max_failover: Option<usize>,
+    /// 缓冲请求体的上限（字节），超过时改为流式转发且不做故障转移（None 表示总是完整缓冲）
+    pub max_buffered_body: Option<usize>,
+    /// 同时处理的代理请求数上限（None 表示不限制），超出时返回 429
+    pub concurrency_limit: Option<Arc<tokio::sync::Semaphore>>,
+    /// 并发上限（与 `concurrency_limit` 的许可数一致，用于日志和响应）
+    pub max_concurrency: Option<usize>,
+    /// 在代理中跟随上游重定向的最大次数（0 表示不跟随，重定向按失败处理）
+    pub follow_redirects: usize,
+    /// 上游返回 5xx 时先对同一提供商重试一次再故障转移（网络错误始终直接转移）
+    pub retry_same_on_5xx: bool,
+    /// 达到速率限制后跳过提供商（hard）还是只降低其优先级（soft）
+    pub rate_limit_mode: RateLimitMode,
+    /// 访问日志（None 表示不记录），每个客户端请求一条记录
+    pub access_log: Option<Arc<AccessLog>>,
+    /// Token 使用记录（None 表示不记录），每个成功的请求一行 CSV
+    pub usage_csv: Option<Arc<UsageCsv>>,
+    /// 是否在成功响应中添加 `X-Proxy-Provider` 和 `X-Proxy-Attempts` 头（默认不暴露后端身份）
+    pub expose_provider_header: bool,
+    /// 所有提供商都不健康时是否进入紧急模式（关闭后直接返回 503，避免向故障的池继续消耗额度）
+    pub emergency_mode: bool,
+    /// 所有提供商健康度都降到0时是否自动把它们恢复到最低健康度继续尝试（关闭后直接返回 503）
+    pub auto_recovery: bool,
+    /// 客户端没有通过 `X-Timeout-Ms` 指定时，整个请求（包括所有故障转移）的默认总时限（None 表示不限制）
+    pub request_timeout: Option<Duration>,
+    /// 在界面中按 M 键或通过管理接口未指定时长时，提供商维护状态的持续时间
+    pub maintenance_duration: Duration,
+    /// 各场景下 503/429 响应的 Retry-After 秒数
+    pub retry_after: RetryAfter,
+    /// 没有提供商、全部禁用和全部不可用时自定义的响应
+    pub unavailable_responses: UnavailableResponses,
+    /// Token 估算器（默认为启发式估算）
+    pub token_estimator: Box<dyn TokenEstimator>,
+    /// 选择提供商使用的随机数生成器（None 时使用线程本地的系统熵源，测试中可注入固定种子以复现选择顺序）
+    pub rng: Option<std::sync::Mutex<rand::rngs::StdRng>>,
+    /// 所有提供商都达到速率限制时，等待空闲配额的最长时间（为0时立即返回503）
+    pub queue_wait: Duration,
+    /// 按路径前缀路由到指定标签的规则（路径前缀, 标签）
+    pub tag_routes: Vec<(String, String)>,
+    /// 允许转发的请求（方法, 路径前缀），方法为 None 表示任意方法；为空时不限制
+    pub allowed_requests: Vec<(Option<hyper::Method>, String)>,
+    /// 请求日志的输出级别阈值
+    pub log_level: LogThreshold,
+    /// 会话粘滞使用的请求头（未设置时不启用）
+    pub sticky_header: Option<hyper::header::HeaderName>,
+    /// 成功响应中不应出现的 JSON 指针（如 `/error`），出现时视为失败（未设置时只看状态码）
+    pub error_pointer: Option<String>,
+    /// 服务器信息（用于状态接口）
+    pub server_info: Option<Arc<ServerInfo>>,
+    /// 可重载的提供商列表（用于重载接口，未设置时不支持重载）
+    pub provider_store: Option<Arc<crate::reload::ProviderStore>>,
+    /// 管理接口中修改配置的操作（如重载）所需的令牌（未设置时这些操作不可用）
+    pub admin_token: Option<String>,
+    /// 管理接口是否在独立端口上提供（此时代理端口拒绝所有 `/_` 开头的路径，只转发代理流量）
+    pub separate_admin_port: bool,
+    /// 相同的并发 GET/HEAD 请求合并为一次上游请求（未设置合并路径时为 None）
+    pub coalescer: Option<RequestCoalescer>,
+    /// GET 响应缓存（未设置缓存规则时为 None）
+    pub response_cache: Option<ResponseCache>,
+}
+
+impl Default for ProxyState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProxyState {
@@ -40,15 +633,325 @@ impl ProxyState {
             round_robin_counter: AtomicUsize::new(0),
             rate_limiters: std::sync::Mutex::new(HashMap::new()),
             provider_health: std::sync::Mutex::new(HashMap::new()),
+            health_config: HealthConfig::default(),
             last_status_codes: std::sync::Mutex::new(HashMap::new()),
             token_usage: std::sync::Mutex::new(HashMap::new()),
+            input_token_usage: std::sync::Mutex::new(HashMap::new()),
+            request_counts: std::sync::Mutex::new(HashMap::new()),
             rate_limit,
             interactive_manager: Arc::new(InteractiveProviderManager::new()),
+            request_log: std::sync::Mutex::new(VecDeque::with_capacity(REQUEST_LOG_CAPACITY)),
+            http2_enabled: true,
+            connect_timeout: None,
+            preserve_host: false,
+            balanced_requests: AtomicU64::new(0),
+            failover_requests: AtomicU64::new(0),
+            failover_events: AtomicU64::new(0),
+            request_histogram: RequestHistogram::new(),
+            last_activity: std::sync::Mutex::new(Instant::now()),
+            strategy: LoadBalanceStrategy::default(),
+            round_robin_jitter: true,
+            weighted_current: std::sync::Mutex::new(HashMap::new()),
+            last_latencies: std::sync::Mutex::new(HashMap::new()),
+            latency_averages: std::sync::Mutex::new(HashMap::new()),
+            latency_samples: std::sync::Mutex::new(HashMap::new()),
+            explore_percent: DEFAULT_EXPLORE_PERCENT,
+            healthy_flags: std::sync::Mutex::new(HashMap::new()),
+            health_history: std::sync::Mutex::new(HashMap::new()),
+            recent_outcomes: std::sync::Mutex::new(HashMap::new()),
+            auth_failed: std::sync::Mutex::new(HashSet::new()),
+            dns_failures: std::sync::Mutex::new(HashMap::new()),
+            dns_cooldown: DEFAULT_DNS_COOLDOWN,
+            health_check_interval: None,
+            health_check_jitter_percent: DEFAULT_HEALTH_CHECK_JITTER_PERCENT,
+            max_failover: None,
+            max_buffered_body: None,
+            concurrency_limit: None,
+            max_concurrency: None,
+            follow_redirects: 0,
+            retry_same_on_5xx: false,
+            expose_provider_header: false,
+            access_log: None,
+            usage_csv: None,
+            rate_limit_mode: RateLimitMode::default(),
+            emergency_mode: true,
+            auto_recovery: true,
+            request_timeout: None,
+            maintenance_duration: DEFAULT_MAINTENANCE_DURATION,
+            retry_after: RetryAfter::default(),
+            unavailable_responses: UnavailableResponses::default(),
+            token_estimator: Box::new(HeuristicTokenEstimator::default()),
+            rng: None,
+            queue_wait: Duration::ZERO,
+            tag_routes: Vec::new(),
+            allowed_requests: Vec::new(),
+            log_level: LogThreshold::default(),
+            sticky_header: None,
+            error_pointer: None,
+            server_info: None,
+            provider_store: None,
+            admin_token: None,
+            separate_admin_port: false,
+            coalescer: None,
+            response_cache: None,
         }
     }
 
+    /// 设置健康度系统配置
+    pub fn with_health_config(mut self, config: HealthConfig) -> Self {
+        self.health_config = config;
+        self
+    }
+
+    /// 设置域名解析失败后的冷却时长
+    pub fn with_dns_cooldown(mut self, cooldown: Duration) -> Self {
+        self.dns_cooldown = cooldown;
+        self
+    }
+
+    /// 设置主动健康检查的间隔
+    pub fn with_health_check_interval(mut self, interval: Option<Duration>) -> Self {
+        self.health_check_interval = interval;
+        self
+    }
+
+    /// 设置主动健康检查间隔的抖动比例（0-50，0 表示所有提供商同时检查）
+    pub fn with_health_check_jitter_percent(mut self, percent: u8) -> Self {
+        self.health_check_jitter_percent = percent.min(50);
+        self
+    }
+
+    /// 提供商的主动健康检查计划：首次检查前的延迟和之后的检查间隔
+    ///
+    /// 间隔为 `interval × (1 ± 抖动比例)`，首次延迟落在 `[0, interval × 2 × 抖动比例]` 内，
+    /// 两者都由提供商名称的哈希决定，同一提供商每次启动得到相同的计划，不同提供商的探测错开。
+    pub fn health_check_schedule(&self, provider_key: &str, interval: Duration) -> (Duration, Duration) {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        provider_key.hash(&mut hasher);
+        // 0.0..=1.0 之间的固定位置
+        let position = (hasher.finish() % 10_001) as f64 / 10_000.0;
+        let jitter = self.health_check_jitter_percent as f64 / 100.0;
+        let period = interval.mul_f64(1.0 + (2.0 * position - 1.0) * jitter);
+        let initial_delay = interval.mul_f64(2.0 * position * jitter);
+        (initial_delay, period)
+    }
+
+    /// 设置单个请求最多尝试的提供商数量
+    pub fn with_max_failover(mut self, max_failover: Option<usize>) -> Self {
+        self.max_failover = max_failover;
+        self
+    }
+
+    /// 设置缓冲请求体的上限，超过时流式转发（无法重发，因此不做故障转移）
+    pub fn with_max_buffered_body(mut self, max_buffered_body: Option<usize>) -> Self {
+        self.max_buffered_body = max_buffered_body;
+        self
+    }
+
+    /// 设置同时处理的代理请求数上限
+    pub fn with_max_concurrency(mut self, max_concurrency: Option<usize>) -> Self {
+        self.concurrency_limit = max_concurrency.map(|n| Arc::new(tokio::sync::Semaphore::new(n)));
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// 设置在代理中跟随上游重定向的最大次数
+    pub fn with_follow_redirects(mut self, max_redirects: usize) -> Self {
+        self.follow_redirects = max_redirects;
+        self
+    }
+
+    /// 设置上游返回 5xx 时是否先重试同一提供商
+    pub fn with_retry_same_on_5xx(mut self, enabled: bool) -> Self {
+        self.retry_same_on_5xx = enabled;
+        self
+    }
+
+    /// 设置达到速率限制后的处理方式
+    pub fn with_rate_limit_mode(mut self, mode: RateLimitMode) -> Self {
+        self.rate_limit_mode = mode;
+        self
+    }
+
+    /// 设置访问日志
+    pub fn with_access_log(mut self, access_log: Option<AccessLog>) -> Self {
+        self.access_log = access_log.map(Arc::new);
+        self
+    }
+
+    /// 设置 Token 使用记录文件
+    pub fn with_usage_csv(mut self, usage_csv: Option<UsageCsv>) -> Self {
+        self.usage_csv = usage_csv.map(Arc::new);
+        self
+    }
+
+    /// 设置是否在成功响应中暴露处理请求的提供商和尝试次数
+    pub fn with_expose_provider_header(mut self, enabled: bool) -> Self {
+        self.expose_provider_header = enabled;
+        self
+    }
+
+    /// 设置所有提供商都不健康时是否进入紧急模式
+    pub fn with_emergency_mode(mut self, enabled: bool) -> Self {
+        self.emergency_mode = enabled;
+        self
+    }
+
+    /// 设置所有提供商健康度都降到0时是否自动恢复
+    pub fn with_auto_recovery(mut self, enabled: bool) -> Self {
+        self.auto_recovery = enabled;
+        self
+    }
+
+    /// 设置整个请求（包括所有故障转移）的默认总时限
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// 设置提供商维护状态的默认持续时间
+    pub fn with_maintenance_duration(mut self, duration: Duration) -> Self {
+        self.maintenance_duration = duration;
+        self
+    }
+
+    /// 使用固定种子初始化选择提供商的随机数生成器，使随机选择、探索和抖动的结果可以复现
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng = Some(std::sync::Mutex::new(rand::SeedableRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// 设置Token估算器
+    pub fn with_token_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.token_estimator = Box::new(estimator);
+        self
+    }
+
+    /// 设置 503 响应的 Retry-After 秒数
+    pub fn with_retry_after(mut self, retry_after: RetryAfter) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// 设置没有提供商、全部禁用和全部不可用时的响应
+    pub fn with_unavailable_responses(mut self, responses: UnavailableResponses) -> Self {
+        self.unavailable_responses = responses;
+        self
+    }
+
+    /// 设置所有提供商都达到速率限制时等待空闲配额的最长时间
+    pub fn with_queue_wait(mut self, queue_wait: Duration) -> Self {
+        self.queue_wait = queue_wait;
+        self
+    }
+
+    /// 设置按路径前缀路由到标签的规则
+    pub fn with_tag_routes(mut self, routes: Vec<(String, String)>) -> Self {
+        self.tag_routes = routes;
+        self
+    }
+
+    /// 设置允许转发的请求白名单
+    pub fn with_allowed_requests(mut self, rules: Vec<(Option<hyper::Method>, String)>) -> Self {
+        self.allowed_requests = rules;
+        self
+    }
+
+    /// 设置请求日志的输出级别阈值
+    pub fn with_log_level(mut self, log_level: LogThreshold) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    /// 设置会话粘滞使用的请求头
+    pub fn with_sticky_header(mut self, header: Option<hyper::header::HeaderName>) -> Self {
+        self.sticky_header = header;
+        self
+    }
+
+    /// 设置成功响应中不应出现的 JSON 指针
+    pub fn with_error_pointer(mut self, pointer: Option<String>) -> Self {
+        self.error_pointer = pointer;
+        self
+    }
+
+    /// 设置可重载的提供商列表
+    pub fn with_provider_store(mut self, store: Arc<crate::reload::ProviderStore>) -> Self {
+        self.provider_store = Some(store);
+        self
+    }
+
+    /// 设置管理接口令牌
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token.filter(|t| !t.is_empty());
+        self
+    }
+
+    /// 设置参与请求合并的路径前缀：路径匹配的相同并发 GET/HEAD 请求只向上游发送一次（为空时不合并）
+    pub fn with_coalesce_paths(mut self, paths: Vec<String>) -> Self {
+        self.coalescer = (!paths.is_empty()).then(|| RequestCoalescer::new(paths));
+        self
+    }
+
+    /// 设置 GET 响应缓存
+    pub fn with_response_cache(mut self, cache: Option<ResponseCache>) -> Self {
+        self.response_cache = cache;
+        self
+    }
+
+    /// 设置管理接口是否在独立端口上提供
+    pub fn with_separate_admin_port(mut self, separate: bool) -> Self {
+        self.separate_admin_port = separate;
+        self
+    }
+
+    /// 设置是否允许与上游协商 HTTP/2
+    pub fn with_http2(mut self, enabled: bool) -> Self {
+        self.http2_enabled = enabled;
+        self
+    }
+
+    /// 设置建立上游连接的超时时间
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 关联服务器信息，供状态接口读取启动时间和网络状态
+    pub fn with_server_info(mut self, server_info: Arc<ServerInfo>) -> Self {
+        self.server_info = Some(server_info);
+        self
+    }
+
+    /// 设置负载均衡策略
+    pub fn with_strategy(mut self, strategy: LoadBalanceStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// 设置 fastest 策略的探索概率（百分比，超过100按100处理）
+    pub fn with_explore_percent(mut self, percent: u8) -> Self {
+        self.explore_percent = percent.min(100);
+        self
+    }
+
+    /// 设置轮询是否启用抖动（关闭后选择顺序完全确定，便于测试）
+    pub fn with_round_robin_jitter(mut self, enabled: bool) -> Self {
+        self.round_robin_jitter = enabled;
+        self
+    }
+
+    /// 设置是否保留客户端原始 Host 头
+    pub fn with_preserve_host(mut self, enabled: bool) -> Self {
+        self.preserve_host = enabled;
+        self
+    }
+
     /// 安全获取mutex锁，处理中毒情况
-    fn safe_mutex_lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<T> {
+    fn safe_mutex_lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
         match mutex.lock() {
             Ok(guard) => guard,
             Err(poisoned) => {
@@ -71,6 +974,11 @@ impl ProxyState {
         limiter.can_request()
     }
     
+    /// 是否允许向提供商发送请求：硬限制下需未达到速率限制，软限制下总是允许
+    pub fn rate_limit_allows(&self, provider_name: &str) -> bool {
+        self.rate_limit_mode == RateLimitMode::Soft || self.can_request(provider_name)
+    }
+    
     /// 记录一次请求到指定提供商
     pub fn record_request(&self, provider_name: &str) {
         let mut limiters = Self::safe_mutex_lock(&self.rate_limiters);
@@ -91,16 +999,83 @@ impl ProxyState {
     pub fn record_provider_success(&self, provider_name: &str) {
         let mut health_map = Self::safe_mutex_lock(&self.provider_health);
         let health = health_map.entry(provider_name.to_string())
-            .or_insert_with(|| ProviderHealth::new());
+            .or_insert_with(|| ProviderHealth::with_config(self.health_config));
         health.record_success();
+        let score = health.get_health_score();
+        drop(health_map);
+        self.record_health_sample(provider_name, score);
+        self.count_request(provider_name, true);
     }
     
     /// 记录提供商失败请求
     pub fn record_provider_failure(&self, provider_name: &str) {
         let mut health_map = Self::safe_mutex_lock(&self.provider_health);
         let health = health_map.entry(provider_name.to_string())
-            .or_insert_with(|| ProviderHealth::new());
+            .or_insert_with(|| ProviderHealth::with_config(self.health_config));
         health.record_failure();
+        let score = health.get_health_score();
+        drop(health_map);
+        self.record_health_sample(provider_name, score);
+        self.count_request(provider_name, false);
+    }
+    
+    /// 累计请求成功/失败次数，并记录到滚动成功率窗口
+    fn count_request(&self, provider_name: &str, success: bool) {
+        let mut counts = Self::safe_mutex_lock(&self.request_counts);
+        let entry = counts.entry(provider_name.to_string()).or_default();
+        if success {
+            entry.success += 1;
+        } else {
+            entry.failure += 1;
+        }
+        drop(counts);
+        
+        let mut outcomes = Self::safe_mutex_lock(&self.recent_outcomes);
+        let window = outcomes.entry(provider_name.to_string()).or_default();
+        window.push_back(success);
+        while window.len() > SUCCESS_RATE_WINDOW {
+            window.pop_front();
+        }
+    }
+    
+    /// 获取提供商最近请求中的成功次数和总次数（最多 `SUCCESS_RATE_WINDOW` 次）
+    pub fn get_recent_outcomes(&self, provider_name: &str) -> (usize, usize) {
+        let outcomes = Self::safe_mutex_lock(&self.recent_outcomes);
+        outcomes.get(provider_name)
+            .map(|window| (window.iter().filter(|&&success| success).count(), window.len()))
+            .unwrap_or((0, 0))
+    }
+    
+    /// 获取提供商最近请求的成功率（百分比），还没有请求时为 100
+    pub fn get_success_rate(&self, provider_name: &str) -> f32 {
+        match self.get_recent_outcomes(provider_name) {
+            (_, 0) => 100.0,
+            (successes, total) => successes as f32 / total as f32 * 100.0,
+        }
+    }
+    
+    /// 获取提供商的累计请求成功/失败次数
+    pub fn get_request_counts(&self, provider_name: &str) -> RequestCounts {
+        let counts = Self::safe_mutex_lock(&self.request_counts);
+        counts.get(provider_name).copied().unwrap_or_default()
+    }
+    
+    /// 追加一个健康度采样，超出容量时丢弃最旧的采样
+    fn record_health_sample(&self, provider_name: &str, score: u8) {
+        let mut history = Self::safe_mutex_lock(&self.health_history);
+        let samples = history.entry(provider_name.to_string()).or_default();
+        samples.push_back(score);
+        while samples.len() > HEALTH_HISTORY_CAPACITY {
+            samples.pop_front();
+        }
+    }
+    
+    /// 获取提供商最近的健康度采样（按时间从旧到新）
+    pub fn get_health_history(&self, provider_name: &str) -> Vec<u8> {
+        let history = Self::safe_mutex_lock(&self.health_history);
+        history.get(provider_name)
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default()
     }
 
     /// 记录提供商响应状态码
@@ -116,10 +1091,26 @@ impl ProxyState {
     }
     
     /// 记录成功Token使用量（估算值）
-    pub fn record_token_usage(&self, provider_name: &str, tokens: u64) {
+    pub fn record_token_usage(&self, provider_name: &str, analysis: &RequestAnalysis) {
         let mut usage_map = Self::safe_mutex_lock(&self.token_usage);
         let current_usage = usage_map.entry(provider_name.to_string()).or_insert(0);
-        *current_usage += tokens;
+        *current_usage += analysis.estimated_tokens;
+        drop(usage_map);
+        
+        let mut input_map = Self::safe_mutex_lock(&self.input_token_usage);
+        *input_map.entry(provider_name.to_string()).or_insert(0) += analysis.input_tokens;
+        drop(input_map);
+        
+        if let Some(usage_csv) = &self.usage_csv {
+            usage_csv.record(provider_name, analysis);
+        }
+    }
+    
+    /// 把访问日志和 Token 使用记录缓冲的内容写入文件，两个都会尝试，返回第一个错误
+    pub fn flush_record_files(&self) -> std::io::Result<()> {
+        let access_log = self.access_log.as_ref().map_or(Ok(()), |access_log| access_log.flush());
+        let usage_csv = self.usage_csv.as_ref().map_or(Ok(()), |usage_csv| usage_csv.flush());
+        access_log.and(usage_csv)
     }
     
     /// 获取提供商Token使用量
@@ -128,6 +1119,36 @@ impl ProxyState {
         usage_map.get(provider_name).copied().unwrap_or(0)
     }
     
+    /// 获取提供商Token使用量中输入部分的估算值
+    pub fn get_input_token_usage(&self, provider_name: &str) -> u64 {
+        let input_map = Self::safe_mutex_lock(&self.input_token_usage);
+        input_map.get(provider_name).copied().unwrap_or(0)
+    }
+    
+    /// 计算整个提供商池的汇总统计
+    pub fn pool_stats(&self, providers: &[Provider]) -> PoolStats {
+        let mut stats = PoolStats {
+            total: providers.len(),
+            failover_rate: self.get_failover_rate(),
+            ..PoolStats::default()
+        };
+        let mut total_health = 0u32;
+        for provider in providers {
+            if self.is_provider_healthy(provider.state_key()) {
+                stats.healthy += 1;
+            }
+            total_health += self.get_provider_health_score(provider.state_key()) as u32;
+            stats.total_tokens += self.get_token_usage(provider.state_key());
+            let counts = self.get_request_counts(provider.state_key());
+            stats.total_requests += counts.total();
+            stats.failed_requests += counts.failure;
+        }
+        if !providers.is_empty() {
+            stats.avg_health = total_health / providers.len() as u32;
+        }
+        stats
+    }
+    
     /// 获取所有提供商的Token使用量总和
     pub fn get_total_token_usage(&self) -> u64 {
         let usage_map = Self::safe_mutex_lock(&self.token_usage);
@@ -144,36 +1165,217 @@ impl ProxyState {
         (provider_usage as f32 / total as f32) * 100.0
     }
     
-    /// 获取提供商健康度分数
-    pub fn get_provider_health_score(&self, provider_name: &str) -> u8 {
-        let mut health_map = Self::safe_mutex_lock(&self.provider_health);
-        let health = health_map.entry(provider_name.to_string())
-            .or_insert_with(|| ProviderHealth::new());
-        health.get_health_score()
+    /// 标记提供商认证失败（token 无效或已过期），重试无济于事，停止选择该提供商
+    pub fn mark_auth_failed(&self, provider_name: &str) {
+        Self::safe_mutex_lock(&self.auth_failed).insert(provider_name.to_string());
+        self.count_request(provider_name, false);
     }
     
-    /// 检查提供商是否健康
-    pub fn is_provider_healthy(&self, provider_name: &str) -> bool {
-        let mut health_map = Self::safe_mutex_lock(&self.provider_health);
-        let health = health_map.entry(provider_name.to_string())
-            .or_insert_with(|| ProviderHealth::new());
-        health.is_healthy()
+    /// 检查提供商是否处于认证失败状态
+    pub fn is_auth_failed(&self, provider_name: &str) -> bool {
+        Self::safe_mutex_lock(&self.auth_failed).contains(provider_name)
     }
     
-    /// 检查所有供应商是否都不健康
-    pub fn all_providers_unhealthy(&self, providers: &[Provider]) -> bool {
-        for provider in providers {
-            if self.is_provider_healthy(&provider.name) {
-                return false;
-            }
+    /// 清除提供商的认证失败状态，返回之前是否处于该状态
+    pub fn clear_auth_failed(&self, provider_name: &str) -> bool {
+        Self::safe_mutex_lock(&self.auth_failed).remove(provider_name)
+    }
+    
+    /// 提供商是否可以参与选择（未被禁用、不在维护中且未认证失败）
+    fn is_selectable(&self, provider_name: &str) -> bool {
+        !self.interactive_manager.is_provider_disabled(provider_name)
+            && !self.interactive_manager.is_provider_in_maintenance(provider_name)
+            && !self.is_auth_failed(provider_name)
+            && !self.is_dns_failed(provider_name)
+    }
+    
+    /// 标记提供商域名解析失败，返回之前是否已处于该状态
+    ///
+    /// 解析失败与提供商的服务质量无关，不计入健康度，只在冷却期内停止选择该提供商。
+    pub fn mark_dns_failed(&self, provider_name: &str) -> bool {
+        let previous = Self::safe_mutex_lock(&self.dns_failures).insert(provider_name.to_string(), Instant::now());
+        self.count_request(provider_name, false);
+        previous.is_some_and(|failed_at| failed_at.elapsed() < self.dns_cooldown)
+    }
+    
+    /// 检查提供商是否处于域名解析失败的冷却期
+    pub fn is_dns_failed(&self, provider_name: &str) -> bool {
+        self.dns_cooldown_remaining(provider_name).is_some()
+    }
+    
+    /// 域名解析失败冷却期的剩余时间，不在冷却期时返回 None
+    pub fn dns_cooldown_remaining(&self, provider_name: &str) -> Option<Duration> {
+        Self::safe_mutex_lock(&self.dns_failures)
+            .get(provider_name)
+            .and_then(|failed_at| self.dns_cooldown.checked_sub(failed_at.elapsed()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+    
+    /// 清除提供商的域名解析失败状态，返回之前是否处于该状态
+    pub fn clear_dns_failed(&self, provider_name: &str) -> bool {
+        Self::safe_mutex_lock(&self.dns_failures).remove(provider_name).is_some()
+    }
+    
+    /// 获取提供商最近一次请求的耗时（毫秒）
+    pub fn get_last_latency_ms(&self, provider_name: &str) -> Option<u64> {
+        let latencies = Self::safe_mutex_lock(&self.last_latencies);
+        latencies.get(provider_name).copied()
+    }
+
+    /// 获取提供商成功请求的滚动平均耗时（毫秒），还没有成功请求时返回 None
+    pub fn get_average_latency_ms(&self, provider_name: &str) -> Option<f64> {
+        Self::safe_mutex_lock(&self.latency_averages).get(provider_name).copied()
+    }
+
+    /// 根据提供商最近的耗时样本计算分位数，没有样本时返回 None
+    ///
+    /// 样本数量有上限，复制后排序的开销很小，可以在每次界面渲染时调用。
+    pub fn get_latency_percentiles(&self, provider_name: &str) -> Option<LatencyPercentiles> {
+        let mut latencies: Vec<u64> = match Self::safe_mutex_lock(&self.latency_samples).get(provider_name) {
+            Some(samples) if !samples.is_empty() => samples.iter().copied().collect(),
+            _ => return None,
+        };
+        latencies.sort_unstable();
+        
+        // 最近秩法：取第 ceil(p * n) 个样本
+        let percentile = |p: usize| {
+            let rank = (p * latencies.len()).div_ceil(100);
+            latencies[rank.saturating_sub(1)]
+        };
+        Some(LatencyPercentiles {
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+            samples: latencies.len(),
+        })
+    }
+
+    /// 记录一次请求结果，超出容量时丢弃最旧的记录
+    pub fn record_request_outcome(&self, record: RequestRecord) {
+        self.touch_activity();
+        Self::safe_mutex_lock(&self.last_latencies).insert(record.state_key().to_string(), record.latency_ms);
+        // 网络错误（状态码 0）的耗时反映的是连接超时等，不计入分位数
+        if record.status != 0 {
+            let mut samples = Self::safe_mutex_lock(&self.latency_samples);
+            let window = samples.entry(record.state_key().to_string()).or_default();
+            window.push_back(record.latency_ms);
+            while window.len() > LATENCY_SAMPLE_CAPACITY {
+                window.pop_front();
+            }
+        }
+        // 失败的请求可能很快返回，只用成功请求的耗时计算平均值，避免故障提供商显得更快
+        if (200..300).contains(&record.status) {
+            let mut averages = Self::safe_mutex_lock(&self.latency_averages);
+            let sample = record.latency_ms as f64;
+            averages.entry(record.state_key().to_string())
+                .and_modify(|avg| *avg += LATENCY_EWMA_ALPHA * (sample - *avg))
+                .or_insert(sample);
+        }
+        let mut log = Self::safe_mutex_lock(&self.request_log);
+        log.push_back(record);
+        while log.len() > REQUEST_LOG_CAPACITY {
+            log.pop_front();
+        }
+    }
+
+    /// 记录一次代理请求活动（收到或完成请求时调用）
+    pub fn touch_activity(&self) {
+        *Self::safe_mutex_lock(&self.last_activity) = Instant::now();
+    }
+    
+    /// 距最近一次代理请求活动经过的时间
+    pub fn idle_duration(&self) -> Duration {
+        Self::safe_mutex_lock(&self.last_activity).elapsed()
+    }
+
+    /// 最近 `window_secs` 秒内完成的请求数（基于请求记录，超出记录容量的部分不计入）
+    pub fn recent_request_count(&self, window_secs: i64) -> usize {
+        let cutoff = Local::now() - chrono::Duration::seconds(window_secs);
+        Self::safe_mutex_lock(&self.request_log)
+            .iter()
+            .rev()
+            .take_while(|record| record.timestamp >= cutoff)
+            .count()
+    }
+
+    /// 获取最近的 n 条请求记录（按时间从旧到新）
+    pub fn recent_requests(&self, n: usize) -> Vec<RequestRecord> {
+        let log = Self::safe_mutex_lock(&self.request_log);
+        let start = log.len().saturating_sub(n);
+        log.iter().skip(start).cloned().collect()
+    }
+    
+    /// 记录一次请求经历的故障转移次数及最终结果
+    pub fn record_failovers(&self, failovers: u32, succeeded: bool) {
+        self.balanced_requests.fetch_add(1, Ordering::Relaxed);
+        self.request_histogram.record(succeeded);
+        if failovers > 0 {
+            self.failover_requests.fetch_add(1, Ordering::Relaxed);
+            self.failover_events.fetch_add(failovers as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// 获取故障转移率（发生过转移的请求占比，百分比）
+    pub fn get_failover_rate(&self) -> f32 {
+        let total = self.balanced_requests.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        let failed_over = self.failover_requests.load(Ordering::Relaxed);
+        (failed_over as f32 / total as f32) * 100.0
+    }
+    
+    /// 获取提供商健康度分数
+    pub fn get_provider_health_score(&self, provider_name: &str) -> u8 {
+        let mut health_map = Self::safe_mutex_lock(&self.provider_health);
+        let health = health_map.entry(provider_name.to_string())
+            .or_insert_with(|| ProviderHealth::with_config(self.health_config));
+        health.get_health_score()
+    }
+    
+    /// 获取提供商的连续失败次数
+    pub fn get_consecutive_failures(&self, provider_name: &str) -> u8 {
+        let health_map = Self::safe_mutex_lock(&self.provider_health);
+        health_map.get(provider_name)
+            .map(|health| health.get_consecutive_failures())
+            .unwrap_or(0)
+    }
+    
+    /// 检查提供商是否健康
+    pub fn is_provider_healthy(&self, provider_name: &str) -> bool {
+        let mut health_map = Self::safe_mutex_lock(&self.provider_health);
+        let health = health_map.entry(provider_name.to_string())
+            .or_insert_with(|| ProviderHealth::with_config(self.health_config));
+        health.is_healthy()
+    }
+    
+    /// 检测提供商健康状态是否发生了变化
+    ///
+    /// 与上次观察到的状态比较并更新，变为健康时返回 `Some(true)`，变为不健康时返回 `Some(false)`，
+    /// 未变化时返回 None。首次观察时以健康状态（初始健康度100%）作为基准。
+    pub fn health_transition(&self, provider_name: &str) -> Option<bool> {
+        let healthy = self.is_provider_healthy(provider_name);
+        let mut flags = Self::safe_mutex_lock(&self.healthy_flags);
+        let previous = flags.insert(provider_name.to_string(), healthy).unwrap_or(true);
+        (previous != healthy).then_some(healthy)
+    }
+    
+    /// 检查所有供应商是否都不健康
+    ///
+    /// 金丝雀提供商不参与判断，避免金丝雀故障让整个池进入紧急模式。
+    pub fn all_providers_unhealthy(&self, providers: &[Provider]) -> bool {
+        for provider in pool_providers(providers) {
+            if self.is_provider_healthy(provider.state_key()) {
+                return false;
+            }
         }
         true
     }
     
-    /// 检查所有供应商是否都完全不可用
+    /// 检查所有供应商是否都完全不可用（不含金丝雀提供商）
     pub fn all_providers_down(&self, providers: &[Provider]) -> bool {
-        for provider in providers {
-            let health_score = self.get_provider_health_score(&provider.name);
+        for provider in pool_providers(providers) {
+            let health_score = self.get_provider_health_score(provider.state_key());
             if health_score > 0 {
                 return false;
             }
@@ -188,19 +1390,151 @@ impl ProxyState {
         }
         
         for provider in providers {
-            if !self.interactive_manager.is_provider_disabled(&provider.name) {
+            if !self.interactive_manager.is_provider_disabled(provider.state_key()) {
                 return false;
             }
         }
         true
     }
     
+    /// 检查所有供应商是否都被禁用、维护中、认证失败或域名解析失败
+    pub fn all_providers_unselectable(&self, providers: &[Provider]) -> bool {
+        providers.iter().all(|p| !self.is_selectable(p.state_key()))
+    }
+    
+    /// 获取带有指定标签的提供商
+    pub fn providers_with_tag<'a>(&self, providers: &'a [Provider], tag: &str) -> Vec<&'a Provider> {
+        providers.iter().filter(|p| p.has_tag(tag)).collect()
+    }
+    
+    /// 获取默认提供商（处理没有命中路由规则的请求）
+    pub fn default_providers<'a>(&self, providers: &'a [Provider]) -> Vec<&'a Provider> {
+        providers.iter().filter(|p| p.is_default).collect()
+    }
+    
+    /// 查找请求路径对应的路由标签（最长前缀优先）
+    pub fn route_tag(&self, path: &str) -> Option<&str> {
+        self.tag_routes.iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, tag)| tag.as_str())
+    }
+    
+    /// 按白名单检查请求，允许时返回 None
+    ///
+    /// 路径命中某条规则但方法不匹配时返回 405 及该路径允许的方法，路径未命中任何规则时返回 403。
+    pub fn check_request_allowed(&self, method: &hyper::Method, path: &str) -> Option<(hyper::StatusCode, Vec<String>)> {
+        if self.allowed_requests.is_empty() {
+            return None;
+        }
+        
+        let matching: Vec<&Option<hyper::Method>> = self.allowed_requests.iter()
+            .filter(|(_, prefix)| path.starts_with(prefix.as_str()))
+            .map(|(allowed_method, _)| allowed_method)
+            .collect();
+        if matching.is_empty() {
+            return Some((hyper::StatusCode::FORBIDDEN, Vec::new()));
+        }
+        let method_allowed = matching.iter().any(|allowed| match allowed {
+            Some(allowed_method) => allowed_method == method,
+            None => true,
+        });
+        if method_allowed {
+            return None;
+        }
+        
+        let mut allowed_methods: Vec<String> = matching.iter()
+            .filter_map(|allowed| allowed.as_ref().map(|m| m.to_string()))
+            .collect();
+        allowed_methods.sort();
+        allowed_methods.dedup();
+        Some((hyper::StatusCode::METHOD_NOT_ALLOWED, allowed_methods))
+    }
+    
+    /// 清理已不在配置中的提供商的残留状态，返回被清理的条目总数
+    ///
+    /// 重载配置后调用，避免长期运行、频繁变更配置时状态表持续增长。
+    pub fn gc(&self, providers: &[Provider]) -> usize {
+        let known: HashSet<&str> = providers.iter().map(|p| p.state_key()).collect();
+        let mut removed = Self::prune_map(&self.rate_limiters, &known)
+            + Self::prune_map(&self.provider_health, &known)
+            + Self::prune_map(&self.last_status_codes, &known)
+            + Self::prune_map(&self.token_usage, &known)
+            + Self::prune_map(&self.weighted_current, &known)
+            + Self::prune_map(&self.last_latencies, &known)
+            + Self::prune_map(&self.latency_averages, &known)
+            + Self::prune_map(&self.latency_samples, &known)
+            + Self::prune_map(&self.health_history, &known)
+            + Self::prune_map(&self.recent_outcomes, &known)
+            + Self::prune_map(&self.healthy_flags, &known)
+            + Self::prune_map(&self.input_token_usage, &known)
+            + Self::prune_map(&self.request_counts, &known)
+            + Self::prune_map(&self.dns_failures, &known)
+            + Self::prune_map(&self.interactive_manager.disabled_providers, &known)
+            + Self::prune_map(&self.interactive_manager.maintenance, &known);
+        
+        let mut auth_failed = Self::safe_mutex_lock(&self.auth_failed);
+        let before = auth_failed.len();
+        auth_failed.retain(|name| known.contains(name.as_str()));
+        removed += before - auth_failed.len();
+        
+        removed
+    }
+    
+    /// 重置提供商的熔断和冷却状态：健康度（重新进入预热期）、速率窗口、最近成功率以及认证失败和域名解析失败标记
+    ///
+    /// 用于更换Token或地址后让提供商立即重新参与选择；Token使用量、请求计数等累计统计保持不变。
+    pub fn reset_provider(&self, provider_name: &str) {
+        Self::safe_mutex_lock(&self.provider_health).remove(provider_name);
+        Self::safe_mutex_lock(&self.rate_limiters).remove(provider_name);
+        Self::safe_mutex_lock(&self.recent_outcomes).remove(provider_name);
+        Self::safe_mutex_lock(&self.healthy_flags).remove(provider_name);
+        self.clear_auth_failed(provider_name);
+        self.clear_dns_failed(provider_name);
+    }
+    
+    /// 删除状态表中不在 `known` 里的提供商条目，返回删除数量
+    fn prune_map<V>(map: &std::sync::Mutex<HashMap<String, V>>, known: &HashSet<&str>) -> usize {
+        let mut map = Self::safe_mutex_lock(map);
+        let before = map.len();
+        map.retain(|name, _| known.contains(name.as_str()));
+        before - map.len()
+    }
+    
+    /// 获取各状态表当前的条目数量
+    pub fn map_sizes(&self) -> StateMapSizes {
+        StateMapSizes {
+            rate_limiters: Self::safe_mutex_lock(&self.rate_limiters).len(),
+            provider_health: Self::safe_mutex_lock(&self.provider_health).len(),
+            last_status_codes: Self::safe_mutex_lock(&self.last_status_codes).len(),
+            token_usage: Self::safe_mutex_lock(&self.token_usage).len(),
+            weighted_current: Self::safe_mutex_lock(&self.weighted_current).len(),
+            last_latencies: Self::safe_mutex_lock(&self.last_latencies).len(),
+            latency_averages: Self::safe_mutex_lock(&self.latency_averages).len(),
+            latency_samples: Self::safe_mutex_lock(&self.latency_samples).len(),
+            health_history: Self::safe_mutex_lock(&self.health_history).len(),
+            recent_outcomes: Self::safe_mutex_lock(&self.recent_outcomes).len(),
+            healthy_flags: Self::safe_mutex_lock(&self.healthy_flags).len(),
+            input_token_usage: Self::safe_mutex_lock(&self.input_token_usage).len(),
+            request_counts: Self::safe_mutex_lock(&self.request_counts).len(),
+            auth_failed: Self::safe_mutex_lock(&self.auth_failed).len(),
+            disabled_providers: Self::safe_mutex_lock(&self.interactive_manager.disabled_providers).len(),
+            maintenance: Self::safe_mutex_lock(&self.interactive_manager.maintenance).len(),
+            request_log: Self::safe_mutex_lock(&self.request_log).len(),
+        }
+    }
+    
+    /// 是否有可选择且未达到速率限制的供应商
+    pub fn any_provider_can_request(&self, providers: &[Provider]) -> bool {
+        providers.iter().any(|p| self.is_selectable(p.state_key()) && self.can_request(p.state_key()))
+    }
+    
     /// 紧急恢复所有供应商
     pub fn emergency_recovery_all(&self, providers: &[Provider]) {
         let mut health_map = Self::safe_mutex_lock(&self.provider_health);
         for provider in providers {
-            let health = health_map.entry(provider.name.clone())
-                .or_insert_with(|| ProviderHealth::new());
+            let health = health_map.entry(provider.state_key().to_string())
+                .or_insert_with(|| ProviderHealth::with_config(self.health_config));
             health.emergency_recovery();
         }
     }
@@ -210,10 +1544,10 @@ impl ProxyState {
         println!();
         println!("{}", "📊 提供商健康状态汇总".bright_cyan().bold());
         println!("{}", "═".repeat(70).bright_black());
-        println!("{}  {} {:<15} {:<4} {:<4}   {:<8} {:<4}   {}", 
+        println!("{}  {} {} {:<4} {:<4}   {:<8} {:<4}   {}", 
             "状态".bright_white().bold(),
             "序号".bright_white().bold(),
-            "名称".bright_white().bold(),
+            fit_display_width("名称", NAME_COLUMN_WIDTH).bright_white().bold(),
             "健康".bright_white().bold(),
             "健康度".bright_white().bold(),
             "速率限制".bright_white().bold(),
@@ -222,19 +1556,11 @@ impl ProxyState {
         );
         println!("{}", "─".repeat(70).bright_black());
         
-        let mut healthy_count = 0;
-        let mut total_health = 0u32;
-        
         for (index, provider) in providers.iter().enumerate() {
-            let health_score = self.get_provider_health_score(&provider.name);
-            let current_requests = self.get_current_requests(&provider.name);
+            let health_score = self.get_provider_health_score(provider.state_key());
+            let current_requests = self.get_current_requests(provider.state_key());
             let is_healthy = health_score > 20;
-            let can_request = self.can_request(&provider.name);
-            
-            if is_healthy {
-                healthy_count += 1;
-            }
-            total_health += health_score as u32;
+            let can_request = self.can_request(provider.state_key());
             
             // 状态图标和颜色
             let (status_icon, health_color) = match health_score {
@@ -248,17 +1574,15 @@ impl ProxyState {
             let rate_status = if can_request { "✅" } else { "🚫" };
             
             // 计算各字段的显示宽度（考虑中文字符）
-            let name_display_width = calculate_display_width(&provider.name);
-            let name_padding = if name_display_width < 15 { 15 - name_display_width } else { 1 };
+            let name_display = fit_display_width(&provider.name, NAME_COLUMN_WIDTH);
             
             let health_text = if health_score > 20 { "健康" } else { "异常" };
             let status_text = if is_healthy { "可用" } else { "不可用" };
             
-            println!("{} {:<2} {}{} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {}", 
+            println!("{} {:<2} {} {:<4} {:<4}% │ 速率: {:<2}/{:<2} {} │ {}", 
                 status_icon,
                 index + 1,
-                provider.name.bright_cyan(),
-                " ".repeat(name_padding),
+                name_display.bright_cyan(),
                 if health_score > 20 { health_text.bright_green() } else { health_text.bright_red() },
                 health_score.to_string().color(health_color).bold(),
                 current_requests.to_string().bright_cyan(),
@@ -269,20 +1593,197 @@ impl ProxyState {
         }
         
         println!("{}", "═".repeat(70).bright_black());
-        let avg_health = if providers.is_empty() { 0 } else { total_health / providers.len() as u32 };
+        let stats = self.pool_stats(providers);
         println!("{} 健康供应商: {:<2}/{:<2} │ 平均健康度: {:<3}% │ 系统状态: {}", 
             "🏥".cyan(),
-            healthy_count.to_string().bright_green(),
-            providers.len().to_string().bright_white(),
-            avg_health.to_string().bright_yellow(),
-            if healthy_count > 0 { "正常".bright_green() } else { "警告".bright_red() }
+            stats.healthy.to_string().bright_green(),
+            stats.total.to_string().bright_white(),
+            stats.avg_health.to_string().bright_yellow(),
+            if stats.healthy > 0 { "正常".bright_green() } else { "警告".bright_red() }
+        );
+        println!("{}", "═".repeat(70).bright_black());
+    }
+    
+    /// 每个提供商当前状态的快照，按配置顺序排列
+    pub fn provider_snapshots(&self, providers: &[Provider]) -> Vec<ProviderSnapshot> {
+        providers.iter().map(|provider| {
+            let key = provider.state_key();
+            let status = if self.interactive_manager.is_provider_disabled(key) {
+                "disabled"
+            } else if self.interactive_manager.is_provider_in_maintenance(key) {
+                "maintenance"
+            } else if self.is_auth_failed(key) {
+                "auth_failed"
+            } else if self.is_provider_healthy(key) {
+                "healthy"
+            } else {
+                "unhealthy"
+            };
+            ProviderSnapshot {
+                name: provider.name.clone(),
+                status,
+                health_score: self.get_provider_health_score(key),
+                current_requests: self.get_current_requests(key),
+                rate_limit: self.rate_limit,
+                tokens: self.get_token_usage(key),
+                counts: self.get_request_counts(key),
+                last_status: self.get_last_status_code(key),
+                last_latency_ms: self.get_last_latency_ms(key),
+                average_latency_ms: self.get_average_latency_ms(key),
+                p95_latency_ms: self.get_latency_percentiles(key).map(|p| p.p95),
+            }
+        }).collect()
+    }
+    
+    /// 当前提供商池状态的纯文本快照（不含颜色，适合写入 stderr 或 journald）
+    ///
+    /// 每个提供商一行：健康度、是否禁用、速率窗口、Token使用量、最近状态码和耗时，最后一行为池汇总。
+    pub fn state_dump(&self, providers: &[Provider]) -> String {
+        let mut lines = vec![format!("📊 提供商池状态 {}", Local::now().format("%Y-%m-%d %H:%M:%S"))];
+        for (index, snapshot) in self.provider_snapshots(providers).iter().enumerate() {
+            let availability = match snapshot.status {
+                "disabled" => "禁用",
+                "maintenance" => "维护中",
+                "auth_failed" => "认证失败",
+                "healthy" => "健康",
+                _ => "异常",
+            };
+            let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+            lines.push(format!(
+                "{:>2}. {} [{}] 健康度 {}% 速率 {}/{} Token {} 最近状态 {} 耗时 {} (平均 {}, P95 {})",
+                index + 1,
+                snapshot.name,
+                availability,
+                snapshot.health_score,
+                snapshot.current_requests,
+                snapshot.rate_limit,
+                snapshot.tokens,
+                or_dash(snapshot.last_status.map(|code| code.to_string())),
+                or_dash(snapshot.last_latency_ms.map(|ms| format!("{}ms", ms))),
+                or_dash(snapshot.average_latency_ms.map(|ms| format!("{:.0}ms", ms))),
+                or_dash(snapshot.p95_latency_ms.map(|ms| format!("{}ms", ms))),
+            ));
+        }
+        let stats = self.pool_stats(providers);
+        lines.push(format!(
+            "🏥 健康 {}/{} 平均健康度 {}% 请求 {} 失败 {} 转移率 {:.1}% Token {}",
+            stats.healthy, stats.total, stats.avg_health, stats.total_requests, stats.failed_requests, stats.failover_rate, stats.total_tokens,
+        ));
+        lines.join("\n")
+    }
+    
+    /// 打印退出时的运行汇总：每个提供商的请求数、成功率、Token使用量，以及配置了价格时的估算费用
+    pub fn print_shutdown_summary(&self, providers: &[Provider]) {
+        let show_cost = providers.iter().any(|p| p.cost_per_1k_tokens.is_some());
+        
+        println!();
+        println!("{}", "📊 运行汇总".bright_cyan().bold());
+        println!("{}", "═".repeat(70).bright_black());
+        
+        let mut total_cost = 0.0;
+        for (index, provider) in providers.iter().enumerate() {
+            let counts = self.get_request_counts(provider.state_key());
+            let tokens = self.get_token_usage(provider.state_key());
+            
+            let name_display = fit_display_width(&provider.name, NAME_COLUMN_WIDTH);
+            let success_rate = match counts.success_rate() {
+                Some(rate) => format!("{:.1}%", rate),
+                None => "--".to_string(),
+            };
+            let cost_text = match provider.cost_per_1k_tokens {
+                Some(price) => {
+                    let cost = tokens as f64 / 1000.0 * price;
+                    total_cost += cost;
+                    format!(" │ 费用: {:.4}", cost)
+                }
+                None if show_cost => " │ 费用: --".to_string(),
+                None => String::new(),
+            };
+            
+            println!("{:<2} {} │ 请求: {:<6} │ 成功率: {:<6} │ Token: {}{}",
+                index + 1,
+                name_display.bright_cyan(),
+                counts.total().to_string().bright_white(),
+                success_rate.bright_green(),
+                tokens.to_string().bright_magenta(),
+                cost_text.bright_yellow()
+            );
+        }
+        
+        println!("{}", "═".repeat(70).bright_black());
+        let stats = self.pool_stats(providers);
+        let total_success_rate = match stats.success_rate() {
+            Some(rate) => format!("{:.1}%", rate),
+            None => "--".to_string(),
+        };
+        let total_cost_text = if show_cost { format!(" │ 估算费用: {:.4}", total_cost) } else { String::new() };
+        println!("{} 总请求: {} │ 成功率: {} │ 总Token: {}{}",
+            "📈".cyan(),
+            stats.total_requests.to_string().bright_white(),
+            total_success_rate.bright_green(),
+            stats.total_tokens.to_string().bright_magenta(),
+            total_cost_text.bright_yellow()
         );
         println!("{}", "═".repeat(70).bright_black());
     }
     
+    /// 紧急模式的候选提供商顺序
+    ///
+    /// 跳过被禁用和认证失败的提供商，按健康度从高到低排序；仍处于速率限制冷却中的提供商会被排除，
+    /// 除非所有提供商都在冷却中。
+    pub fn emergency_candidates(&self, providers: &[Provider]) -> Vec<usize> {
+        let mut candidates: Vec<(usize, u8)> = providers.iter()
+            .enumerate()
+            .filter(|(_, p)| self.is_selectable(p.state_key()))
+            .map(|(index, p)| (index, self.get_provider_health_score(p.state_key())))
+            .collect();
+        candidates.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        
+        let ready: Vec<usize> = candidates.iter()
+            .filter(|(index, _)| self.can_request(providers[*index].state_key()))
+            .map(|(index, _)| *index)
+            .collect();
+        if ready.is_empty() {
+            candidates.into_iter().map(|(index, _)| index).collect()
+        } else {
+            ready
+        }
+    }
+    
     /// 使用轮询算法选择下一个健康的提供商
     pub fn select_next_provider(&self, providers: &[Provider]) -> Option<usize> {
-        self.select_provider_with_strategy(providers, false)
+        self.select_next_provider_excluding(providers, &[])
+    }
+    
+    /// 选择下一个提供商，`tried` 为本次请求已经尝试过的提供商
+    ///
+    /// fastest 和 balance-tokens 策略在排序不变时总会选中同一个提供商，故障转移时需要排除已尝试过的；
+    /// 其他策略依靠轮询计数器自然轮转，不使用 `tried`。
+    pub fn select_next_provider_excluding(&self, providers: &[Provider], tried: &[usize]) -> Option<usize> {
+        let select_ranked = match self.strategy {
+            LoadBalanceStrategy::Fastest => Self::select_fastest,
+            LoadBalanceStrategy::BalanceTokens => Self::select_least_tokens,
+            _ => return self.select_provider_with_strategy(providers, false),
+        };
+        if providers.is_empty() {
+            return None;
+        }
+        if let Some(index) = self.select_canary(providers) {
+            return Some(index);
+        }
+        if let Some(index) = select_ranked(self, providers, tried) {
+            return Some(index);
+        }
+        // 没有未尝试过的健康提供商时，继续按轮询选择；金丝雀已经抽取过，不再重复抽取
+        self.select_regular_provider(providers, false)
+    }
+    
+    /// 随机数：设置了种子时使用注入的生成器，否则使用线程本地生成器
+    fn random_u64(&self) -> u64 {
+        match &self.rng {
+            Some(rng) => rand::Rng::gen(&mut *Self::safe_mutex_lock(rng)),
+            None => rand::random(),
+        }
     }
     
     /// 使用随机化策略选择提供商
@@ -290,53 +1791,234 @@ impl ProxyState {
         self.select_provider_with_strategy(providers, true)
     }
     
+    /// 按 `canary_percent` 抽取金丝雀提供商
+    ///
+    /// 每个请求掷一次 0-99 的随机数，依次落入各金丝雀的百分比区间时选中该金丝雀；
+    /// 金丝雀被禁用、不健康或处于速率限制时，该请求回到常规池。
+    fn select_canary(&self, providers: &[Provider]) -> Option<usize> {
+        if !providers.iter().any(|p| p.is_canary()) {
+            return None;
+        }
+        
+        let roll = (self.random_u64() % 100) as u32;
+        let mut threshold = 0u32;
+        for (index, provider) in providers.iter().enumerate() {
+            let Some(percent) = provider.canary_percent else {
+                continue;
+            };
+            threshold += percent.min(100) as u32;
+            if roll < threshold {
+                let available = self.is_selectable(provider.state_key())
+                    && self.can_request(provider.state_key())
+                    && self.is_provider_healthy(provider.state_key());
+                return if available { Some(index) } else { None };
+            }
+        }
+        None
+    }
+    
+    /// 按会话标识选择粘滞的提供商（Rendezvous 哈希）
+    ///
+    /// 在常规池中为每个提供商计算 `hash(会话标识, 名称)`，取最大者作为该会话的提供商，
+    /// 不需要保存会话映射，提供商增减时只有少量会话会迁移。该提供商被禁用、不健康或达到
+    /// 速率限制时返回 None，由调用方按正常负载均衡选择；恢复后会话会自动回到该提供商。
+    pub fn select_sticky_provider(&self, providers: &[Provider], session_key: &[u8]) -> Option<usize> {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        
+        let (index, provider) = providers.iter()
+            .enumerate()
+            .filter(|(_, p)| !p.is_canary())
+            .max_by_key(|(_, p)| {
+                let mut hasher = DefaultHasher::new();
+                session_key.hash(&mut hasher);
+                p.state_key().hash(&mut hasher);
+                hasher.finish()
+            })?;
+        
+        let available = self.is_selectable(provider.state_key())
+            && self.can_request(provider.state_key())
+            && self.is_provider_healthy(provider.state_key());
+        if available { Some(index) } else { None }
+    }
+    
+    /// 平滑加权轮询（Smooth Weighted Round-Robin）
+    ///
+    /// 只在已启用、健康且未被速率限制、权重大于0的提供商之间分配，
+    /// 没有符合条件的提供商时返回 None，由调用方回退到备用提供商。
+    fn select_weighted_static(&self, providers: &[Provider]) -> Option<usize> {
+        let eligible: Vec<usize> = providers.iter()
+            .enumerate()
+            .filter(|(_, p)| p.weight > 0 && !p.is_canary())
+            .filter(|(_, p)| self.is_selectable(p.state_key()))
+            .filter(|(_, p)| self.can_request(p.state_key()) && self.is_provider_healthy(p.state_key()))
+            .map(|(index, _)| index)
+            .collect();
+        if eligible.is_empty() {
+            return None;
+        }
+        
+        let mut current = Self::safe_mutex_lock(&self.weighted_current);
+        let mut total_weight = 0i64;
+        let mut best: Option<(usize, i64)> = None;
+        for &index in &eligible {
+            let weight = providers[index].weight as i64;
+            total_weight += weight;
+            let entry = current.entry(providers[index].state_key().to_string()).or_insert(0);
+            *entry += weight;
+            let is_better = match best {
+                Some((_, best_weight)) => *entry > best_weight,
+                None => true,
+            };
+            if is_better {
+                best = Some((index, *entry));
+            }
+        }
+        
+        let (best_index, _) = best?;
+        if let Some(entry) = current.get_mut(providers[best_index].state_key()) {
+            *entry -= total_weight;
+        }
+        Some(best_index)
+    }
+    
+    /// 最低延迟优先：在健康且未达到速率限制的提供商中选择滚动平均耗时最低的
+    ///
+    /// 还没有耗时样本的提供商优先选择，使其尽快得到测量；否则按 `explore_percent`
+    /// 的概率随机选择一个非最快的提供商，避免其他提供商的耗时数据过期。
+    fn select_fastest(&self, providers: &[Provider], tried: &[usize]) -> Option<usize> {
+        let eligible: Vec<usize> = providers.iter()
+            .enumerate()
+            .filter(|(index, p)| !tried.contains(index) && !p.is_canary())
+            .filter(|(_, p)| self.is_selectable(p.state_key()))
+            .filter(|(_, p)| self.can_request(p.state_key()) && self.is_provider_healthy(p.state_key()))
+            .map(|(index, _)| index)
+            .collect();
+        if eligible.is_empty() {
+            return None;
+        }
+        
+        let averages = Self::safe_mutex_lock(&self.latency_averages);
+        let unmeasured: Vec<usize> = eligible.iter()
+            .copied()
+            .filter(|&index| !averages.contains_key(providers[index].state_key()))
+            .collect();
+        if !unmeasured.is_empty() {
+            return Some(unmeasured[(self.random_u64() as usize) % unmeasured.len()]);
+        }
+        
+        let mut measured: Vec<(usize, f64)> = eligible.iter()
+            .map(|&index| (index, averages.get(providers[index].state_key()).copied().unwrap_or(f64::MAX)))
+            .collect();
+        drop(averages);
+        measured.sort_by(|a, b| a.1.total_cmp(&b.1));
+        
+        if measured.len() > 1 && self.random_u64() % 100 < self.explore_percent as u64 {
+            let others = &measured[1..];
+            return Some(others[(self.random_u64() as usize) % others.len()].0);
+        }
+        Some(measured[0].0)
+    }
+    
+    /// 最少Token使用量优先：在健康且未达到速率限制的提供商中选择累计Token使用量最低的
+    ///
+    /// 使用量相同时（如启动后都为0）按轮询计数器在并列的提供商之间轮转，避免总是选中配置中靠前的。
+    /// 使用量在响应结束后才计入，并发请求会同时选中当前最低的提供商，直到它的使用量超过其他提供商。
+    fn select_least_tokens(&self, providers: &[Provider], tried: &[usize]) -> Option<usize> {
+        let eligible: Vec<(usize, u64)> = providers.iter()
+            .enumerate()
+            .filter(|(index, p)| !tried.contains(index) && !p.is_canary())
+            .filter(|(_, p)| self.is_selectable(p.state_key()))
+            .filter(|(_, p)| self.can_request(p.state_key()) && self.is_provider_healthy(p.state_key()))
+            .map(|(index, p)| (index, self.get_token_usage(p.state_key())))
+            .collect();
+        let lowest = eligible.iter().map(|&(_, usage)| usage).min()?;
+        let tied: Vec<usize> = eligible.into_iter()
+            .filter(|&(_, usage)| usage == lowest)
+            .map(|(index, _)| index)
+            .collect();
+        let turn = self.round_robin_counter.fetch_add(1, Ordering::Relaxed);
+        Some(tied[turn % tied.len()])
+    }
+    
     /// 选择提供商的通用方法
     fn select_provider_with_strategy(&self, providers: &[Provider], use_random: bool) -> Option<usize> {
         if providers.is_empty() {
             return None;
         }
         
+        // 金丝雀流量先于负载均衡策略抽取，其余请求只在常规池中分配
+        if let Some(index) = self.select_canary(providers) {
+            return Some(index);
+        }
+        self.select_regular_provider(providers, use_random)
+    }
+    
+    /// 在常规池（不含金丝雀）中按负载均衡策略选择提供商，调用方负责先抽取金丝雀
+    fn select_regular_provider(&self, providers: &[Provider], use_random: bool) -> Option<usize> {
+        if providers.is_empty() {
+            return None;
+        }
+        
+        if self.strategy == LoadBalanceStrategy::WeightedStatic && !use_random {
+            if let Some(index) = self.select_weighted_static(providers) {
+                return Some(index);
+            }
+            // 所有带权重的提供商都不可用时，继续按轮询选择（包括 weight 为 0 的备用提供商）
+        }
+        
         let provider_count = providers.len();
         let start_index = if use_random {
             // 使用随机起点，避免并发请求冲突
-            use std::collections::hash_map::DefaultHasher;
-            use std::hash::{Hash, Hasher};
-            use std::time::{SystemTime, UNIX_EPOCH};
-            
-            let mut hasher = DefaultHasher::new();
-            SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(std::time::Duration::from_secs(0)).as_nanos().hash(&mut hasher);
-            std::thread::current().id().hash(&mut hasher);
-            (hasher.finish() as usize) % provider_count
+            (self.random_u64() as usize) % provider_count
         } else {
             self.round_robin_counter.fetch_add(1, Ordering::Relaxed) % provider_count
         };
         
+        // 轮询抖动：首选提供商不可用时从随机位置继续查找，避免其流量全部挤到相邻的提供商上
+        // 随机起点只在其余提供商中取，使首选提供商的流量均匀分给其他提供商
+        let fallback_start = if self.round_robin_jitter && !use_random && provider_count > 1 {
+            start_index + 1 + (self.random_u64() as usize) % (provider_count - 1)
+        } else {
+            start_index
+        };
+        let scan_order = move || std::iter::once(start_index).chain(
+            (0..provider_count)
+                .map(move |i| (fallback_start + i) % provider_count)
+                .filter(move |&index| index != start_index)
+        );
+        
         // 从当前索引开始轮询查找健康的提供商
-        for i in 0..provider_count {
-            let index = (start_index + i) % provider_count;
+        for index in scan_order() {
             let provider = &providers[index];
             
-            // 检查是否被禁用
-            if self.interactive_manager.is_provider_disabled(&provider.name) {
+            // 检查是否被禁用或认证失败，金丝雀只通过 select_canary 获得流量
+            if provider.is_canary() || !self.is_selectable(provider.state_key()) {
                 continue;
             }
             
             // 检查速率限制和健康状态
-            if self.can_request(&provider.name) && self.is_provider_healthy(&provider.name) {
+            if self.can_request(provider.state_key()) && self.is_provider_healthy(provider.state_key()) {
                 return Some(index);
             }
         }
         
         // 如果没有健康的提供商，则选择下一个可用的提供商（仅检查速率限制）
-        for i in 0..provider_count {
-            let index = (start_index + i) % provider_count;
+        for index in scan_order() {
             let provider = &providers[index];
             
-            if self.can_request(&provider.name) {
+            if !provider.is_canary() && self.is_selectable(provider.state_key()) && self.can_request(provider.state_key()) {
                 return Some(index);
             }
         }
         
+        // 软限制：所有提供商都达到速率限制时，仍按健康优先选择，而不是让整个池返回 503
+        if self.rate_limit_mode == RateLimitMode::Soft {
+            let selectable = |index: &usize| !providers[*index].is_canary() && self.is_selectable(providers[*index].state_key());
+            return scan_order().filter(selectable).find(|&index| self.is_provider_healthy(providers[index].state_key()))
+                .or_else(|| scan_order().find(selectable));
+        }
+        
         // 如果所有提供商都被速率限制，返回None而不是固定索引
         None
     }
@@ -354,126 +2036,544 @@ pub async fn handle_request_with_logger(
     state: Arc<ProxyState>,
     logger: Option<Arc<crate::ui::Logger>>
 ) -> Result<Response<Body>, Infallible> {
+    // 管理接口由代理自身处理，不转发到上游；管理接口使用独立端口时代理端口不响应也不转发 `/_` 路径
+    if state.separate_admin_port {
+        if req.uri().path().starts_with("/_") {
+            log_event(&state, &logger, LogLevel::Warning, format!("🚷 {} {} 管理接口只在管理端口上提供，返回 404", req.method(), req.uri().path()));
+            return Ok(Response::builder()
+                .status(404)
+                .body(Body::from("Not Found"))
+                .unwrap_or_else(|_| Response::new(Body::from("Not Found"))));
+        }
+    } else if crate::admin::is_admin_path(req.uri().path()) {
+        return Ok(crate::admin::handle_admin_request(&req, &providers, &state, &logger).await);
+    }
+    
+    // 每个客户端请求一个 span，上游的每次尝试是它的子 span（启用 otlp 特性并指定 --otlp-endpoint 时导出）
+    let span = tracing::info_span!(
+        "proxy_request",
+        http.method = %req.method(),
+        http.target = %req.uri().path(),
+        http.status_code = tracing::field::Empty,
+        provider = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+        tokens = tracing::field::Empty,
+    );
+    
+    // 响应确定后写一条访问日志（响应体仍在流式转发，字节数取自 Content-Length）
+    let access_log = state.access_log.clone();
+    let started_at = Instant::now();
+    let header_text = |name: hyper::header::HeaderName| req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let record = access_log.as_ref().map(|_| AccessLogRecord {
+        timestamp: Local::now(),
+        method: req.method().to_string(),
+        path: req.uri().path().to_string(),
+        version: format!("{:?}", req.version()),
+        status: 0,
+        bytes: None,
+        provider: None,
+        latency_ms: 0,
+        tokens: 0,
+        referer: header_text(hyper::header::REFERER),
+        user_agent: header_text(hyper::header::USER_AGENT),
+    });
+    let response = forward_request(req, providers, state, logger).instrument(span.clone()).await?;
+    let status = response.status().as_u16();
+    let served = response.extensions().get::<ServedBy>();
+    let latency_ms = started_at.elapsed().as_millis() as u64;
+    
+    span.record("http.status_code", status);
+    span.record("latency_ms", latency_ms);
+    if let Some(served) = served {
+        span.record("provider", served.provider.as_str());
+        span.record("tokens", served.tokens);
+    }
+    
+    if let (Some(access_log), Some(mut record)) = (access_log, record) {
+        record.status = status;
+        record.bytes = response.headers().get(CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok());
+        if let Some(served) = served {
+            record.provider = Some(served.provider.clone());
+            record.tokens = served.tokens;
+        }
+        record.latency_ms = latency_ms;
+        access_log.write(&record);
+    }
+    Ok(response)
+}
+
+/// 转发客户端请求（管理接口之外的所有请求）
+async fn forward_request(
+    req: Request<Body>,
+    providers: Arc<Vec<Provider>>,
+    state: Arc<ProxyState>,
+    logger: Option<Arc<crate::ui::Logger>>,
+) -> Result<Response<Body>, Infallible> {
+    state.touch_activity();
+    
+    // 请求总时限从收到请求时开始计算，之后每次尝试只能使用剩余的时间
+    let deadline = match req.headers().get(DEADLINE_HEADER) {
+        Some(value) => match value.to_str().ok().and_then(|v| v.trim().parse::<u64>().ok()).filter(|&ms| ms > 0) {
+            Some(ms) => Some(Instant::now() + Duration::from_millis(ms)),
+            None => {
+                return Ok(Response::builder()
+                    .status(400)
+                    .body(Body::from("Invalid X-Timeout-Ms header: expected a positive number of milliseconds"))
+                    .unwrap_or_else(|_| Response::new(Body::from("Bad Request"))));
+            }
+        },
+        None => state.request_timeout.map(|timeout| Instant::now() + timeout),
+    };
+    
+    // 超过并发上限时短暂等待空位，仍然没有空位则由代理直接返回 429，而不是把压力转给上游
+    let _permit = match &state.concurrency_limit {
+        Some(semaphore) => match tokio::time::timeout(CONCURRENCY_WAIT, Arc::clone(semaphore).acquire_owned()).await {
+            Ok(Ok(permit)) => Some(permit),
+            _ => {
+                let limit = state.max_concurrency.unwrap_or(0);
+                let overload_msg = format!("🚦 {} {} 并发请求数已达上限 {}，返回 429", req.method(), req.uri().path(), limit);
+                log_event(&state, &logger, LogLevel::Warning, overload_msg);
+                return Ok(Response::builder()
+                    .status(429)
+                    .header("Retry-After", state.retry_after.overloaded)
+                    .body(Body::from(format!("Too many concurrent requests (limit {}). Please retry shortly.", limit)))
+                    .unwrap_or_else(|_| Response::new(Body::from("Too Many Requests"))));
+            }
+        },
+        None => None,
+    };
+    
+    // 白名单之外的请求在读取请求体和占用速率配额之前直接拒绝
+    if let Some((status, allowed_methods)) = state.check_request_allowed(req.method(), req.uri().path()) {
+        let blocked_msg = format!("🚫 {} {} 不在访问白名单内 [{}]", req.method(), req.uri(), status);
+        log_event(&state, &logger, LogLevel::Warning, blocked_msg);
+        let mut response = Response::builder().status(status);
+        if !allowed_methods.is_empty() {
+            response = response.header(hyper::header::ALLOW, allowed_methods.join(", "));
+        }
+        return Ok(response
+            .body(Body::from(format!("{} {} is not allowed by this proxy", req.method(), req.uri().path())))
+            .unwrap_or_else(|_| Response::new(Body::from("Forbidden"))));
+    }
+    
+    // 缓存的响应直接返回，不访问上游也不占用速率配额；指定提供商或携带会话粘滞请求头的请求
+    // 必须由对应的提供商处理，不使用缓存
+    let cache_key = state.response_cache.as_ref()
+        .filter(|_| !req.headers().contains_key(PIN_PROVIDER_HEADER))
+        .filter(|_| !state.sticky_header.as_ref().is_some_and(|name| req.headers().contains_key(name)))
+        .and_then(|cache| cache.key(req.method(), req.uri(), req.headers()).map(|key| (cache, key)));
+    if let Some((cache, (key, ttl))) = cache_key {
+        if let Some(response) = cache.get(&key) {
+            log_event(&state, &logger, LogLevel::Debug, format!("💾 {} {} 命中缓存 ({})", req.method(), req.uri(), response.status().as_u16()));
+            return Ok(response);
+        }
+        let response = route_request(req, providers, Arc::clone(&state), deadline, logger).await?;
+        return Ok(cache.store(key, ttl, response).await);
+    }
+    route_request(req, providers, state, deadline, logger).await
+}
+
+/// 按标签路由和影子配置选择提供商并转发请求
+async fn route_request(
+    req: Request<Body>,
+    providers: Arc<Vec<Provider>>,
+    state: Arc<ProxyState>,
+    deadline: Option<Instant>,
+    logger: Option<Arc<crate::ui::Logger>>,
+) -> Result<Response<Body>, Infallible> {
+    // 影子提供商不参与常规选择，只接收请求的镜像副本
+    let (providers, shadows) = split_shadow_providers(providers);
+    
+    // 命中标签路由时只在带有该标签的提供商中选择；未命中时如果配置了默认提供商，只在默认提供商中选择
+    let providers = match state.route_tag(req.uri().path()) {
+        Some(tag) => {
+            let tagged: Vec<Provider> = state.providers_with_tag(&providers, tag).into_iter().cloned().collect();
+            if tagged.is_empty() {
+                return Ok(Response::builder()
+                    .status(503)
+                    .body(Body::from(format!("No providers tagged '{}' for this route", tag)))
+                    .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))));
+            }
+            Arc::new(tagged)
+        }
+        None if providers.iter().any(|p| p.is_default) => {
+            Arc::new(state.default_providers(&providers).into_iter().cloned().collect())
+        }
+        None => providers,
+    };
+    
     let method = req.method().clone();
     let uri = req.uri().clone();
     let headers = req.headers().clone();
     
-    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
-        Ok(bytes) => bytes,
-        Err(_e) => {
+    let body_bytes = match read_request_body(req.into_body(), state.max_buffered_body).await {
+        Ok(IncomingBody::Buffered(bytes)) => bytes,
+        Ok(IncomingBody::Streaming(prefix, rest)) => {
+            // 请求体超过缓冲上限：只用已读取的前缀估算Token，剩余部分边读边转发
+            let analysis = state.token_estimator.estimate_request(&prefix, &uri, &headers);
+            return handle_streaming_request(&providers, &state, &method, &uri, &headers, prefix, rest, &analysis, deadline, logger).await;
+        }
+        Err(e) => {
+            let reason = body_read_error_reason(&e);
+            log_event(&state, &logger, LogLevel::Warning, format!("📭 {} {} 读取请求体失败，返回 400: {} ({})", method, uri, reason, e));
             return Ok(Response::builder()
                 .status(400)
-                .body(Body::from("Bad Request"))
+                .body(Body::from(format!("Bad Request: {}", reason)))
                 .unwrap_or_else(|_| Response::new(Body::from("Internal Error"))));
         }
     };
     
-    handle_load_balanced_request(&providers, &state, &method, &uri, &headers, &body_bytes, logger).await
+    // 只解析一次请求体，得到模型和估算的Token使用量
+    let analysis = state.token_estimator.estimate_request(&body_bytes, &uri, &headers);
+    
+    // 相同的并发幂等请求只由第一个请求转发，其余请求等待并复用它的响应；
+    // 指定提供商或携带粘性会话头的请求必须由对应的提供商处理，不参与合并
+    let coalesce = state.coalescer.as_ref()
+        .filter(|_| !headers.contains_key(PIN_PROVIDER_HEADER))
+        .filter(|_| !state.sticky_header.as_ref().is_some_and(|name| headers.contains_key(name)))
+        .and_then(|coalescer| coalescer.key(&method, &uri, &headers, &body_bytes).map(|key| (coalescer, key)));
+    if let Some((coalescer, key)) = coalesce {
+        let forward = || dispatch_buffered_request(&providers, &state, shadows, &method, &uri, &headers, &body_bytes, &analysis, deadline, logger.clone());
+        let (response, coalesced) = coalescer.run(key, forward).await?;
+        if coalesced {
+            log_event(&state, &logger, LogLevel::Debug, format!("🔗 {} {} 与进行中的相同请求合并，复用其响应 ({})", method, uri.path(), response.status().as_u16()));
+        }
+        return Ok(response);
+    }
+    dispatch_buffered_request(&providers, &state, shadows, &method, &uri, &headers, &body_bytes, &analysis, deadline, logger).await
 }
 
-/// 使用负载均衡算法处理请求
-async fn handle_load_balanced_request(
-    providers: &Arc<Vec<Provider>>, 
+/// 转发已完整缓冲的请求，有影子提供商时同时发送镜像请求
+#[allow(clippy::too_many_arguments)]
+async fn dispatch_buffered_request(
+    providers: &Arc<Vec<Provider>>,
     state: &Arc<ProxyState>,
+    shadows: Vec<Provider>,
     method: &hyper::Method,
     uri: &hyper::Uri,
     headers: &hyper::HeaderMap,
     body_bytes: &hyper::body::Bytes,
+    analysis: &RequestAnalysis,
+    deadline: Option<Instant>,
     logger: Option<Arc<crate::ui::Logger>>,
 ) -> Result<Response<Body>, Infallible> {
-    let provider_count = providers.len();
+    if shadows.is_empty() {
+        return handle_load_balanced_request(providers, state, method, uri, headers, body_bytes, analysis, deadline, logger).await;
+    }
     
-    if provider_count == 0 {
-        return Ok(Response::builder()
-            .status(503)
-            .header("Retry-After", "60")
-            .body(Body::from("No providers configured"))
-            .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))));
+    // 镜像请求与主请求并行发出，主请求结束后把结果交给镜像任务用于对比
+    let (primary_tx, primary_rx) = tokio::sync::watch::channel(None);
+    for shadow in shadows {
+        spawn_shadow_request(shadow, state, method, uri, headers, body_bytes, primary_rx.clone(), logger.clone());
     }
+    let started_at = Instant::now();
+    let result = handle_load_balanced_request(providers, state, method, uri, headers, body_bytes, analysis, deadline, logger).await;
+    if let Ok(response) = &result {
+        let _ = primary_tx.send(Some((response.status().as_u16(), started_at.elapsed().as_millis() as u64)));
+    }
+    result
+}
 
-    // 检查所有提供商是否被禁用
-    if state.all_providers_disabled(&providers) {
-        return Ok(Response::builder()
-            .status(503)
-            .header("Retry-After", "30")
-            .body(Body::from("All providers are disabled by user. Please enable at least one provider."))
-            .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))));
+/// 读取请求体的结果
+enum IncomingBody {
+    /// 请求体已完整读入内存，故障转移时可以重复发送
+    Buffered(hyper::body::Bytes),
+    /// 请求体超过缓冲上限：已读取的前缀和尚未读取的剩余部分
+    Streaming(hyper::body::Bytes, Body),
+}
+
+/// 读取请求体，超过 `limit` 字节时停止缓冲，把已读取的前缀和剩余的流交给调用方
+async fn read_request_body(mut body: Body, limit: Option<usize>) -> Result<IncomingBody, hyper::Error> {
+    use hyper::body::HttpBody;
+    
+    let Some(limit) = limit else {
+        return hyper::body::to_bytes(body).await.map(IncomingBody::Buffered);
+    };
+    let mut buffered = Vec::new();
+    while let Some(chunk) = body.data().await {
+        buffered.extend_from_slice(&chunk?);
+        if buffered.len() > limit {
+            return Ok(IncomingBody::Streaming(buffered.into(), body));
+        }
+    }
+    Ok(IncomingBody::Buffered(buffered.into()))
+}
+
+/// 读取客户端请求体失败的原因，区分读取超时和客户端提前断开连接
+fn body_read_error_reason(error: &hyper::Error) -> &'static str {
+    let io_kind = std::error::Error::source(error)
+        .and_then(|source| source.downcast_ref::<std::io::Error>())
+        .map(|io_error| io_error.kind());
+    if error.is_timeout() || io_kind == Some(std::io::ErrorKind::TimedOut) {
+        "timed out reading the request body"
+    } else if error.is_incomplete_message()
+        || error.is_body_write_aborted()
+        || error.is_canceled()
+        || error.is_closed()
+        || matches!(io_kind, Some(std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::UnexpectedEof))
+    {
+        "connection closed before the request body was complete"
+    } else {
+        "failed to read the request body"
+    }
+}
+
+/// 把影子提供商从常规提供商列表中分离出来（没有影子提供商时不复制列表）
+fn split_shadow_providers(providers: Arc<Vec<Provider>>) -> (Arc<Vec<Provider>>, Vec<Provider>) {
+    if !providers.iter().any(|p| p.shadow) {
+        return (providers, Vec::new());
+    }
+    let (shadows, primary): (Vec<Provider>, Vec<Provider>) = providers.iter().cloned().partition(|p| p.shadow);
+    (Arc::new(primary), shadows)
+}
+
+/// 异步向影子提供商发送请求的镜像副本，并在日志中与主请求的状态码和耗时对比
+///
+/// 请求体与主请求共享同一块缓冲区，不会再复制一份；镜像的响应体直接丢弃，
+/// 任何失败都只记录日志，不影响返回给客户端的响应。
+#[allow(clippy::too_many_arguments)]
+fn spawn_shadow_request(
+    shadow: Provider,
+    state: &Arc<ProxyState>,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    body_bytes: &hyper::body::Bytes,
+    mut primary_rx: tokio::sync::watch::Receiver<Option<(u16, u64)>>,
+    logger: Option<Arc<crate::ui::Logger>>,
+) {
+    if state.interactive_manager.is_provider_disabled(shadow.state_key())
+        || state.interactive_manager.is_provider_in_maintenance(shadow.state_key()) {
+        return;
+    }
+    let state = Arc::clone(state);
+    let method = method.clone();
+    let uri = uri.clone();
+    let headers = headers.clone();
+    let body_bytes = body_bytes.clone();
+    tokio::spawn(async move {
+        let started_at = Instant::now();
+        let result = try_provider(&shadow, &method, &uri, &headers, &body_bytes, &state).await;
+        let latency_ms = started_at.elapsed().as_millis() as u64;
+        let (mirror_code, mirror_text) = match &result {
+            Ok(response) => {
+                let status_code = response.status().as_u16();
+                (Some(status_code), format!("[{}] {}ms", status_code, latency_ms))
+            }
+            Err(ProxyError::RateLimited) => (None, "跳过（速率限制）".to_string()),
+            Err(e) => (e.status_code(), format!("[{}] {}ms", e, latency_ms)),
+        };
+        drop(result);
+        if let Some(status_code) = mirror_code {
+            state.record_status_code(shadow.state_key(), status_code);
+        }
+        
+        // 等待主请求结束（主请求被取消时发送端会被丢弃）
+        let primary = match primary_rx.wait_for(|outcome| outcome.is_some()).await {
+            Ok(outcome) => *outcome,
+            Err(_) => None,
+        };
+        let (level, primary_text) = match primary {
+            // 状态码一致时按普通日志输出，不一致时作为警告便于发现差异
+            Some((status, ms)) if mirror_code == Some(status) => (LogLevel::Info, format!("[{}] {}ms", status, ms)),
+            Some((status, ms)) => (LogLevel::Warning, format!("[{}] {}ms", status, ms)),
+            None => (LogLevel::Warning, "未完成".to_string()),
+        };
+        let shadow_msg = format!("🪞 镜像 {} {} → {} {} | 主请求 {}", method, uri, shadow.name, mirror_text, primary_text);
+        log_event(&state, &logger, level, shadow_msg);
+    });
+}
+
+/// 使用负载均衡算法处理请求
+#[allow(clippy::too_many_arguments)]
+async fn handle_load_balanced_request(
+    providers: &Arc<Vec<Provider>>, 
+    state: &Arc<ProxyState>,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    body_bytes: &hyper::body::Bytes,
+    analysis: &RequestAnalysis,
+    deadline: Option<Instant>,
+    logger: Option<Arc<crate::ui::Logger>>,
+) -> Result<Response<Body>, Infallible> {
+    let provider_count = providers.len();
+    
+    if provider_count == 0 {
+        return Ok(state.unavailable_responses.respond(UnavailableCondition::NoProviders, "No providers configured", 60));
+    }
+
+    // 通过请求头指定了提供商时绕过负载均衡
+    if let Some(pinned) = headers.get(PIN_PROVIDER_HEADER) {
+        let pinned_name = String::from_utf8_lossy(pinned.as_bytes()).trim().to_string();
+        return try_pinned_provider(providers, state, &pinned_name, method, uri, headers, body_bytes, analysis, deadline, logger).await;
+    }
+
+    // 请求指定了模型时只在支持该模型的提供商中选择
+    let model_providers;
+    let providers = match analysis.model.as_deref() {
+        Some(model) if providers.iter().any(|p| !p.supports_model(model)) => {
+            let supported: Vec<Provider> = providers.iter().filter(|p| p.supports_model(model)).cloned().collect();
+            if supported.is_empty() {
+                return Ok(model_not_supported(model, providers));
+            }
+            model_providers = Arc::new(supported);
+            &model_providers
+        }
+        _ => providers,
+    };
+    let provider_count = providers.len();
+
+    // 检查所有提供商是否被禁用
+    if state.all_providers_disabled(providers) {
+        return Ok(state.unavailable_responses.respond(
+            UnavailableCondition::Disabled,
+            "All providers are disabled by user. Please enable at least one provider.",
+            state.retry_after.unavailable,
+        ));
+    }
+    
+    // 剩余提供商都认证失败时，重试没有意义
+    if state.all_providers_unselectable(providers) {
+        return Ok(state.unavailable_responses.respond(
+            UnavailableCondition::Down,
+            "All enabled providers are in maintenance, failed authentication or cannot be resolved. Please check their tokens and base_url hosts.",
+            state.retry_after.down,
+        ));
+    }
+    
+    // 所有提供商都达到速率限制时，按配置等待空闲配额而不是立即返回503
+    if !state.queue_wait.is_zero() && !state.any_provider_can_request(providers) {
+        wait_for_request_slot(providers, state, method, uri, &logger).await;
     }
     
     // 检查是否需要紧急恢复
-    if state.all_providers_down(&providers) {
-        state.emergency_recovery_all(&providers);
+    if state.all_providers_down(providers) {
+        if !state.auto_recovery {
+            let down_msg = format!("⛔ {} {} 所有供应商健康度都为 0，自动恢复已关闭，直接返回 503", method, uri);
+            log_event(state, &logger, LogLevel::Warning, down_msg);
+            return Ok(state.unavailable_responses.respond(
+                UnavailableCondition::Down,
+                "Service unavailable - all providers are down and automatic recovery is disabled",
+                state.retry_after.down,
+            ));
+        }
+        state.emergency_recovery_all(providers);
     }
     
     // 快速失败检查：如果所有供应商都不健康且连续失败超过阈值
-    let all_unhealthy = state.all_providers_unhealthy(&providers);
+    let all_unhealthy = state.all_providers_unhealthy(providers);
+    if all_unhealthy && !state.emergency_mode {
+        let hard_fail_msg = format!("⛔ {} {} 所有供应商都不健康，紧急模式已关闭，直接返回 503", method, uri);
+        log_event(state, &logger, LogLevel::Warning, hard_fail_msg);
+        return Ok(state.unavailable_responses.respond(
+            UnavailableCondition::Down,
+            "Service temporarily unavailable - all providers are unhealthy and emergency mode is disabled",
+            state.retry_after.down,
+        ));
+    }
     if all_unhealthy {
         // 在紧急模式下只尝试1轮，每个供应商最多1次重试
-        return try_emergency_mode(&providers, &state, method, uri, headers, body_bytes, logger).await;
+        return try_emergency_mode(providers, state, method, uri, headers, body_bytes, analysis, deadline, logger).await;
     }
     
     // 优化模式：直接尝试每个提供商，失败立即转移，不重试
     // 先尝试轮询选择健康的提供商
+    // 会话粘滞：首次尝试使用会话对应的提供商，失败后按正常负载均衡转移
+    let mut sticky_index = state.sticky_header.as_ref()
+        .and_then(|name| headers.get(name))
+        .and_then(|value| state.select_sticky_provider(providers, value.as_bytes()));
+    
+    let mut failovers = 0u32;
+    let mut attempts = 0usize;
+    let mut tried: Vec<usize> = Vec::new();
+    let mut max_failover_reached = false;
     for _attempt in 0..provider_count {
-        if let Some(provider_index) = state.select_next_provider(&providers) {
+        // 达到 --max-failover 上限后不再尝试其他提供商，直接返回503
+        if state.max_failover.is_some_and(|max| attempts >= max) {
+            max_failover_reached = true;
+            break;
+        }
+        
+        if let Some(provider_index) = sticky_index.take().or_else(|| state.select_next_provider_excluding(providers, &tried)) {
             let provider = &providers[provider_index];
+            tried.push(provider_index);
             
             // 立即记录转发日志
             let forward_msg = format!("🔄 {} {} 转发至 {}", method, uri, provider.name);
-            if let Some(ref logger) = logger {
-                logger.info(forward_msg);
-            } else {
-                eprintln!("{}", forward_msg);
-            }
+            log_event(state, &logger, LogLevel::Info, forward_msg);
             
-            match try_provider(&provider, &method, &uri, &headers, &body_bytes, state).await {
-                Ok(response) => {
-                    let status = response.status();
-                    let status_code = status.as_u16();
-                    state.record_status_code(&provider.name, status_code);
-                    
-                    // 记录响应日志
-                    if status.is_success() {
-                        let success_msg = format!("✅ {} {} → {} [{}]", method, uri, provider.name, status_code);
-                        if let Some(ref logger) = logger {
-                            logger.success(success_msg);
-                        } else {
-                            eprintln!("{}", success_msg);
-                        }
-                        state.record_provider_success(&provider.name);
-                        
-                        // 估算Token使用量（根据请求的内容长度和基本固定成本）
-                        let estimated_tokens = TokenCalculator::estimate_usage(&body_bytes, &uri);
-                        state.record_token_usage(&provider.name, estimated_tokens);
-                        
-                        return Ok(response);
-                    } else {
-                        state.record_provider_failure(&provider.name);
+            // 开启 --retry-same-on-5xx 时，上游返回 5xx 先对同一提供商重试一次再转移；网络错误直接转移
+            let mut retried = false;
+            loop {
+                let started_at = Instant::now();
+                let result = within_deadline(deadline, try_provider(provider, method, uri, headers, body_bytes, state)).await;
+                // 因速率限制跳过的提供商和同一提供商的重试都不计入尝试次数
+                if !retried && !matches!(result, Err(ProxyError::RateLimited)) {
+                    attempts += 1;
+                }
+                match result {
+                    Ok(response) => {
+                        let status = response.status();
+                        let status_code = status.as_u16();
+                        state.record_status_code(provider.state_key(), status_code);
                         
-                        // 使用HTTP状态码标准描述
-                        let status_description = status.to_string();
-                        let error_msg = format!("❌ {} {} → {} [{}]", method, uri, provider.name, status_description);
-                        if let Some(ref logger) = logger {
-                            logger.warning(error_msg);
+                        // 记录响应日志
+                        if provider.is_success_status(status) {
+                            let success_msg = format!("✅ {} {} → {} [{}]", method, uri, provider.name, status_code);
+                            log_event(state, &logger, LogLevel::Success, success_msg);
+                            record_health(state, provider, true, &logger);
+                            
+                            // 记录估算的Token使用量（根据请求的内容长度和基本固定成本）
+                            state.record_token_usage(provider.state_key(), analysis);
+                            record_outcome(state, method, uri, provider, status_code, started_at, analysis.estimated_tokens);
+                            log_failover_summary(state, failovers, true, &logger);
+                            
+                            let response = served_by(response, provider, analysis.estimated_tokens);
+                            return Ok(expose_provider(state, response, provider, attempts));
+                        } else if is_auth_failure(status) {
+                            // token 无效或已过期，不计入健康度，直接停止选择该提供商
+                            state.mark_auth_failed(provider.state_key());
+                            record_outcome(state, method, uri, provider, status_code, started_at, 0);
+                            failovers += 1;
+                            log_auth_failure(state, method, uri, provider, status_code, &logger);
                         } else {
-                            eprintln!("{}", error_msg);
+                            let error_msg = format!("❌ {} {} → {} [{}]", method, uri, provider.name, ProxyError::UpstreamStatus(status_code));
+                            log_event(state, &logger, LogLevel::Warning, error_msg);
+                            
+                            // 重试前的失败不计入健康度和请求记录，同一提供商只按最后一次尝试的结果记录一次
+                            if state.retry_same_on_5xx && !retried && status.is_server_error() {
+                                retried = true;
+                                let retry_msg = format!("🔁 {} {} 重试 {}", method, uri, provider.name);
+                                log_event(state, &logger, LogLevel::Info, retry_msg);
+                                continue;
+                            }
+                            record_health(state, provider, false, &logger);
+                            record_outcome(state, method, uri, provider, status_code, started_at, 0);
+                            failovers += 1;
                         }
-                        
-                        // 如果这是最后一个提供商，返回错误响应；否则继续尝试下一个
-                        continue; // 立即尝试下一个提供商
                     }
-                }
-                Err(e) => {
-                    state.record_provider_failure(&provider.name);
-                    state.record_status_code(&provider.name, 0);
-                    let error_msg = format!("❌ {} {} → {} [网络错误: {}]", method, uri, provider.name, e);
-                    if let Some(ref logger) = logger {
-                        logger.error(error_msg);
-                    } else {
-                        eprintln!("{}", error_msg);
+                    Err(ProxyError::DeadlineExceeded) => {
+                        log_failover_summary(state, failovers, false, &logger);
+                        return Ok(deadline_exceeded(state, method, uri, provider, attempts, &logger));
+                    }
+                    Err(e) if !e.counts_as_health_failure() => {
+                        // 速率限制不是提供商的故障，不影响健康度和状态码
+                        let rate_msg = format!("⏳ {} {} 跳过 {} [{}]", method, uri, provider.name, e);
+                        log_event(state, &logger, LogLevel::Warning, rate_msg);
+                    }
+                    Err(e) => {
+                        record_error_health(state, method, uri, provider, &e, &logger);
+                        let status_code = e.status_code().unwrap_or(0);
+                        state.record_status_code(provider.state_key(), status_code);
+                        record_outcome(state, method, uri, provider, status_code, started_at, 0);
+                        failovers += 1;
+                        let error_msg = format!("❌ {} {} → {} [{}]", method, uri, provider.name, e);
+                        log_event(state, &logger, LogLevel::Error, error_msg);
                     }
-                    continue; // 立即尝试下一个提供商
                 }
+                // 立即尝试下一个提供商
+                break;
             }
         } else {
             // 没有更多健康的提供商可用
@@ -482,14 +2582,244 @@ async fn handle_load_balanced_request(
     }
     
     // 负载均衡失败
-    Ok(Response::builder()
-        .status(503)
-        .header("Retry-After", "30")
-        .body(Body::from("Service temporarily unavailable - all providers failed"))
-        .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))))
+    log_failover_summary(state, failovers, false, &logger);
+    let message = if max_failover_reached {
+        format!("Service temporarily unavailable - gave up after {} attempt(s) (max failover reached)", attempts)
+    } else {
+        format!("Service temporarily unavailable - all providers failed ({} attempt(s))", attempts)
+    };
+    Ok(state.unavailable_responses.respond(UnavailableCondition::Down, &message, state.retry_after.down))
+}
+
+/// 处理请求的提供商，附加在响应的扩展中供访问日志使用（不会发送给客户端）
+#[derive(Debug, Clone)]
+pub struct ServedBy {
+    pub provider: String,
+    /// 估算的Token使用量（失败的请求为 0）
+    pub tokens: u64,
+}
+
+/// 在上游响应的扩展中记录处理请求的提供商
+fn served_by(mut response: Response<Body>, provider: &Provider, tokens: u64) -> Response<Body> {
+    response.extensions_mut().insert(ServedBy { provider: provider.name.clone(), tokens });
+    response
+}
+
+/// 开启 `expose_provider_header` 时在成功响应上标注提供商名称和尝试次数
+///
+/// 名称无法作为请求头的值（如包含非 ASCII 字符）时只添加尝试次数。
+fn expose_provider(state: &ProxyState, mut response: Response<Body>, provider: &Provider, attempts: usize) -> Response<Body> {
+    if !state.expose_provider_header {
+        return response;
+    }
+    let headers = response.headers_mut();
+    if let Ok(name) = HeaderValue::from_str(&provider.name) {
+        headers.insert(SERVED_BY_HEADER, name);
+    }
+    headers.insert(ATTEMPTS_HEADER, HeaderValue::from(attempts));
+    response
+}
+
+/// 只转发到通过 `X-Proxy-Provider` 指定的提供商，不可用时直接返回 503，不回退到其他提供商
+#[allow(clippy::too_many_arguments)]
+async fn try_pinned_provider(
+    providers: &Arc<Vec<Provider>>,
+    state: &Arc<ProxyState>,
+    pinned_name: &str,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    body_bytes: &hyper::body::Bytes,
+    analysis: &RequestAnalysis,
+    deadline: Option<Instant>,
+    logger: Option<Arc<crate::ui::Logger>>,
+) -> Result<Response<Body>, Infallible> {
+    let unavailable = |reason: String| {
+        let msg = format!("📌 {} {} 指定的提供商 {} 不可用: {}", method, uri, pinned_name, reason);
+        log_event(state, &logger, LogLevel::Warning, msg);
+        Ok(Response::builder()
+            .status(503)
+            .body(Body::from(format!("Pinned provider '{}' is unavailable: {}", pinned_name, reason)))
+            .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))))
+    };
+    
+    let Some(provider) = providers.iter().find(|p| p.name == pinned_name) else {
+        return unavailable("not configured".to_string());
+    };
+    if state.interactive_manager.is_provider_disabled(provider.state_key()) {
+        return unavailable("disabled by user".to_string());
+    }
+    if let Some(remaining) = state.interactive_manager.maintenance_remaining(provider.state_key()) {
+        return unavailable(format!("in maintenance for another {}s", remaining.as_secs()));
+    }
+    if state.is_auth_failed(provider.state_key()) {
+        return unavailable("authentication failed".to_string());
+    }
+    // 冷却期内不再向无法解析的主机发起域名查询
+    if let Some(remaining) = state.dns_cooldown_remaining(provider.state_key()) {
+        return unavailable(format!("DNS resolution failed, retrying in {}s", remaining.as_secs()));
+    }
+    
+    let forward_msg = format!("📌 {} {} 指定转发至 {}", method, uri, provider.name);
+    log_event(state, &logger, LogLevel::Info, forward_msg);
+    
+    let started_at = Instant::now();
+    match within_deadline(deadline, try_provider(provider, method, uri, headers, body_bytes, state)).await {
+        // 指定提供商时原样返回上游响应，便于调试
+        Ok(response) => Ok(record_single_response(state, method, uri, provider, response, started_at, analysis, &logger)),
+        Err(ProxyError::RateLimited) => unavailable("rate limited".to_string()),
+        Err(ProxyError::DeadlineExceeded) => Ok(deadline_exceeded(state, method, uri, provider, 1, &logger)),
+        Err(e) => {
+            record_error_health(state, method, uri, provider, &e, &logger);
+            let status_code = e.status_code().unwrap_or(0);
+            state.record_status_code(provider.state_key(), status_code);
+            record_outcome(state, method, uri, provider, status_code, started_at, 0);
+            unavailable(e.to_string())
+        }
+    }
+}
+
+/// 记录只尝试一次的请求（指定提供商、流式转发）的上游响应，响应本身原样返回给客户端（只附加 `ServedBy` 扩展）
+#[allow(clippy::too_many_arguments)]
+fn record_single_response(
+    state: &ProxyState,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    provider: &Provider,
+    response: Response<Body>,
+    started_at: Instant,
+    analysis: &RequestAnalysis,
+    logger: &Option<Arc<crate::ui::Logger>>,
+) -> Response<Body> {
+    let status = response.status();
+    let status_code = status.as_u16();
+    state.record_status_code(provider.state_key(), status_code);
+    
+    if provider.is_success_status(status) {
+        let success_msg = format!("✅ {} {} → {} [{}]", method, uri, provider.name, status_code);
+        log_event(state, logger, LogLevel::Success, success_msg);
+        record_health(state, provider, true, logger);
+        state.record_token_usage(provider.state_key(), analysis);
+        record_outcome(state, method, uri, provider, status_code, started_at, analysis.estimated_tokens);
+        return served_by(response, provider, analysis.estimated_tokens);
+    } else if is_auth_failure(status) {
+        state.mark_auth_failed(provider.state_key());
+        record_outcome(state, method, uri, provider, status_code, started_at, 0);
+        log_auth_failure(state, method, uri, provider, status_code, logger);
+    } else {
+        record_health(state, provider, false, logger);
+        record_outcome(state, method, uri, provider, status_code, started_at, 0);
+        let error_msg = format!("❌ {} {} → {} [{}]", method, uri, provider.name, ProxyError::UpstreamStatus(status_code));
+        log_event(state, logger, LogLevel::Warning, error_msg);
+    }
+    served_by(response, provider, 0)
+}
+
+/// 流式转发超过缓冲上限的请求
+///
+/// 请求体边读边发，无法重放，因此只选择一个提供商尝试一次：不做故障转移、不重试、不跟随重定向，
+/// 也不发送影子镜像；上游失败时直接返回 502。
+#[allow(clippy::too_many_arguments)]
+async fn handle_streaming_request(
+    providers: &Arc<Vec<Provider>>,
+    state: &Arc<ProxyState>,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    prefix: hyper::body::Bytes,
+    rest: Body,
+    analysis: &RequestAnalysis,
+    deadline: Option<Instant>,
+    logger: Option<Arc<crate::ui::Logger>>,
+) -> Result<Response<Body>, Infallible> {
+    let unavailable = |reason: &str| {
+        let msg = format!("🌊 {} {} 流式请求没有可用的提供商: {}", method, uri, reason);
+        log_event(state, &logger, LogLevel::Warning, msg);
+        Ok(Response::builder()
+            .status(503)
+            .header("Retry-After", state.retry_after.unavailable)
+            .body(Body::from(format!("No provider available for streamed request: {}", reason)))
+            .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))))
+    };
+    
+    // 与常规请求一样支持指定提供商和按模型过滤，但只选择一次
+    let candidates: Vec<Provider> = match headers.get(PIN_PROVIDER_HEADER) {
+        Some(pinned) => {
+            let pinned_name = String::from_utf8_lossy(pinned.as_bytes()).trim().to_string();
+            providers.iter().filter(|p| p.name == pinned_name).cloned().collect()
+        }
+        None => providers.iter()
+            .filter(|p| analysis.model.as_deref().is_none_or(|model| p.supports_model(model)))
+            .cloned()
+            .collect(),
+    };
+    let Some(index) = state.select_next_provider_excluding(&candidates, &[]) else {
+        return unavailable("all providers are disabled, unhealthy or rate limited");
+    };
+    let provider = &candidates[index];
+    
+    let forward_msg = format!("🌊 {} {} 请求体超过 {} 字节，流式转发至 {}（不做故障转移）",
+        method, uri, state.max_buffered_body.unwrap_or(0), provider.name);
+    log_event(state, &logger, LogLevel::Info, forward_msg);
+    
+    let started_at = Instant::now();
+    use futures::StreamExt;
+    let body = Body::wrap_stream(futures::stream::once(async move { Ok::<_, hyper::Error>(prefix) }).chain(rest));
+    match within_deadline(deadline, try_provider_streaming(provider, method, uri, headers, body, state)).await {
+        Ok(response) => Ok(record_single_response(state, method, uri, provider, response, started_at, analysis, &logger)),
+        Err(ProxyError::RateLimited) => unavailable("rate limited"),
+        Err(ProxyError::DeadlineExceeded) => Ok(deadline_exceeded(state, method, uri, provider, 1, &logger)),
+        Err(e) => {
+            record_error_health(state, method, uri, provider, &e, &logger);
+            let status_code = e.status_code().unwrap_or(0);
+            state.record_status_code(provider.state_key(), status_code);
+            record_outcome(state, method, uri, provider, status_code, started_at, 0);
+            let error_msg = format!("❌ {} {} → {} [{}]", method, uri, provider.name, e);
+            log_event(state, &logger, LogLevel::Error, error_msg);
+            Ok(Response::builder()
+                .status(502)
+                .body(Body::from(format!("Streamed request to '{}' failed: {}", provider.name, e)))
+                .unwrap_or_else(|_| Response::new(Body::from("Bad Gateway"))))
+        }
+    }
+}
+
+/// 在请求截止时间内等待上游响应，截止时间已过或等待超时时返回 `ProxyError::DeadlineExceeded`
+///
+/// 只限制到收到响应头为止，响应体仍按上游的速度流式转发给客户端。
+async fn within_deadline(
+    deadline: Option<Instant>,
+    attempt: impl std::future::Future<Output = Result<Response<Body>, ProxyError>>,
+) -> Result<Response<Body>, ProxyError> {
+    let Some(deadline) = deadline else {
+        return attempt.await;
+    };
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(ProxyError::DeadlineExceeded);
+    }
+    tokio::time::timeout(remaining, attempt).await.unwrap_or(Err(ProxyError::DeadlineExceeded))
+}
+
+/// 请求总时限已用完时返回 504（不计入提供商的健康度）
+fn deadline_exceeded(
+    state: &ProxyState,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    provider: &Provider,
+    attempts: usize,
+    logger: &Option<Arc<crate::ui::Logger>>,
+) -> Response<Body> {
+    let deadline_msg = format!("⌛ {} {} 等待 {} 时请求总时限已用完，返回 504", method, uri, provider.name);
+    log_event(state, logger, LogLevel::Warning, deadline_msg);
+    Response::builder()
+        .status(504)
+        .body(Body::from(format!("Gateway Timeout - request deadline exceeded after {} attempt(s)", attempts)))
+        .unwrap_or_else(|_| Response::new(Body::from("Gateway Timeout")))
 }
 
 /// 紧急模式处理：所有供应商都不健康时
+#[allow(clippy::too_many_arguments)]
 async fn try_emergency_mode(
     providers: &Arc<Vec<Provider>>, 
     state: &Arc<ProxyState>,
@@ -497,79 +2827,390 @@ async fn try_emergency_mode(
     uri: &hyper::Uri,
     headers: &hyper::HeaderMap,
     body_bytes: &hyper::body::Bytes,
+    analysis: &RequestAnalysis,
+    deadline: Option<Instant>,
     logger: Option<Arc<crate::ui::Logger>>,
 ) -> Result<Response<Body>, Infallible> {
     
-    // 在紧急模式下，给每个供应商一次机会，但跳过被禁用的供应商
-    for (_index, provider) in providers.iter().enumerate() {
-        // 检查是否被禁用 - 即使在紧急模式下也要跳过被禁用的供应商
-        if state.interactive_manager.is_provider_disabled(&provider.name) {
+    // 在紧急模式下，按健康度从高到低给每个供应商一次机会（已跳过被禁用和冷却中的供应商）
+    let mut failovers = 0u32;
+    let mut attempted = 0usize;
+    for provider_index in state.emergency_candidates(providers) {
+        if state.max_failover.is_some_and(|max| attempted >= max) {
+            break;
+        }
+        let provider = &providers[provider_index];
+        
+        // 所有供应商都在冷却时仍会进入候选列表，此时不发请求，也不计入健康度失败
+        if !state.rate_limit_allows(provider.state_key()) {
+            let rate_msg = format!("⏳ 紧急模式 {} {} 跳过 {} [速率限制]", method, uri, provider.name);
+            log_event(state, &logger, LogLevel::Warning, rate_msg);
             continue;
         }
+        attempted += 1;
         
         // 立即记录紧急模式转发日志
         let emergency_msg = format!("🚨 紧急模式 {} {} 转发至 {}", method, uri, provider.name);
-        if let Some(ref logger) = logger {
-            logger.warning(emergency_msg);
-        } else {
-            eprintln!("{}", emergency_msg);
-        }
+        log_event(state, &logger, LogLevel::Warning, emergency_msg);
         
-        match try_provider(&provider, &method, &uri, &headers, &body_bytes, state).await {
+        let started_at = Instant::now();
+        match within_deadline(deadline, try_provider(provider, method, uri, headers, body_bytes, state)).await {
             Ok(response) => {
                 let status = response.status();
                 let status_code = status.as_u16();
-                state.record_status_code(&provider.name, status_code);
+                state.record_status_code(provider.state_key(), status_code);
                 
                 // 记录响应日志
-                if status.is_success() {
+                if provider.is_success_status(status) {
                     let success_msg = format!("✅ 紧急模式 {} {} → {} [{}]", method, uri, provider.name, status_code);
-                    if let Some(ref logger) = logger {
-                        logger.success(success_msg);
-                    } else {
-                        eprintln!("{}", success_msg);
-                    }
-                    state.record_provider_success(&provider.name);
+                    log_event(state, &logger, LogLevel::Success, success_msg);
+                    record_health(state, provider, true, &logger);
                     
-                    // 估算Token使用量
-                    let estimated_tokens = TokenCalculator::estimate_usage(&body_bytes, &uri);
-                    state.record_token_usage(&provider.name, estimated_tokens);
+                    // 记录估算的Token使用量
+                    state.record_token_usage(provider.state_key(), analysis);
+                    record_outcome(state, method, uri, provider, status_code, started_at, analysis.estimated_tokens);
+                    log_failover_summary(state, failovers, true, &logger);
                     
-                    return Ok(response);
+                    let response = served_by(response, provider, analysis.estimated_tokens);
+                    return Ok(expose_provider(state, response, provider, attempted));
+                } else if is_auth_failure(status) {
+                    state.mark_auth_failed(provider.state_key());
+                    record_outcome(state, method, uri, provider, status_code, started_at, 0);
+                    failovers += 1;
+                    log_auth_failure(state, method, uri, provider, status_code, &logger);
                 } else {
-                    state.record_provider_failure(&provider.name);
+                    record_health(state, provider, false, &logger);
+                    record_outcome(state, method, uri, provider, status_code, started_at, 0);
+                    failovers += 1;
                     
-                    // 使用HTTP状态码标准描述
-                    let status_description = status.to_string();
-                    let error_msg = format!("❌ 紧急模式 {} {} → {} [{}]", method, uri, provider.name, status_description);
-                    if let Some(ref logger) = logger {
-                        logger.error(error_msg);
-                    } else {
-                        eprintln!("{}", error_msg);
-                    }
+                    let error_msg = format!("❌ 紧急模式 {} {} → {} [{}]", method, uri, provider.name, ProxyError::UpstreamStatus(status_code));
+                    log_event(state, &logger, LogLevel::Error, error_msg);
                 }
             }
+            Err(ProxyError::DeadlineExceeded) => {
+                log_failover_summary(state, failovers, false, &logger);
+                return Ok(deadline_exceeded(state, method, uri, provider, attempted, &logger));
+            }
+            Err(e) if !e.counts_as_health_failure() => {
+                // 检查与发送之间被其他请求占满了速率配额，同样不计入健康度
+                let rate_msg = format!("⏳ 紧急模式 {} {} 跳过 {} [{}]", method, uri, provider.name, e);
+                log_event(state, &logger, LogLevel::Warning, rate_msg);
+            }
             Err(e) => {
-                state.record_provider_failure(&provider.name);
-                state.record_status_code(&provider.name, 0);
-                let error_msg = format!("❌ 紧急模式 {} {} → {} [网络错误: {}]", method, uri, provider.name, e);
-                if let Some(ref logger) = logger {
-                    logger.error(error_msg);
-                } else {
-                    eprintln!("{}", error_msg);
-                }
+                record_error_health(state, method, uri, provider, &e, &logger);
+                let status_code = e.status_code().unwrap_or(0);
+                state.record_status_code(provider.state_key(), status_code);
+                record_outcome(state, method, uri, provider, status_code, started_at, 0);
+                failovers += 1;
+                let error_msg = format!("❌ 紧急模式 {} {} → {} [{}]", method, uri, provider.name, e);
+                log_event(state, &logger, LogLevel::Error, error_msg);
             }
         }
     }
     
     // 紧急模式也失败了
-    Ok(Response::builder()
-        .status(503)
-        .header("Retry-After", "120") // 建议2分钟后重试
-        .body(Body::from("Service unavailable - all providers are down. Please try again in 2 minutes."))
-        .unwrap_or_else(|_| Response::new(Body::from("Emergency mode failed"))))
+    log_failover_summary(state, failovers, false, &logger);
+    if attempted == 0 {
+        // 所有可用供应商都处于速率限制冷却中
+        return Ok(Response::builder()
+            .status(503)
+            .header("Retry-After", state.retry_after.rate_limited)
+            .body(Body::from("Service unavailable - all providers are rate limited. Please try again later."))
+            .unwrap_or_else(|_| Response::new(Body::from("Service Unavailable"))));
+    }
+    let message = format!(
+        "Service unavailable - all providers are down ({} attempt(s)). Please try again in {} seconds.",
+        attempted, state.retry_after.down
+    );
+    Ok(state.unavailable_responses.respond(UnavailableCondition::Down, &message, state.retry_after.down))
+}
+
+/// 轮询等待任一提供商的速率配额空闲，最多等待 `state.queue_wait`
+///
+/// 使用异步休眠，等待期间不持有任何锁；超时后由调用方按原流程处理（通常返回503）。
+async fn wait_for_request_slot(
+    providers: &[Provider],
+    state: &ProxyState,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    logger: &Option<Arc<crate::ui::Logger>>,
+) {
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+    
+    let queue_msg = format!("⏳ {} {} 所有提供商都达到速率限制，最多等待 {}ms", method, uri, state.queue_wait.as_millis());
+    log_event(state, logger, LogLevel::Info, queue_msg);
+    
+    let deadline = Instant::now() + state.queue_wait;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            let timeout_msg = format!("⏳ {} {} 等待速率配额超时", method, uri);
+            log_event(state, logger, LogLevel::Warning, timeout_msg);
+            return;
+        }
+        tokio::time::sleep(POLL_INTERVAL.min(deadline - now)).await;
+        if state.any_provider_can_request(providers) {
+            return;
+        }
+    }
 }
 
+/// 没有任何提供商支持请求的模型时返回 404，并列出可用的模型
+fn model_not_supported(model: &str, providers: &[Provider]) -> Response<Body> {
+    let mut available: Vec<&str> = providers.iter()
+        .filter_map(|p| p.models.as_ref())
+        .flatten()
+        .map(|m| m.as_str())
+        .collect();
+    available.sort();
+    available.dedup();
+    let body = serde_json::json!({
+        "error": format!("Model '{}' is not supported by any configured provider", model),
+        "available_models": available,
+    });
+    Response::builder()
+        .status(404)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_else(|_| Response::new(Body::from("Not Found")))
+}
+
+/// 上游返回 401/403 表示 token 无效或已过期
+fn is_auth_failure(status: hyper::StatusCode) -> bool {
+    status == hyper::StatusCode::UNAUTHORIZED || status == hyper::StatusCode::FORBIDDEN
+}
+
+/// 记录认证失败日志
+fn log_auth_failure(state: &ProxyState, method: &hyper::Method, uri: &hyper::Uri, provider: &Provider, status_code: u16, logger: &Option<Arc<crate::ui::Logger>>) {
+    let auth_msg = format!("🔑 {} {} → {} [{}] 认证失败，已停止选择该提供商（检查token后重载配置或在界面中重新启用）",
+        method, uri, provider.name, ProxyError::UpstreamStatus(status_code));
+    log_event(state, logger, LogLevel::Error, auth_msg);
+}
+
+/// 记录请求错误对提供商状态的影响：域名解析失败进入冷却期，其他错误计入健康度失败
+fn record_error_health(state: &ProxyState, method: &hyper::Method, uri: &hyper::Uri, provider: &Provider, error: &ProxyError, logger: &Option<Arc<crate::ui::Logger>>) {
+    if !matches!(error, ProxyError::Dns(_)) {
+        record_health(state, provider, false, logger);
+        return;
+    }
+    if !state.mark_dns_failed(provider.state_key()) {
+        let dns_msg = format!("🌐 {} {} → {} 域名解析失败，{} 秒内不再选择该提供商（健康巡检会重新解析，成功后提前恢复）",
+            method, uri, provider.name, state.dns_cooldown.as_secs());
+        log_event(state, logger, LogLevel::Error, dns_msg);
+    }
+}
+
+/// 重新解析处于域名解析失败状态的提供商主机名，解析成功时恢复选择，失败时延长冷却期
+pub async fn probe_dns_failures(state: &ProxyState, providers: &[Provider], logger: &Option<Arc<crate::ui::Logger>>) {
+    let failed: Vec<&Provider> = {
+        let failures = ProxyState::safe_mutex_lock(&state.dns_failures);
+        providers.iter().filter(|p| failures.contains_key(p.state_key())).collect()
+    };
+    for provider in failed {
+        let Ok(uri) = provider.base_url.parse::<hyper::Uri>() else {
+            continue;
+        };
+        let Some(host) = uri.host() else {
+            continue;
+        };
+        let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+        let resolved = match tokio::time::timeout(DNS_PROBE_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+            Ok(Ok(mut addrs)) => addrs.next().is_some(),
+            _ => false,
+        };
+        if resolved {
+            if state.clear_dns_failed(provider.state_key()) {
+                log_event(state, logger, LogLevel::Success, format!("🌐 {} 域名解析已恢复，重新参与选择", provider.name));
+            }
+        } else {
+            // 仍无法解析时重新开始计算冷却期，避免冷却期结束后请求又发往无法解析的主机
+            if let Some(failed_at) = ProxyState::safe_mutex_lock(&state.dns_failures).get_mut(provider.state_key()) {
+                *failed_at = Instant::now();
+            }
+        }
+    }
+}
+
+/// 主动探测单个提供商，返回上游状态码
+///
+/// 配置了 `health_check_path` 时向 `base_url + health_check_path` 发送 GET，否则向 `base_url` 发送 HEAD；
+/// 探测携带提供商的认证信息，但不占用速率限制配额。
+pub async fn health_check_provider(provider: &Provider, state: &ProxyState) -> Result<u16, ProxyError> {
+    let (method, path) = match &provider.health_check_path {
+        Some(path) => (hyper::Method::GET, path.as_str()),
+        None => (hyper::Method::HEAD, ""),
+    };
+    let target_uri = format!("{}{}", provider.base_url, path);
+    let target_uri: hyper::Uri = target_uri.parse()
+        .map_err(|e| ProxyError::InvalidRequest(format!("目标地址 {} 无效: {}", target_uri, e)))?;
+    
+    let https = build_https_connector(provider.insecure_skip_verify, state.http2_enabled, state.connect_timeout);
+    let client = Client::builder().build::<_, hyper::Body>(https);
+    let request = build_upstream_request(provider, &method, &target_uri, &hyper::HeaderMap::new(), Body::empty(), state, true)?;
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, client.request(request)).await {
+        Ok(result) => result.map(|response| response.status().as_u16()).map_err(ProxyError::from_hyper),
+        Err(_) => Err(ProxyError::Timeout),
+    }
+}
+
+/// 并发地对所有未被禁用且不在维护中的提供商进行一次主动健康检查，结果与真实请求一样计入健康度
+pub async fn run_health_checks(state: &ProxyState, providers: &[Provider], logger: &Option<Arc<crate::ui::Logger>>) {
+    let checks = providers.iter()
+        .filter(|p| !state.interactive_manager.is_provider_disabled(p.state_key()))
+        .filter(|p| !state.interactive_manager.is_provider_in_maintenance(p.state_key()))
+        .map(|provider| async move { (provider, health_check_provider(provider, state).await) });
+    for (provider, result) in futures::future::join_all(checks).await {
+        let healthy = matches!(result, Ok(status) if provider.is_health_check_success(status));
+        record_health(state, provider, healthy, logger);
+        if !healthy {
+            let reason = match result {
+                Ok(status) => format!("[{}]", status),
+                Err(e) => format!("[{}]", e),
+            };
+            log_event(state, logger, LogLevel::Warning, format!("🩺 {} 健康检查失败 {}", provider.name, reason));
+        }
+    }
+}
+
+/// 常规池中的提供商（不含金丝雀）；全部为金丝雀时返回所有提供商
+fn pool_providers(providers: &[Provider]) -> Vec<&Provider> {
+    let pool: Vec<&Provider> = providers.iter().filter(|p| !p.is_canary()).collect();
+    if pool.is_empty() {
+        providers.iter().collect()
+    } else {
+        pool
+    }
+}
+
+/// 统计本次请求的故障转移次数，并在发生转移时输出汇总日志
+fn log_failover_summary(state: &ProxyState, failovers: u32, succeeded: bool, logger: &Option<Arc<crate::ui::Logger>>) {
+    state.record_failovers(failovers, succeeded);
+    if failovers == 0 {
+        return;
+    }
+    
+    let summary_msg = if succeeded {
+        format!("🔀 请求经过 {} 次转移后成功", failovers)
+    } else {
+        format!("🔀 请求尝试 {} 个提供商后全部失败", failovers)
+    };
+    let level = if succeeded { LogLevel::Info } else { LogLevel::Warning };
+    log_event(state, logger, level, summary_msg);
+}
+
+/// 按日志级别阈值输出请求日志：有界面时写入界面日志，否则输出到标准错误
+pub(crate) fn log_event(state: &ProxyState, logger: &Option<Arc<crate::ui::Logger>>, level: LogLevel, message: String) {
+    if !state.log_level.allows(&level) {
+        return;
+    }
+    // 同时作为 tracing 事件输出，导出链路时附加在当前请求的 span 上
+    match level {
+        LogLevel::Error => tracing::error!("{}", message),
+        LogLevel::Warning => tracing::warn!("{}", message),
+        LogLevel::Debug => tracing::debug!("{}", message),
+        LogLevel::Info | LogLevel::Success => tracing::info!("{}", message),
+    }
+    match logger {
+        Some(logger) => logger.log(level, message),
+        None => eprintln!("{}", message),
+    }
+}
+
+/// 记录提供商请求成功或失败，健康状态发生变化时输出一次日志
+fn record_health(state: &ProxyState, provider: &Provider, success: bool, logger: &Option<Arc<crate::ui::Logger>>) {
+    if success {
+        state.record_provider_success(provider.state_key());
+    } else {
+        state.record_provider_failure(provider.state_key());
+    }
+    log_health_transition(state, provider, logger);
+}
+
+/// 检查单个提供商的健康状态变化并输出日志
+fn log_health_transition(state: &ProxyState, provider: &Provider, logger: &Option<Arc<crate::ui::Logger>>) {
+    match state.health_transition(provider.state_key()) {
+        Some(true) => log_event(state, logger, LogLevel::Success, format!("💚 提供商 {} 已恢复健康", provider.name)),
+        Some(false) => log_event(state, logger, LogLevel::Warning, format!("💔 提供商 {} 变为不健康", provider.name)),
+        None => {}
+    }
+}
+
+/// 巡检所有提供商的健康状态变化（空闲恢复、紧急恢复等不经过请求的变化也能被发现）
+///
+/// 每次状态变化只输出一次日志，与请求路径上的检测共用同一份状态记录。
+pub fn check_health_transitions(state: &ProxyState, providers: &[Provider], logger: &Option<Arc<crate::ui::Logger>>) {
+    for provider in providers {
+        log_health_transition(state, provider, logger);
+    }
+}
+
+/// 将一次转发结果写入请求记录
+fn record_outcome(
+    state: &ProxyState,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    provider: &Provider,
+    status: u16,
+    started_at: Instant,
+    tokens: u64,
+) {
+    state.record_request_outcome(RequestRecord {
+        timestamp: Local::now(),
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        provider: provider.name.clone(),
+        provider_id: provider.id.clone(),
+        status,
+        latency_ms: started_at.elapsed().as_millis() as u64,
+        tokens,
+    });
+}
+
+/// 自检时发送给每个提供商的请求
+#[derive(Debug, Clone)]
+pub struct SelfTestRequest {
+    pub method: hyper::Method,
+    /// 请求路径（可带查询字符串），拼接在提供商的 base_url 之后
+    pub path: String,
+    /// JSON 请求体
+    pub body: String,
+    /// 单个提供商的超时时间
+    pub timeout: Duration,
+}
+
+/// 单个提供商的自检结果
+#[derive(Debug)]
+pub struct SelfTestResult {
+    /// 上游状态码，请求失败时为错误原因
+    pub outcome: Result<u16, ProxyError>,
+    /// 状态码是否视为成功（见 `Provider::is_success_status`）
+    pub success: bool,
+    pub latency_ms: u64,
+}
+
+/// 向单个提供商发送自检请求，不经过负载均衡，也不影响健康度等统计
+pub async fn self_test_provider(provider: &Provider, state: &Arc<ProxyState>, request: &SelfTestRequest) -> SelfTestResult {
+    let started_at = Instant::now();
+    let outcome = match request.path.parse::<hyper::Uri>() {
+        Ok(uri) => {
+            let mut headers = hyper::HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            let body = hyper::body::Bytes::from(request.body.clone());
+            match tokio::time::timeout(request.timeout, try_provider(provider, &request.method, &uri, &headers, &body, state)).await {
+                Ok(result) => result.map(|response| response.status()),
+                Err(_) => Err(ProxyError::Timeout),
+            }
+        }
+        Err(e) => Err(ProxyError::InvalidRequest(format!("路径 {} 无效: {}", request.path, e))),
+    };
+    SelfTestResult {
+        success: outcome.as_ref().is_ok_and(|status| provider.is_success_status(*status)),
+        outcome: outcome.map(|status| status.as_u16()),
+        latency_ms: started_at.elapsed().as_millis() as u64,
+    }
+}
+
+#[tracing::instrument(name = "upstream_attempt", skip_all, fields(provider = %provider.name, http.status_code = tracing::field::Empty), err(level = "warn", Display))]
 async fn try_provider(
     provider: &Provider,
     method: &hyper::Method,
@@ -577,33 +3218,134 @@ async fn try_provider(
     headers: &hyper::HeaderMap,
     body_bytes: &hyper::body::Bytes,
     state: &Arc<ProxyState>,
-) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    // 检查速率限制
-    if !state.can_request(&provider.name) {
-        return Err("Rate limit exceeded".into());
+) -> Result<Response<Body>, ProxyError> {
+    // 检查速率限制（软限制下只影响选择顺序，不阻止请求）
+    if !state.rate_limit_allows(provider.state_key()) {
+        return Err(ProxyError::RateLimited);
     }
     
     // 记录请求
-    state.record_request(&provider.name);
+    state.record_request(provider.state_key());
     
-    let https = HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_or_http()
-        .enable_http1()
-        .build();
+    let https = build_https_connector(provider.insecure_skip_verify, state.http2_enabled, state.connect_timeout);
     let client = Client::builder().build::<_, hyper::Body>(https);
     
-    let target_uri = format!("{}{}", provider.base_url, uri.path_and_query().map(|x| x.as_str()).unwrap_or("/"));
-    let target_uri: hyper::Uri = target_uri.parse()?;
+    let target_uri = upstream_target_uri(provider, uri)?;
+    let new_req = build_upstream_request(provider, method, &target_uri, headers, Body::from(body_bytes.clone()), state, true)?;
+    let mut response = client.request(new_req).await.map_err(ProxyError::from_hyper)?;
+    
+    // 按 --follow-redirects 在代理中跟随重定向，重定向到其他主机时不携带认证信息
+    let mut current_uri = target_uri.clone();
+    let mut current_method = method.clone();
+    let mut current_body = body_bytes.clone();
+    let mut visited = vec![current_uri.to_string()];
+    for _ in 0..state.follow_redirects {
+        let status = response.status();
+        let Some(next_uri) = redirect_target(&response, &current_uri) else {
+            break;
+        };
+        if visited.contains(&next_uri.to_string()) {
+            return Err(ProxyError::Redirect(status.as_u16(), format!("重定向循环 {}", next_uri)));
+        }
+        visited.push(next_uri.to_string());
+        
+        // 303 以及非 GET/HEAD 请求的 301/302 按浏览器惯例改为不带请求体的 GET
+        let becomes_get = status == hyper::StatusCode::SEE_OTHER
+            || (matches!(status, hyper::StatusCode::MOVED_PERMANENTLY | hyper::StatusCode::FOUND)
+                && current_method != hyper::Method::GET && current_method != hyper::Method::HEAD);
+        if becomes_get {
+            current_method = hyper::Method::GET;
+            current_body = hyper::body::Bytes::new();
+        }
+        
+        let same_origin = is_same_origin(&target_uri, &next_uri);
+        let next_req = build_upstream_request(provider, &current_method, &next_uri, headers, Body::from(current_body.clone()), state, same_origin)?;
+        current_uri = next_uri;
+        response = client.request(next_req).await.map_err(ProxyError::from_hyper)?;
+    }
+    if state.follow_redirects > 0 && redirect_target(&response, &current_uri).is_some() {
+        return Err(ProxyError::Redirect(
+            response.status().as_u16(),
+            format!("重定向次数超过 {} 次", state.follow_redirects),
+        ));
+    }
+    tracing::Span::current().record("http.status_code", response.status().as_u16());
+    
+    match &state.error_pointer {
+        Some(pointer) if should_inspect_body(provider, &response) => check_response_body(response, pointer).await,
+        // 响应体（包括 gzip/deflate 等压缩编码）原样转发给客户端，不在代理中解码
+        _ => Ok(response),
+    }
+}
+
+/// 以流的形式向提供商发送一次请求：请求体只能读取一次，因此不跟随重定向
+#[tracing::instrument(name = "upstream_attempt", skip_all, fields(provider = %provider.name, http.status_code = tracing::field::Empty), err(level = "warn", Display))]
+async fn try_provider_streaming(
+    provider: &Provider,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    body: Body,
+    state: &Arc<ProxyState>,
+) -> Result<Response<Body>, ProxyError> {
+    if !state.rate_limit_allows(provider.state_key()) {
+        return Err(ProxyError::RateLimited);
+    }
+    state.record_request(provider.state_key());
+    
+    let https = build_https_connector(provider.insecure_skip_verify, state.http2_enabled, state.connect_timeout);
+    let client = Client::builder().build::<_, hyper::Body>(https);
+    
+    let target_uri = upstream_target_uri(provider, uri)?;
+    let mut new_req = build_upstream_request(provider, method, &target_uri, headers, body, state, true)?;
+    // 流式请求体的长度未知，沿用客户端声明的 Content-Length，避免改为分块传输
+    if let Some(length) = headers.get(CONTENT_LENGTH) {
+        new_req.headers_mut().insert(CONTENT_LENGTH, length.clone());
+    }
+    let response = client.request(new_req).await.map_err(ProxyError::from_hyper)?;
+    tracing::Span::current().record("http.status_code", response.status().as_u16());
     
+    match &state.error_pointer {
+        Some(pointer) if should_inspect_body(provider, &response) => check_response_body(response, pointer).await,
+        _ => Ok(response),
+    }
+}
+
+/// 把客户端请求的路径和查询参数拼接到提供商的 base_url 之后
+fn upstream_target_uri(provider: &Provider, uri: &hyper::Uri) -> Result<hyper::Uri, ProxyError> {
+    let target_uri = format!("{}{}", provider.base_url, uri.path_and_query().map(|x| x.as_str()).unwrap_or("/"));
+    target_uri.parse()
+        .map_err(|e| ProxyError::InvalidRequest(format!("目标地址 {} 无效: {}", target_uri, e)))
+}
+
+/// 构建发往上游的请求
+///
+/// `with_auth` 为 false 时（重定向到其他主机）不携带提供商 token，同时去掉客户端的 Cookie 等凭据。
+fn build_upstream_request(
+    provider: &Provider,
+    method: &hyper::Method,
+    target_uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    body: Body,
+    state: &ProxyState,
+    with_auth: bool,
+) -> Result<Request<Body>, ProxyError> {
     let mut new_req = Request::builder()
         .method(method)
-        .uri(&target_uri);
+        .uri(target_uri);
     
-    // 复制原始请求头，只跳过需要重新设置的关键头部
+    // 复制原始请求头，只跳过需要重新设置的关键头部；Accept-Encoding 原样转发，
+    // 代理不会自行添加，保证上游只在客户端要求时才压缩响应
     for (name, value) in headers {
         let name_lower = name.as_str().to_lowercase();
-        if name_lower == "host" || name_lower == "authorization" {
+        if name_lower == "authorization" || name_lower == PIN_PROVIDER_HEADER || name_lower == DEADLINE_HEADER || (name_lower == "host" && !state.preserve_host) {
+            continue;
+        }
+        if !with_auth && (name == COOKIE || name == PROXY_AUTHORIZATION || name == HOST) {
+            continue;
+        }
+        // 请求体改变（重定向改为 GET）时由 hyper 重新计算长度
+        if name == CONTENT_LENGTH {
             continue;
         }
         new_req = new_req.header(name, value);
@@ -611,20 +3353,102 @@ async fn try_provider(
     
     // 设置新的Authorization和Host头
     
-    new_req = new_req.header(AUTHORIZATION, format!("Bearer {}", provider.token));
+    if with_auth {
+        new_req = new_req.header(AUTHORIZATION, provider.authorization_value());
+    }
     
-    if let Some(host) = target_uri.host() {
+    // 开启 preserve_host 且客户端带有 Host 时保留原值，否则改写为目标主机
+    let keep_client_host = with_auth && state.preserve_host && headers.contains_key(HOST);
+    if let Some(host) = target_uri.host().filter(|_| !keep_client_host) {
         let target_host = if let Some(port) = target_uri.port_u16() {
             format!("{}:{}", host, port)
         } else {
             host.to_string()
         };
-        new_req = new_req.header(HOST, HeaderValue::from_str(&target_host)?);
+        let host_value = HeaderValue::from_str(&target_host)
+            .map_err(|e| ProxyError::InvalidRequest(e.to_string()))?;
+        new_req = new_req.header(HOST, host_value);
     }
     
-    let new_req = new_req.body(Body::from(body_bytes.clone()))?;
+    new_req.body(body)
+        .map_err(|e| ProxyError::InvalidRequest(e.to_string()))
+}
+
+/// 响应为带 Location 的重定向时，返回解析后的目标地址
+fn redirect_target(response: &Response<Body>, current: &hyper::Uri) -> Option<hyper::Uri> {
+    use hyper::StatusCode;
+    if !matches!(
+        response.status(),
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+    ) {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    resolve_location(current, location)
+}
+
+/// 将 Location 解析为绝对地址（支持绝对地址、`//host/path`、`/path` 和相对路径）
+fn resolve_location(current: &hyper::Uri, location: &str) -> Option<hyper::Uri> {
+    if let Ok(uri) = location.parse::<hyper::Uri>() {
+        if uri.scheme().is_some() && uri.authority().is_some() {
+            return Some(uri);
+        }
+    }
+    let scheme = current.scheme_str()?;
+    let authority = current.authority()?.as_str();
+    let resolved = if let Some(rest) = location.strip_prefix("//") {
+        format!("{}://{}", scheme, rest)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    } else {
+        let path = current.path();
+        let dir = &path[..path.rfind('/').map(|i| i + 1).unwrap_or(0)];
+        format!("{}://{}{}{}", scheme, authority, if dir.is_empty() { "/" } else { dir }, location)
+    };
+    resolved.parse().ok()
+}
+
+/// 两个地址的协议、主机和端口是否都相同
+fn is_same_origin(a: &hyper::Uri, b: &hyper::Uri) -> bool {
+    a.scheme_str() == b.scheme_str()
+        && a.host().map(|h| h.to_ascii_lowercase()) == b.host().map(|h| h.to_ascii_lowercase())
+        && a.port_u16() == b.port_u16()
+}
+
+/// 是否需要缓冲响应体检查错误字段
+///
+/// 只检查未压缩的 JSON 成功响应；SSE 等流式响应和压缩响应直接转发。
+fn should_inspect_body(provider: &Provider, response: &Response<Body>) -> bool {
+    if !provider.is_success_status(response.status()) {
+        return false;
+    }
+    let headers = response.headers();
+    let is_json = headers.get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.to_ascii_lowercase().contains("json"));
+    let is_encoded = headers.get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !v.eq_ignore_ascii_case("identity"));
+    is_json && !is_encoded
+}
+
+/// 缓冲响应体，若 `pointer` 指向的字段存在且不为 null 则视为失败，否则重新组装响应返回
+async fn check_response_body(response: Response<Body>, pointer: &str) -> Result<Response<Body>, ProxyError> {
+    let (mut parts, body) = response.into_parts();
+    // 响应体重新组装后由 hyper 按实际长度设置 Content-Length，不沿用上游的分块或长度头
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(TRANSFER_ENCODING);
+    let bytes = hyper::body::to_bytes(body).await
+        .map_err(|e| ProxyError::BodyRead(e.to_string()))?;
     
-    let response = client.request(new_req).await?;
+    let has_error = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|json| json.pointer(pointer).map(|value| !value.is_null()))
+        .unwrap_or(false);
+    if has_error {
+        return Err(ProxyError::UpstreamBodyError(parts.status.as_u16(), pointer.to_string()));
+    }
     
-    Ok(response)
+    Ok(Response::from_parts(parts, Body::from(bytes)))
 }
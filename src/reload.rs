@@ -0,0 +1,135 @@
+//! 配置重载 - 在运行时重新读取配置文件并替换提供商列表
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::Serialize;
+use crate::config::{check_providers, load_providers_file};
+use crate::provider::Provider;
+use crate::proxy::ProxyState;
+
+/// 可在运行时整体替换的提供商列表
+///
+/// 每个请求开始时取一次当前列表的快照，替换不会影响进行中的请求。
+pub struct ProviderStore {
+    providers: std::sync::Mutex<Arc<Vec<Provider>>>,
+    /// 配置文件路径（直接传入提供商时为 None，不支持重载）
+    config_path: Option<PathBuf>,
+}
+
+/// 重载前后提供商列表的差异（按状态键比较，列表中为提供商名称）
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// 状态键不变但配置有变化（包括改名）的提供商
+    pub changed: Vec<String>,
+    /// 配置变化中Token或地址有变化、熔断和冷却状态已被重置的提供商（`changed` 的子集）
+    pub reset: Vec<String>,
+}
+
+impl ReloadDiff {
+    /// 是否没有任何变化
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl ProviderStore {
+    pub fn new(providers: Vec<Provider>, config_path: Option<PathBuf>) -> Self {
+        Self {
+            providers: std::sync::Mutex::new(Arc::new(providers)),
+            config_path,
+        }
+    }
+
+    /// 当前的提供商列表
+    pub fn current(&self) -> Arc<Vec<Provider>> {
+        match self.providers.lock() {
+            Ok(providers) => Arc::clone(&providers),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// 配置文件路径
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
+    /// 替换提供商列表，返回与旧列表的差异
+    pub fn replace(&self, providers: Vec<Provider>) -> ReloadDiff {
+        let mut current = match self.providers.lock() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let diff = diff_providers(&current, &providers);
+        *current = Arc::new(providers);
+        diff
+    }
+
+    /// 重新读取配置文件并替换提供商列表，同时清理代理状态
+    ///
+    /// 读取、解析或校验失败（与 `--check-config` 相同的检查，包括名称或 ID 重复）时保留原来的列表并返回错误。
+    /// 配置文件在阻塞线程池中读取，`exec:` token命令不会占用处理请求的线程。新增或配置有变化的提供商按新配置的
+    /// `enabled` 设置启用状态并重置认证失败和域名解析失败标记，其中Token或地址有变化的还会重置
+    /// 健康度和速率窗口；已删除的提供商的状态会被清理，缓存的响应全部失效。
+    pub async fn reload(&self, state: &ProxyState) -> Result<ReloadDiff, String> {
+        let path = self.config_path.clone().ok_or_else(|| "未使用配置文件启动，无法重载".to_string())?;
+        let providers = tokio::task::spawn_blocking(move || load_providers_file(&path))
+            .await
+            .map_err(|e| format!("❌ 读取配置文件失败: {}", e))??;
+        check_providers(&providers)?;
+        let diff = self.replace(providers);
+        
+        let current = self.current();
+        let touched: Vec<Provider> = current.iter()
+            .filter(|p| diff.added.contains(&p.name) || diff.changed.contains(&p.name))
+            .cloned()
+            .collect();
+        state.interactive_manager.reseed_from_config(&touched);
+        for provider in &touched {
+            state.clear_auth_failed(provider.state_key());
+            state.clear_dns_failed(provider.state_key());
+        }
+        for provider in current.iter().filter(|p| diff.reset.contains(&p.name)) {
+            state.reset_provider(provider.state_key());
+        }
+        state.gc(&current);
+        // 缓存的响应可能来自已删除或已修改的提供商
+        if let Some(cache) = &state.response_cache {
+            cache.clear();
+        }
+        
+        Ok(diff)
+    }
+}
+
+/// 按状态键（设置了 `id` 时为ID，否则为名称）比较两个提供商列表
+///
+/// ID 相同而名称不同的提供商视为改名，记为配置变化，累计状态保持不变。
+fn diff_providers(old: &[Provider], new: &[Provider]) -> ReloadDiff {
+    let find = |list: &[Provider], key: &str| list.iter().find(|p| p.state_key() == key).cloned();
+    let mut diff = ReloadDiff::default();
+    for provider in new {
+        match find(old, provider.state_key()) {
+            None => diff.added.push(provider.name.clone()),
+            Some(previous) if !same_config(&previous, provider) => {
+                if previous.token != provider.token || previous.base_url != provider.base_url {
+                    diff.reset.push(provider.name.clone());
+                }
+                diff.changed.push(provider.name.clone());
+            }
+            Some(_) => {}
+        }
+    }
+    for provider in old {
+        if find(new, provider.state_key()).is_none() {
+            diff.removed.push(provider.name.clone());
+        }
+    }
+    diff
+}
+
+/// 两个提供商的配置是否完全相同
+fn same_config(a: &Provider, b: &Provider) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
@@ -0,0 +1,554 @@
+//! 代理服务器入口 - 供命令行程序和其他程序嵌入使用
+
+use std::convert::Infallible;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use crate::config::read_providers_config;
+use crate::network::NetworkStatus;
+use crate::persistence::state_file_path;
+use crate::provider::Provider;
+use crate::reload::ProviderStore;
+use crate::admin::handle_admin_port_request;
+use crate::proxy::{check_health_transitions, handle_request_with_logger, probe_dns_failures, run_health_checks, self_test_provider, LoadBalanceStrategy, ProxyState, SelfTestRequest, SelfTestResult, HISTOGRAM_BUCKET_DURATION};
+use crate::tls::tls_incoming;
+use crate::ui::{LogLevel, Logger, ServerInfo};
+use crate::appender::APPEND_FLUSH_INTERVAL;
+
+/// 启动过程中的错误
+pub type ServerError = Box<dyn StdError + Send + Sync>;
+
+/// 健康状态巡检间隔
+const HEALTH_WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 自定义代理状态的回调
+type StateOptions = Box<dyn FnOnce(ProxyState) -> ProxyState + Send>;
+
+/// 代理服务器构建器
+///
+/// ```no_run
+/// # async fn example() -> Result<(), auto_proxy::ServerError> {
+/// let handle = auto_proxy::ProxyServer::from_config(None)?
+///     .with_bind_addr(([127, 0, 0, 1], 8080).into())
+///     .with_rate_limit(10)
+///     .run()
+///     .await?;
+/// // ...
+/// handle.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ProxyServer {
+    providers: Vec<Provider>,
+    /// 配置文件路径，用于确定禁用状态文件的位置（直接传入提供商时为 None，不持久化禁用状态）
+    config_path: Option<PathBuf>,
+    bind_addr: SocketAddr,
+    /// 管理接口的独立监听地址（None 时管理接口与代理流量共用监听端口）
+    admin_addr: Option<SocketAddr>,
+    rate_limit: usize,
+    strategy: LoadBalanceStrategy,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    logger: Option<Arc<Logger>>,
+    state_options: Option<StateOptions>,
+}
+
+impl ProxyServer {
+    /// 从配置文件创建（`None` 时使用默认配置文件路径）
+    pub fn from_config(config_path: Option<PathBuf>) -> Result<Self, String> {
+        let (providers, actual_path) = read_providers_config(config_path)?;
+        let mut server = Self::from_providers(providers);
+        server.config_path = Some(actual_path);
+        Ok(server)
+    }
+
+    /// 直接使用提供商列表创建
+    pub fn from_providers(providers: Vec<Provider>) -> Self {
+        Self {
+            providers,
+            config_path: None,
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            admin_addr: None,
+            rate_limit: 5,
+            strategy: LoadBalanceStrategy::default(),
+            tls_config: None,
+            logger: None,
+            state_options: None,
+        }
+    }
+
+    /// 设置监听地址（端口为 0 时由系统分配，可通过 `ProxyHandle::local_addr` 获取）
+    pub fn with_bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = addr;
+        self
+    }
+
+    /// 在独立地址上提供管理接口（`/_admin`、`/_status`、`/_metrics`、`/_dashboard`），代理端口不再响应 `/_` 路径
+    ///
+    /// 管理端口不使用 TLS，建议绑定到 `127.0.0.1`。
+    pub fn with_admin_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.admin_addr = addr;
+        self
+    }
+
+    /// 设置每个提供商每分钟的最大请求数
+    pub fn with_rate_limit(mut self, rate_limit: usize) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// 设置负载均衡策略
+    pub fn with_strategy(mut self, strategy: LoadBalanceStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// 设置监听端口的 TLS 配置（设置后以 HTTPS 提供服务）
+    pub fn with_tls_config(mut self, tls_config: Option<Arc<rustls::ServerConfig>>) -> Self {
+        self.tls_config = tls_config;
+        self
+    }
+
+    /// 设置日志记录器（未设置时请求日志输出到标准输出）
+    pub fn with_logger(mut self, logger: Option<Arc<Logger>>) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// 通过 `ProxyState` 的 `with_*` 方法设置其他选项
+    pub fn with_state_options(mut self, options: impl FnOnce(ProxyState) -> ProxyState + Send + 'static) -> Self {
+        self.state_options = Some(Box::new(options));
+        self
+    }
+
+    /// 已加载的提供商
+    pub fn providers(&self) -> &[Provider] {
+        &self.providers
+    }
+
+    /// 不启动服务器，向每个提供商（包括被禁用的）并发发送一次自检请求，按配置顺序返回结果
+    ///
+    /// 使用与正式运行相同的状态选项（连接超时、HTTP/2 等），但不监听端口、不启动后台任务。
+    pub async fn self_test(self, request: SelfTestRequest) -> Vec<(Provider, SelfTestResult)> {
+        let mut state = ProxyState::new_with_rate_limit(self.rate_limit.max(1));
+        if let Some(options) = self.state_options {
+            state = options(state);
+        }
+        let state = Arc::new(state);
+        
+        let tasks: Vec<_> = self.providers.into_iter()
+            .map(|provider| {
+                let state = Arc::clone(&state);
+                let request = request.clone();
+                tokio::spawn(async move {
+                    let result = self_test_provider(&provider, &state, &request).await;
+                    (provider, result)
+                })
+            })
+            .collect();
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            if let Ok(result) = task.await {
+                results.push(result);
+            }
+        }
+        results
+    }
+
+    /// 解析后的实际生效配置（提供商Token已脱敏），用于排查命令行参数与配置文件合并后的结果
+    ///
+    /// 与 `self_test` 一样应用状态选项，但不监听端口、不启动后台任务。
+    pub fn effective_config(self) -> serde_json::Value {
+        let mut state = ProxyState::new_with_rate_limit(self.rate_limit.max(1))
+            .with_strategy(self.strategy);
+        if let Some(options) = self.state_options {
+            state = options(state);
+        }
+
+        let providers: Vec<Provider> = self.providers.iter()
+            .map(|provider| Provider { token: provider.masked_token(), ..provider.clone() })
+            .collect();
+        let tag_routes: Vec<_> = state.tag_routes.iter()
+            .map(|(prefix, tag)| serde_json::json!({ "prefix": prefix, "tag": tag }))
+            .collect();
+        let allowed_requests: Vec<String> = state.allowed_requests.iter()
+            .map(|(method, path)| match method {
+                Some(method) => format!("{} {}", method, path),
+                None => path.clone(),
+            })
+            .collect();
+
+        serde_json::json!({
+            "config_path": self.config_path,
+            "bind_addr": self.bind_addr.to_string(),
+            "admin_addr": self.admin_addr.map(|addr| addr.to_string()),
+            "tls": self.tls_config.is_some(),
+            "rate_limit": state.rate_limit,
+            "rate_limit_mode": value_name(&state.rate_limit_mode),
+            "strategy": value_name(&state.strategy),
+            "round_robin_jitter": state.round_robin_jitter,
+            "explore_percent": state.explore_percent,
+            "http2": state.http2_enabled,
+            "connect_timeout_ms": state.connect_timeout.map(|t| t.as_millis() as u64),
+            "request_timeout_ms": state.request_timeout.map(|t| t.as_millis() as u64),
+            "queue_wait_ms": state.queue_wait.as_millis() as u64,
+            "dns_cooldown_secs": state.dns_cooldown.as_secs(),
+            "health_check_secs": state.health_check_interval.map(|t| t.as_secs()),
+            "health_check_jitter_percent": state.health_check_jitter_percent,
+            "preserve_host": state.preserve_host,
+            "max_failover": state.max_failover,
+            "max_buffered_body": state.max_buffered_body,
+            "token_estimation": state.token_estimator.config(),
+            "max_concurrency": state.max_concurrency,
+            "follow_redirects": state.follow_redirects,
+            "retry_same_on_5xx": state.retry_same_on_5xx,
+            "expose_provider_header": state.expose_provider_header,
+            "emergency_mode": state.emergency_mode,
+            "auto_recovery": state.auto_recovery,
+            "maintenance_minutes": state.maintenance_duration.as_secs() / 60,
+            "retry_after": state.retry_after,
+            "unavailable_responses": state.unavailable_responses,
+            "health": state.health_config,
+            "tag_routes": tag_routes,
+            "allowed_requests": allowed_requests,
+            "coalesce_paths": state.coalescer.as_ref().map(|c| c.paths()).unwrap_or_default(),
+            "cache": state.response_cache.as_ref().map(|cache| serde_json::json!({
+                "rules": cache.rules().iter()
+                    .map(|(prefix, ttl)| serde_json::json!({ "prefix": prefix, "ttl_secs": ttl.as_secs() }))
+                    .collect::<Vec<_>>(),
+                "vary": cache.vary_headers().iter().map(|h| h.as_str()).collect::<Vec<_>>(),
+                "capacity": cache.capacity(),
+            })),
+            "log_level": value_name(&state.log_level),
+            "sticky_header": state.sticky_header.as_ref().map(|h| h.as_str()),
+            "error_pointer": state.error_pointer,
+            "admin_token": state.admin_token.as_ref().map(|_| "****"),
+            "access_log": state.access_log.is_some(),
+            "usage_csv": state.usage_csv.is_some(),
+            "providers": providers,
+        })
+    }
+
+    /// 监听端口并在后台运行服务器，返回用于查询状态和关闭服务器的句柄
+    pub async fn run(self) -> Result<ProxyHandle, ServerError> {
+        let logger = self.logger;
+
+        // 先绑定端口，绑定失败时直接返回错误，端口为 0 时也能得到实际地址
+        let listener = std::net::TcpListener::bind(self.bind_addr)?;
+        let local_addr = listener.local_addr()?;
+        let admin_listener = self.admin_addr.map(std::net::TcpListener::bind).transpose()?;
+        let admin_addr = admin_listener.as_ref().map(|l| l.local_addr()).transpose()?;
+
+        let server_info = Arc::new(ServerInfo::new(local_addr.port(), self.rate_limit));
+        let mut state = ProxyState::new_with_rate_limit(self.rate_limit)
+            .with_server_info(Arc::clone(&server_info))
+            .with_strategy(self.strategy)
+            .with_separate_admin_port(admin_listener.is_some());
+        if let Some(options) = self.state_options {
+            state = options(state);
+        }
+
+        // 配置中 enabled: false 的提供商启动后处于禁用状态
+        let config_disabled = state.interactive_manager.seed_from_config(&self.providers);
+        if !config_disabled.is_empty() {
+            notify(&logger, LogLevel::Warning, format!("⏸️  配置中禁用的提供商: {}", config_disabled.join(", ")));
+        }
+
+        // 恢复上次运行时被禁用的提供商
+        if let Some(config_path) = &self.config_path {
+            match state.interactive_manager.enable_persistence(state_file_path(config_path), &self.providers) {
+                Ok(restored) if !restored.is_empty() => {
+                    notify(&logger, LogLevel::Warning, format!("⏸️  已恢复禁用的提供商: {}", restored.join(", ")));
+                }
+                Ok(_) => {}
+                Err(e) => notify(&logger, LogLevel::Warning, format!("⚠️  禁用状态恢复失败: {}", e)),
+            }
+        }
+
+        if state.all_providers_disabled(&self.providers) {
+            notify(&logger, LogLevel::Warning, "⚠️  所有提供商都处于禁用状态，启用至少一个提供商之前请求将返回 503".to_string());
+        }
+
+        let store = Arc::new(ProviderStore::new(self.providers, self.config_path));
+        let state = Arc::new(state.with_provider_store(Arc::clone(&store)));
+
+        // 异步检测网络状态，供状态接口和界面使用
+        let network_server_info = Arc::clone(&server_info);
+        let network_task = tokio::spawn(async move {
+            let network_status = NetworkStatus::detect().await;
+            network_server_info.update_network_status(network_status);
+        });
+
+        // 定期巡检提供商健康状态，发现不经过请求的变化（如空闲恢复）时输出日志，并重新解析域名解析失败的主机
+        let watchdog_store = Arc::clone(&store);
+        let watchdog_state = Arc::clone(&state);
+        let watchdog_logger = logger.clone();
+        let watchdog_task = tokio::spawn(async move {
+            let mut watchdog_interval = tokio::time::interval(HEALTH_WATCHDOG_INTERVAL);
+            loop {
+                watchdog_interval.tick().await;
+                let providers = watchdog_store.current();
+                check_health_transitions(&watchdog_state, &providers, &watchdog_logger);
+                probe_dns_failures(&watchdog_state, &providers, &watchdog_logger).await;
+            }
+        });
+
+        // 按 --health-check-secs 主动探测提供商，空闲时也能发现故障和恢复；
+        // 每个提供商按名称得到固定的抖动，探测分散在间隔内，网络短暂抖动不会让所有提供商同时失败
+        let health_check_task = state.health_check_interval.map(|health_check_interval| {
+            let health_check_store = Arc::clone(&store);
+            let health_check_state = Arc::clone(&state);
+            let health_check_logger = logger.clone();
+            tokio::spawn(async move {
+                let mut next_checks: HashMap<String, tokio::time::Instant> = HashMap::new();
+                loop {
+                    let providers = health_check_store.current();
+                    let now = tokio::time::Instant::now();
+                    // 重载配置后移除已删除的提供商，新增的提供商按各自的首次延迟加入计划
+                    next_checks.retain(|key, _| providers.iter().any(|p| p.state_key() == key));
+                    for provider in providers.iter() {
+                        next_checks.entry(provider.state_key().to_string()).or_insert_with(|| {
+                            now + health_check_state.health_check_schedule(provider.state_key(), health_check_interval).0
+                        });
+                    }
+
+                    let due: Vec<Provider> = providers.iter()
+                        .filter(|p| next_checks.get(p.state_key()).is_some_and(|next| *next <= now))
+                        .cloned()
+                        .collect();
+                    if !due.is_empty() {
+                        for provider in &due {
+                            let (_, period) = health_check_state.health_check_schedule(provider.state_key(), health_check_interval);
+                            next_checks.insert(provider.state_key().to_string(), now + period);
+                        }
+                        run_health_checks(&health_check_state, &due, &health_check_logger).await;
+                    }
+
+                    // 最长等待一个基础间隔，以便及时安排重载后新增的提供商
+                    let next = next_checks.values().copied().min()
+                        .unwrap_or(now + health_check_interval)
+                        .min(tokio::time::Instant::now() + health_check_interval);
+                    tokio::time::sleep_until(next).await;
+                }
+            })
+        });
+
+        // 定期把缓冲的访问记录和 Token 使用记录写入文件
+        let flush_state = Arc::clone(&state);
+        let flush_task = (state.access_log.is_some() || state.usage_csv.is_some()).then(|| {
+            tokio::spawn(async move {
+                let start = tokio::time::Instant::now() + APPEND_FLUSH_INTERVAL;
+                let mut flush_interval = tokio::time::interval_at(start, APPEND_FLUSH_INTERVAL);
+                loop {
+                    flush_interval.tick().await;
+                    let _ = flush_state.flush_record_files();
+                }
+            })
+        });
+
+        // 每分钟推进一次请求量直方图
+        let histogram_state = Arc::clone(&state);
+        let histogram_task = tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + HISTOGRAM_BUCKET_DURATION;
+            let mut histogram_interval = tokio::time::interval_at(start, HISTOGRAM_BUCKET_DURATION);
+            loop {
+                histogram_interval.tick().await;
+                histogram_state.request_histogram.advance();
+            }
+        });
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let shutdown_signal = async move {
+            let _ = shutdown_rx.await;
+        };
+
+        // 每个请求使用当时的提供商列表快照，重载配置不影响进行中的请求
+        let server_store = Arc::clone(&store);
+        let server_state = Arc::clone(&state);
+        let server_logger = logger.clone();
+        let handler = move |req| {
+            handle_request_with_logger(req, server_store.current(), Arc::clone(&server_state), server_logger.clone())
+        };
+        let is_tls = self.tls_config.is_some();
+        let server_task = tokio::spawn(serve(listener, self.tls_config, handler, shutdown_signal));
+
+        // 管理端口与代理端口共享同一份状态，只处理管理接口
+        let (admin_shutdown_tx, admin_task) = match admin_listener {
+            Some(admin_listener) => {
+                let (admin_shutdown_tx, admin_shutdown_rx) = oneshot::channel::<()>();
+                let admin_store = Arc::clone(&store);
+                let admin_state = Arc::clone(&state);
+                let admin_handler = move |req| {
+                    handle_admin_port_request(req, admin_store.current(), Arc::clone(&admin_state), logger.clone())
+                };
+                let admin_shutdown_signal = async move {
+                    let _ = admin_shutdown_rx.await;
+                };
+                (Some(admin_shutdown_tx), Some(tokio::spawn(serve(admin_listener, None, admin_handler, admin_shutdown_signal))))
+            }
+            None => (None, None),
+        };
+
+        Ok(ProxyHandle {
+            local_addr,
+            admin_addr,
+            is_tls,
+            store,
+            state,
+            server_info,
+            shutdown_tx: Some(shutdown_tx),
+            server_task: Some(server_task),
+            admin_shutdown_tx,
+            admin_task,
+            background_tasks: [network_task, watchdog_task, histogram_task].into_iter().chain(health_check_task).chain(flush_task).collect(),
+        })
+    }
+}
+
+/// 运行中的代理服务器句柄
+pub struct ProxyHandle {
+    local_addr: SocketAddr,
+    admin_addr: Option<SocketAddr>,
+    is_tls: bool,
+    store: Arc<ProviderStore>,
+    state: Arc<ProxyState>,
+    server_info: Arc<ServerInfo>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    server_task: Option<JoinHandle<Result<(), ServerError>>>,
+    admin_shutdown_tx: Option<oneshot::Sender<()>>,
+    admin_task: Option<JoinHandle<Result<(), ServerError>>>,
+    background_tasks: Vec<JoinHandle<()>>,
+}
+
+impl ProxyHandle {
+    /// 实际监听的地址
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// 管理端口实际监听的地址（未设置独立管理端口时为 None）
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    /// 是否以 HTTPS 提供服务
+    pub fn is_tls(&self) -> bool {
+        self.is_tls
+    }
+
+    /// 当前的提供商列表（重载配置后返回新的列表）
+    pub fn providers(&self) -> Arc<Vec<Provider>> {
+        self.store.current()
+    }
+
+    /// 可重载的提供商列表
+    pub fn provider_store(&self) -> &Arc<ProviderStore> {
+        &self.store
+    }
+
+    /// 代理状态
+    pub fn state(&self) -> &Arc<ProxyState> {
+        &self.state
+    }
+
+    /// 服务器信息
+    pub fn server_info(&self) -> &Arc<ServerInfo> {
+        &self.server_info
+    }
+
+    /// 等待服务器结束（正常情况下只有出错时才会结束）
+    pub async fn wait(&mut self) -> Result<(), ServerError> {
+        match self.server_task.take() {
+            Some(task) => task.await?,
+            None => Ok(()),
+        }
+    }
+
+    /// 停止接受新连接，等待进行中的请求完成后关闭服务器
+    pub async fn shutdown(mut self) -> Result<(), ServerError> {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+        if let Some(admin_shutdown_tx) = self.admin_shutdown_tx.take() {
+            let _ = admin_shutdown_tx.send(());
+        }
+        for task in self.background_tasks.drain(..) {
+            task.abort();
+        }
+        let result = self.wait().await;
+        if let Some(admin_task) = self.admin_task.take() {
+            admin_task.await??;
+        }
+        // 进行中的请求完成后写入剩余的访问记录和 Token 使用记录
+        self.state.flush_record_files()?;
+        result
+    }
+}
+
+impl Drop for ProxyHandle {
+    fn drop(&mut self) {
+        for task in &self.background_tasks {
+            task.abort();
+        }
+    }
+}
+
+/// 有日志记录器时写入日志，否则输出到标准输出
+fn notify(logger: &Option<Arc<Logger>>, level: LogLevel, message: String) {
+    match logger {
+        Some(logger) => logger.log(level, message),
+        None => println!("{}", message),
+    }
+}
+
+/// 命令行中使用的枚举取值名称（如 `round-robin`）
+fn value_name<T: clap::ValueEnum>(value: &T) -> Option<String> {
+    value.to_possible_value().map(|v| v.get_name().to_string())
+}
+
+/// 在已绑定的端口上运行服务器，提供了 TLS 配置时在监听端口上终止 HTTPS
+async fn serve<F, Fut>(
+    listener: std::net::TcpListener,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    handler: F,
+    shutdown_signal: impl Future<Output = ()>,
+) -> Result<(), ServerError>
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<Response<Body>, Infallible>> + Send + 'static,
+{
+    // 两种监听方式的连接类型不同，make_service_fn 的闭包需要分别构建
+    match tls_config {
+        Some(config) => {
+            let make_svc = make_service_fn(move |_conn| {
+                let handler = handler.clone();
+                async move { Ok::<_, Infallible>(service_fn(handler)) }
+            });
+            Server::builder(tls_incoming(listener, config)?)
+                .serve(make_svc)
+                .with_graceful_shutdown(shutdown_signal)
+                .await?;
+        }
+        None => {
+            listener.set_nonblocking(true)?;
+            let incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
+            let make_svc = make_service_fn(move |_conn| {
+                let handler = handler.clone();
+                async move { Ok::<_, Infallible>(service_fn(handler)) }
+            });
+            Server::builder(incoming)
+                .serve(make_svc)
+                .with_graceful_shutdown(shutdown_signal)
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! 链路追踪导出 - 把请求的 span 通过 OTLP/HTTP 发送到 OpenTelemetry Collector（`otlp` 特性）
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// 上报的服务名称
+const SERVICE_NAME: &str = "auto-proxy";
+
+/// OTLP/HTTP 的链路上报路径
+const TRACES_PATH: &str = "/v1/traces";
+
+/// 已安装的链路导出器，drop 时把缓冲中的 span 全部发送出去
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    /// 安装全局的 tracing 订阅者，把 span 批量导出到 `endpoint`（如 `http://localhost:4318`，缺少 `/v1/traces` 时自动补上）
+    pub fn init(endpoint: &str) -> Result<Self, String> {
+        let endpoint = traces_endpoint(endpoint);
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| format!("无法创建OTLP导出器: {}", e))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build();
+
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME));
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+            .map_err(|e| format!("无法安装tracing订阅者: {}", e))?;
+        Ok(Self { provider })
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("⚠️ 链路数据导出失败: {}", e);
+        }
+    }
+}
+
+/// 补全 OTLP/HTTP 的链路上报地址
+fn traces_endpoint(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with(TRACES_PATH) {
+        endpoint.to_string()
+    } else {
+        format!("{}{}", endpoint, TRACES_PATH)
+    }
+}
@@ -0,0 +1,140 @@
+//! 上游 TLS 连接配置和代理监听端口的 TLS 终止
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use hyper::client::HttpConnector;
+use hyper::server::accept::Accept;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, PrivateKey, ServerConfig, ServerName};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// 客户端 TLS 握手的超时时间，避免半开连接长期占用资源
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 跳过证书校验的验证器，仅用于显式开启 `insecure_skip_verify` 的提供商
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// 构建上游 HTTPS 连接器
+///
+/// `insecure_skip_verify` 为 true 时接受任意证书（包括自签名证书），否则使用系统根证书严格校验。
+/// `enable_http2` 为 true 时通过 ALPN 与上游协商 HTTP/2，否则强制使用 HTTP/1.1。
+/// `connect_timeout` 只限制建立 TCP 连接的时间（None 表示使用系统默认值），不影响等待响应的时间。
+pub fn build_https_connector(insecure_skip_verify: bool, enable_http2: bool, connect_timeout: Option<Duration>) -> HttpsConnector<HttpConnector> {
+    let builder = if insecure_skip_verify {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+            .with_no_client_auth();
+        HttpsConnectorBuilder::new().with_tls_config(config)
+    } else {
+        HttpsConnectorBuilder::new().with_native_roots()
+    };
+    
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(connect_timeout);
+    
+    let builder = builder.https_or_http().enable_http1();
+    if enable_http2 {
+        builder.enable_http2().wrap_connector(http)
+    } else {
+        builder.wrap_connector(http)
+    }
+}
+
+/// 从 PEM 文件加载代理监听端口使用的证书链和私钥
+///
+/// 私钥支持 PKCS#8、PKCS#1（RSA）和 SEC1（EC）格式，使用文件中的第一个私钥。
+pub fn load_server_tls_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>, String> {
+    let cert_file = File::open(cert_path)
+        .map_err(|e| format!("❌ 无法读取证书文件 {}: {}", cert_path.display(), e))?;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|e| format!("❌ 证书文件格式错误 {}: {}", cert_path.display(), e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("❌ 证书文件中没有找到 PEM 格式的证书: {}", cert_path.display()));
+    }
+    
+    let key_file = File::open(key_path)
+        .map_err(|e| format!("❌ 无法读取私钥文件 {}: {}", key_path.display(), e))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(key_file))
+        .map_err(|e| format!("❌ 私钥文件格式错误 {}: {}", key_path.display(), e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("❌ 私钥文件中没有找到 PEM 格式的私钥: {}", key_path.display()))?;
+    
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("❌ 证书与私钥无效或不匹配: {}", e))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    
+    Ok(Arc::new(config))
+}
+
+/// 将 TCP 监听器包装为完成 TLS 握手后的连接流，供 `hyper::Server::builder` 使用
+///
+/// 每个连接的握手在独立任务中进行，单个慢速或失败的握手（如客户端误用明文 HTTP）不会阻塞其他连接。
+pub fn tls_incoming(
+    listener: std::net::TcpListener,
+    config: Arc<ServerConfig>,
+) -> io::Result<impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error>> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let acceptor = TlsAcceptor::from(config);
+    let (tx, rx) = mpsc::channel::<TlsStream<TcpStream>>(64);
+    
+    tokio::spawn(async move {
+        while !tx.is_closed() {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    // 文件描述符耗尽等错误时稍作等待，避免空转
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                if let Ok(Ok(tls_stream)) = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    let _ = tx.send(tls_stream).await;
+                }
+            });
+        }
+    });
+    
+    let incoming = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|stream| (Ok(stream), rx))
+    });
+    Ok(hyper::server::accept::from_stream(incoming))
+}
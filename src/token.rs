@@ -1,29 +1,189 @@
 use serde_json;
 
+/// 参与JSON内容分析的最大请求体字节数，超过时只按长度估算，避免在请求路径上解析大体积请求
+pub const MAX_ESTIMATION_BYTES: usize = 256 * 1024;
+
+/// 每个图片等媒体内容块的默认Token成本（Claude图片token估算）
+pub const DEFAULT_MEDIA_TOKENS: u64 = 85;
+
+/// 启发式Token估算的参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct TokenEstimationConfig {
+    /// 参与JSON内容分析的最大消息体字节数，超过时不解析，只按长度估算
+    pub max_parse_bytes: usize,
+    /// 每个图片等媒体内容块计入的Token数量
+    pub media_tokens: u64,
+}
+
+impl Default for TokenEstimationConfig {
+    fn default() -> Self {
+        Self {
+            max_parse_bytes: MAX_ESTIMATION_BYTES,
+            media_tokens: DEFAULT_MEDIA_TOKENS,
+        }
+    }
+}
+
+/// 请求体分析结果
+#[derive(Debug, Clone, Default)]
+pub struct RequestAnalysis {
+    /// 请求JSON中的 `model` 字段
+    pub model: Option<String>,
+    /// 估算的输入Token数量
+    pub input_tokens: u64,
+    /// 估算的Token使用量（请求+响应）
+    pub estimated_tokens: u64,
+}
+
+/// Token 估算器，可以替换为调用真实分词服务或返回固定值的实现
+pub trait TokenEstimator: Send + Sync {
+    /// 分析请求体，得到请求的模型和估算的Token使用量
+    fn estimate_request(&self, body_bytes: &hyper::body::Bytes, uri: &hyper::Uri, headers: &hyper::HeaderMap) -> RequestAnalysis;
+
+    /// 估算响应Token使用量，无法估算（如压缩的响应体）时返回 None
+    fn estimate_response(&self, response_body: &[u8], headers: &hyper::HeaderMap) -> Option<u64>;
+
+    /// 估算使用的参数，不基于这些参数估算的实现返回 None
+    fn config(&self) -> Option<TokenEstimationConfig> {
+        None
+    }
+}
+
+/// 默认的Token估算器：按字符类型和JSON结构进行启发式估算（即 `TokenCalculator`）
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicTokenEstimator {
+    pub config: TokenEstimationConfig,
+}
+
+impl HeuristicTokenEstimator {
+    pub fn new(config: TokenEstimationConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl TokenEstimator for HeuristicTokenEstimator {
+    fn estimate_request(&self, body_bytes: &hyper::body::Bytes, uri: &hyper::Uri, headers: &hyper::HeaderMap) -> RequestAnalysis {
+        TokenCalculator::analyze_request_with_config(body_bytes, uri, headers, &self.config)
+    }
+
+    fn estimate_response(&self, response_body: &[u8], headers: &hyper::HeaderMap) -> Option<u64> {
+        if TokenCalculator::is_encoded(headers) {
+            return None;
+        }
+        Some(TokenCalculator::estimate_response_usage_with_config(response_body, &self.config))
+    }
+
+    fn config(&self) -> Option<TokenEstimationConfig> {
+        Some(self.config)
+    }
+}
+
 /// Token 计算相关功能
 pub struct TokenCalculator;
 
 impl TokenCalculator {
     /// 估算请求Token使用量（输入tokens）
     pub fn estimate_request_usage(body_bytes: &hyper::body::Bytes, uri: &hyper::Uri) -> u64 {
-        // 尝试解析JSON请求体获取更准确的token计算
-        if let Ok(body_str) = std::str::from_utf8(body_bytes) {
-            if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(body_str) {
-                // 基于实际内容计算tokens
-                return Self::estimate_from_json(&json_value, uri);
-            }
+        let config = TokenEstimationConfig::default();
+        match Self::parse_json_body(body_bytes, &config) {
+            // 基于实际内容计算tokens
+            Some(json_value) => Self::estimate_from_json(&json_value, uri, &config),
+            // 回退到基础估算
+            None => Self::fallback_estimation(body_bytes, uri),
+        }
+    }
+
+    /// 解析JSON请求体，过大、明显不是JSON或解析失败时返回 None
+    fn parse_json_body(body_bytes: &[u8], config: &TokenEstimationConfig) -> Option<serde_json::Value> {
+        // 过大或明显不是JSON的请求体不解析（如内嵌 base64 图片的多模态请求）
+        if body_bytes.len() > config.max_parse_bytes || !Self::looks_like_json(body_bytes) {
+            return None;
+        }
+        serde_json::from_slice(body_bytes).ok()
+    }
+
+    /// 分析请求体：只解析一次JSON，同时得到请求的模型和估算的Token使用量
+    pub fn analyze_request(body_bytes: &hyper::body::Bytes, uri: &hyper::Uri, headers: &hyper::HeaderMap) -> RequestAnalysis {
+        Self::analyze_request_with_config(body_bytes, uri, headers, &TokenEstimationConfig::default())
+    }
+
+    /// 按指定的估算参数分析请求体
+    pub fn analyze_request_with_config(
+        body_bytes: &hyper::body::Bytes,
+        uri: &hyper::Uri,
+        headers: &hyper::HeaderMap,
+        config: &TokenEstimationConfig,
+    ) -> RequestAnalysis {
+        // 压缩的请求体不解析内容，只按字节长度粗略估算
+        if Self::is_encoded(headers) {
+            let estimated_tokens = Self::fallback_estimation(body_bytes, uri);
+            return RequestAnalysis {
+                model: None,
+                input_tokens: estimated_tokens,
+                estimated_tokens,
+            };
         }
         
-        // 回退到基础估算
-        Self::fallback_estimation(body_bytes, uri)
+        let json = Self::parse_json_body(body_bytes, config);
+        let input_tokens = match &json {
+            Some(json_value) => Self::estimate_from_json(json_value, uri, config),
+            None => Self::fallback_estimation(body_bytes, uri),
+        };
+        let estimated_tokens = input_tokens + Self::estimate_response_from_input(input_tokens);
+        
+        RequestAnalysis {
+            model: json.as_ref()
+                .and_then(|v| v.get("model"))
+                .and_then(|v| v.as_str())
+                .map(|m| m.to_string()),
+            input_tokens,
+            estimated_tokens,
+        }
+    }
+
+    /// 快速判断消息体是否可能是JSON（首个非空白字符为 `{` 或 `[`）
+    fn looks_like_json(body: &[u8]) -> bool {
+        matches!(body.iter().find(|b| !b.is_ascii_whitespace()), Some(b'{') | Some(b'['))
+    }
+
+    /// 消息体是否经过压缩编码（gzip、deflate、br 等）
+    ///
+    /// 压缩后的字节无法按JSON或文本解析，估算时需要跳过内容分析。
+    pub fn is_encoded(headers: &hyper::HeaderMap) -> bool {
+        headers.get_all(hyper::header::CONTENT_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim())
+            .any(|v| !v.is_empty() && !v.eq_ignore_ascii_case("identity"))
+    }
+
+    /// 根据 `Content-Encoding` 估算响应Token使用量，压缩的响应体返回 None
+    ///
+    /// 只用于估算，不会修改转发给客户端的响应体。
+    pub fn estimate_response_usage_with_headers(response_body: &[u8], headers: &hyper::HeaderMap) -> Option<u64> {
+        if Self::is_encoded(headers) {
+            return None;
+        }
+        Some(Self::estimate_response_usage(response_body))
     }
 
     /// 估算响应Token使用量（输出tokens）
     pub fn estimate_response_usage(response_body: &[u8]) -> u64 {
+        Self::estimate_response_usage_with_config(response_body, &TokenEstimationConfig::default())
+    }
+
+    /// 按指定的估算参数估算响应Token使用量
+    pub fn estimate_response_usage_with_config(response_body: &[u8], config: &TokenEstimationConfig) -> u64 {
+        // 过大的响应体只按长度估算
+        if response_body.len() > config.max_parse_bytes {
+            return (response_body.len() as u64 / 4).max(1);
+        }
+        
         // 尝试解析响应JSON
         if let Ok(body_str) = std::str::from_utf8(response_body) {
             if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(body_str) {
-                return Self::estimate_response_from_json(&json_value);
+                return Self::estimate_response_from_json(&json_value, config);
             }
         }
         
@@ -45,7 +205,7 @@ impl TokenCalculator {
     }
 
     /// 从响应JSON估算token数量
-    fn estimate_response_from_json(json: &serde_json::Value) -> u64 {
+    fn estimate_response_from_json(json: &serde_json::Value, config: &TokenEstimationConfig) -> u64 {
         let mut total_tokens = 0u64;
         
         if let Some(obj) = json.as_object() {
@@ -66,7 +226,7 @@ impl TokenCalculator {
             
             // Claude API直接内容
             else if let Some(content) = obj.get("content") {
-                total_tokens += Self::estimate_content_tokens(content);
+                total_tokens += Self::estimate_content_tokens(content, config);
             }
             
             // 单独的文本内容
@@ -88,7 +248,11 @@ impl TokenCalculator {
     /// 基于请求估算响应token数量（当无法读取响应体时的估算）
     pub fn estimate_response_from_request(request_body: &hyper::body::Bytes, uri: &hyper::Uri) -> u64 {
         let input_tokens = Self::estimate_request_usage(request_body, uri);
-        
+        Self::estimate_response_from_input(input_tokens)
+    }
+
+    /// 根据输入token数量估算响应token数量
+    fn estimate_response_from_input(input_tokens: u64) -> u64 {
         // 基于请求复杂度估算响应长度
         // 一般AI响应的token数大约是请求的0.5-2倍
         let response_ratio = if input_tokens < 100 {
@@ -106,8 +270,9 @@ impl TokenCalculator {
 
     /// 估算完整对话的token使用量（请求+估算响应）
     pub fn estimate_conversation_usage(request_body: &hyper::body::Bytes, uri: &hyper::Uri) -> (u64, u64, u64) {
+        // 只分析一次请求体，响应估算复用输入token数量
         let input_tokens = Self::estimate_request_usage(request_body, uri);
-        let estimated_output_tokens = Self::estimate_response_from_request(request_body, uri);
+        let estimated_output_tokens = Self::estimate_response_from_input(input_tokens);
         let total_tokens = input_tokens + estimated_output_tokens;
         
         (input_tokens, estimated_output_tokens, total_tokens)
@@ -119,8 +284,15 @@ impl TokenCalculator {
         total_tokens
     }
 
+    /// 根据请求头估算Token使用量
+    ///
+    /// 请求体经过压缩编码时不解析内容，只按字节长度粗略估算。
+    pub fn estimate_usage_with_headers(body_bytes: &hyper::body::Bytes, uri: &hyper::Uri, headers: &hyper::HeaderMap) -> u64 {
+        Self::analyze_request(body_bytes, uri, headers).estimated_tokens
+    }
+
     /// 基于JSON内容的更准确token估算
-    fn estimate_from_json(json: &serde_json::Value, uri: &hyper::Uri) -> u64 {
+    fn estimate_from_json(json: &serde_json::Value, uri: &hyper::Uri, config: &TokenEstimationConfig) -> u64 {
         let mut total_tokens = 0u64;
         
         // 基础API调用开销
@@ -132,7 +304,7 @@ impl TokenCalculator {
             if let Some(messages) = obj.get("messages").and_then(|v| v.as_array()) {
                 for message in messages {
                     if let Some(content) = message.get("content") {
-                        total_tokens += Self::estimate_content_tokens(content);
+                        total_tokens += Self::estimate_content_tokens(content, config);
                     }
                 }
             }
@@ -153,7 +325,7 @@ impl TokenCalculator {
             
             // 通用内容字段
             else if let Some(input) = obj.get("input") {
-                total_tokens += Self::estimate_content_tokens(input);
+                total_tokens += Self::estimate_content_tokens(input, config);
             }
             
             // 检查system prompt
@@ -180,11 +352,11 @@ impl TokenCalculator {
         total_tokens += path_tokens;
         
         // 合理范围限制
-        total_tokens.max(15).min(100000)
+        total_tokens.clamp(15, 100000)
     }
 
     /// 估算内容的token数量（支持字符串和数组格式）
-    fn estimate_content_tokens(content: &serde_json::Value) -> u64 {
+    fn estimate_content_tokens(content: &serde_json::Value, config: &TokenEstimationConfig) -> u64 {
         match content {
             serde_json::Value::String(text) => Self::estimate_text_tokens(text),
             serde_json::Value::Array(arr) => {
@@ -196,7 +368,7 @@ impl TokenCalculator {
                         }
                         // 图片或其他媒体类型额外成本
                         if obj.get("type").and_then(|v| v.as_str()).unwrap_or("") == "image" {
-                            tokens += 85; // Claude图片token估算
+                            tokens += config.media_tokens;
                         }
                     }
                 }
@@ -256,7 +428,7 @@ impl TokenCalculator {
         
         let estimated = body_tokens + path_tokens + base_tokens;
         
-        estimated.max(10).min(80000)
+        estimated.clamp(10, 80000)
     }
 }
 
@@ -273,4 +445,30 @@ pub fn calculate_display_width(text: &str) -> usize {
             1
         }
     }).sum()
+}
+
+/// 把文本截断或填充到恰好 `width` 个显示宽度，过长时以 `…` 结尾，使表格各列对齐
+pub fn fit_display_width(text: &str, width: usize) -> String {
+    let text_width = calculate_display_width(text);
+    if text_width <= width {
+        return format!("{}{}", text, " ".repeat(width - text_width));
+    }
+    
+    let mut result = String::new();
+    let mut current_width = 0;
+    for ch in text.chars() {
+        let ch_width = calculate_display_width(ch.encode_utf8(&mut [0; 4]));
+        // 为省略号留出 1 列
+        if current_width + ch_width + 1 > width {
+            break;
+        }
+        result.push(ch);
+        current_width += ch_width;
+    }
+    if width > 0 {
+        result.push('…');
+        current_width += 1;
+    }
+    result.push_str(&" ".repeat(width - current_width));
+    result
 }
\ No newline at end of file
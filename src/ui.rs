@@ -1,17 +1,20 @@
 //! 终端UI模块 - 实现顶部状态栏和底部滚动日志
 
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::sync::mpsc;
 use std::collections::VecDeque;
 use std::io::{self, Write};
 use crossterm::{
     terminal::{self, ClearType},
     cursor::{self, MoveTo},
-    style::{Color, SetForegroundColor, ResetColor, Print},
+    style::{Attribute, Color, SetAttribute, SetBackgroundColor, SetForegroundColor, ResetColor, Print},
     execute, queue,
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind, MouseButton, EnableMouseCapture, DisableMouseCapture},
 };
-use chrono::{DateTime, Local};
-use crate::provider::Provider;
+use chrono::{DateTime, Local, Utc};
+use chrono::format::{Item, StrftimeItems};
+use crate::provider::{Provider, CIRCUIT_BREAK_FAILURES};
 use crate::proxy::ProxyState;
 use crate::network::NetworkStatus;
 
@@ -26,6 +29,8 @@ enum TextAlign {
 #[derive(Clone)]
 pub struct ProviderButton {
     pub provider_name: String,
+    /// 提供商在状态表中的键（见 `Provider::state_key`）
+    pub provider_key: String,
     pub row: u16,
     pub start_col: u16,
     pub end_col: u16,
@@ -39,6 +44,42 @@ pub struct LogEntry {
     pub message: String,
 }
 
+/// 日志时间戳的显示格式
+///
+/// 日志条目始终按原样保存时间，只在显示时转换时区和格式。
+#[derive(Clone, Debug)]
+pub struct TimestampFormat {
+    /// strftime 格式，如 `%H:%M:%S` 或 `%Y-%m-%dT%H:%M:%S%.3fZ`
+    pattern: String,
+    /// 是否转换为 UTC 显示（否则为本地时区）
+    utc: bool,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self { pattern: "%H:%M:%S".to_string(), utc: false }
+    }
+}
+
+impl TimestampFormat {
+    /// 创建时间戳格式，格式字符串无效时返回错误
+    pub fn new(pattern: &str, utc: bool) -> Result<Self, String> {
+        if pattern.is_empty() || StrftimeItems::new(pattern).any(|item| matches!(item, Item::Error)) {
+            return Err(format!("时间戳格式无效: {}", pattern));
+        }
+        Ok(Self { pattern: pattern.to_string(), utc })
+    }
+
+    /// 按格式和时区格式化时间
+    pub fn format(&self, timestamp: &DateTime<Local>) -> String {
+        if self.utc {
+            timestamp.with_timezone(&Utc).format(&self.pattern).to_string()
+        } else {
+            timestamp.format(&self.pattern).to_string()
+        }
+    }
+}
+
 /// 日志级别
 #[derive(Clone, Debug)]
 pub enum LogLevel {
@@ -71,35 +112,340 @@ impl LogLevel {
     }
 }
 
+/// 日志输出级别阈值（低于阈值的日志不输出也不保存）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogThreshold {
+    /// 输出所有日志（包括每个请求的转发和成功记录）
+    #[default]
+    Info,
+    /// 只输出警告和错误
+    Warn,
+    /// 只输出错误
+    Error,
+}
+
+impl LogThreshold {
+    /// 指定级别的日志是否达到阈值
+    pub fn allows(&self, level: &LogLevel) -> bool {
+        match self {
+            LogThreshold::Info => true,
+            LogThreshold::Warn => matches!(level, LogLevel::Warning | LogLevel::Error),
+            LogThreshold::Error => matches!(level, LogLevel::Error),
+        }
+    }
+}
+
+/// 终端界面布局
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum UiMode {
+    /// 带边框的完整状态表 + 日志区域
+    #[default]
+    Full,
+    /// 单行汇总 + 日志区域，适合窄终端或 tmux 小窗格
+    Compact,
+}
+
+/// Token 使用量的告警阈值：绝对Token数（如 `500k`）或占全部提供商使用量的百分比（如 `40%`）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenThreshold {
+    Tokens(u64),
+    Percent(f32),
+}
+
+impl TokenThreshold {
+    /// 使用量是否达到阈值
+    fn reached(&self, usage: u64, percentage: f32) -> bool {
+        match *self {
+            TokenThreshold::Tokens(tokens) => usage >= tokens,
+            TokenThreshold::Percent(percent) => usage > 0 && percentage >= percent,
+        }
+    }
+}
+
+impl std::str::FromStr for TokenThreshold {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(percent) = value.strip_suffix('%') {
+            let percent: f32 = percent.trim().parse().map_err(|_| format!("百分比无效: {}", value))?;
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(format!("百分比应在 0-100 之间: {}", value));
+            }
+            return Ok(TokenThreshold::Percent(percent));
+        }
+        let lower = value.to_ascii_lowercase();
+        let (number, multiplier) = if let Some(number) = lower.strip_suffix('k') {
+            (number, 1_000.0)
+        } else if let Some(number) = lower.strip_suffix('m') {
+            (number, 1_000_000.0)
+        } else {
+            (lower.as_str(), 1.0)
+        };
+        let number: f64 = number.trim().parse().map_err(|_| format!("Token数无效（如 500000、500k、2m 或 40%）: {}", value))?;
+        if !number.is_finite() || number <= 0.0 {
+            return Err(format!("Token数必须大于0: {}", value));
+        }
+        Ok(TokenThreshold::Tokens((number * multiplier) as u64))
+    }
+}
+
+/// 界面刷新间隔（10 FPS）
+pub const RENDER_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// 空闲时的界面刷新间隔（1 FPS）
+pub const IDLE_RENDER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// 输入事件检查间隔（约 60 FPS）
+pub const EVENT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(16);
+/// 空闲时的输入事件检查间隔，保证按键后能在 0.1 秒内恢复全速刷新
+pub const IDLE_EVENT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// 紧凑界面计算请求速率的时间窗口（秒）
+const RATE_WINDOW_SECS: i64 = 10;
+
+/// 状态表中单个服务商的显示字段（界面渲染和状态导出共用）
+struct ProviderRowFields {
+    status_text: String,
+    health_color: Color,
+    health_text: String,
+    rate_text: String,
+    can_request: bool,
+    token_usage: u64,
+    usage_percentage: f32,
+    token_text: String,
+    status_code_text: String,
+    last_status: Option<u16>,
+    consecutive_failures: u8,
+    failures_text: String,
+    latency_text: String,
+    action_text: String,
+    /// 已禁用或维护中，整行灰色显示
+    is_disabled: bool,
+    auth_failed: bool,
+}
+
+impl ProviderRowFields {
+    fn collect(index: usize, provider: &Provider, state: &ProxyState) -> Self {
+        let health_score = state.get_provider_health_score(provider.state_key());
+        let current_requests = state.get_current_requests(provider.state_key());
+        let can_request = state.can_request(provider.state_key());
+        let last_status = state.get_last_status_code(provider.state_key());
+        let is_disabled = state.interactive_manager.is_provider_disabled(provider.state_key());
+        let auth_failed = state.is_auth_failed(provider.state_key());
+        let dns_failed = state.is_dns_failed(provider.state_key());
+        let maintenance = state.interactive_manager.maintenance_remaining(provider.state_key());
+        
+        // 状态图标（认证失败优先显示，域名解析失败与服务质量无关，单独显示）
+        let (status_icon, health_color) = if maintenance.is_some() {
+            ("🔧", Color::DarkGrey)
+        } else if auth_failed {
+            ("❌🔑", Color::Red)
+        } else if dns_failed {
+            ("❌🌐", Color::Red)
+        } else {
+            match health_score {
+                90..=100 => ("🟢", Color::Green),
+                70..=89 => ("🟡", Color::Yellow),
+                40..=69 => ("🟠", Color::DarkYellow),
+                20..=39 => ("🔴", Color::Red),
+                _ => ("💀", Color::DarkRed),
+            }
+        };
+
+        // 获取token数据
+        let token_usage = state.get_token_usage(provider.state_key());
+        let usage_percentage = state.get_provider_usage_percentage(provider.state_key());
+        let token_text = if token_usage > 0 {
+            format!("{}({:.1}%)", format_tokens(token_usage), usage_percentage)
+        } else {
+            "0(0.0%)".to_string()
+        };
+        
+        let status_code_text = match last_status {
+            Some(0) if dns_failed => "DNS失败".to_string(),
+            Some(0) => "网络错误".to_string(),
+            Some(code) => code.to_string(),
+            None => "--".to_string(),
+        };
+        
+        // 连续失败次数，为0时留空
+        let consecutive_failures = state.get_consecutive_failures(provider.state_key());
+        let failures_text = if consecutive_failures > 0 {
+            format!("×{}", consecutive_failures)
+        } else {
+            String::new()
+        };
+        
+        let latency_text = match state.get_last_latency_ms(provider.state_key()) {
+            Some(ms) => format!("{}ms", ms),
+            None => "--".to_string(),
+        };
+        
+        Self {
+            // 状态列：图标 + 序号
+            status_text: format!("{} {:2}", status_icon, index + 1),
+            health_color,
+            health_text: format!("{}%", health_score),
+            rate_text: format!("{}/{} {}", current_requests, state.get_rate_limit(), if can_request { "✅" } else { "🚫" }),
+            can_request,
+            token_usage,
+            usage_percentage,
+            token_text,
+            status_code_text,
+            last_status,
+            consecutive_failures,
+            failures_text,
+            latency_text,
+            action_text: match maintenance {
+                Some(remaining) => format!("🔧{}", format_remaining(remaining)),
+                None if auth_failed => "🔑重置".to_string(),
+                None if is_disabled => "❌禁用".to_string(),
+                None => "✅启用".to_string(),
+            },
+            is_disabled: is_disabled || maintenance.is_some(),
+            auth_failed,
+        }
+    }
+}
+
+/// 将当前服务商状态表导出为纯文本文件（无ANSI颜色），返回文件路径
+pub fn export_status_table(providers: &[Provider], state: &ProxyState, server_info: &ServerInfo) -> io::Result<std::path::PathBuf> {
+    const COL_STATUS: usize = 8;
+    const COL_NAME: usize = 20;
+    const COL_HEALTH: usize = 8;
+    const COL_RATE: usize = 12;
+    const COL_TOKEN: usize = 15;
+    const COL_STATUS_CODE: usize = 10;
+    const COL_FAILURES: usize = 6;
+    const COL_LATENCY: usize = 10;
+    const COL_ACTION: usize = 10;
+    
+    let now = Local::now();
+    let mut content = String::new();
+    content.push_str(&format!("Auto Proxy 状态导出 {}\n", now.format("%Y-%m-%d %H:%M:%S")));
+    content.push_str(&format!("端口: {} | 速率限制: {}/分钟 | 运行时间: {} | 转移率: {:.1}%\n",
+        server_info.port,
+        server_info.rate_limit,
+        format_duration(server_info.uptime()),
+        state.get_failover_rate()
+    ));
+    content.push('\n');
+    
+    let header = [
+        TerminalUI::format_text_with_width("状态", COL_STATUS, TextAlign::Center),
+        TerminalUI::format_text_with_width("服务商名称", COL_NAME, TextAlign::Center),
+        TerminalUI::format_text_with_width("健康", COL_HEALTH, TextAlign::Center),
+        TerminalUI::format_text_with_width("速率限制", COL_RATE, TextAlign::Center),
+        TerminalUI::format_text_with_width("Token使用", COL_TOKEN, TextAlign::Center),
+        TerminalUI::format_text_with_width("状态码", COL_STATUS_CODE, TextAlign::Center),
+        TerminalUI::format_text_with_width("连败", COL_FAILURES, TextAlign::Center),
+        TerminalUI::format_text_with_width("延迟", COL_LATENCY, TextAlign::Center),
+        TerminalUI::format_text_with_width("操作", COL_ACTION, TextAlign::Center),
+    ];
+    content.push_str(header.concat().trim_end());
+    content.push('\n');
+    
+    for (i, provider) in providers.iter().enumerate() {
+        let fields = ProviderRowFields::collect(i, provider, state);
+        let row = [
+            TerminalUI::format_text_with_width(&fields.status_text, COL_STATUS, TextAlign::Center),
+            TerminalUI::format_text_with_width(&provider.name, COL_NAME, TextAlign::Center),
+            TerminalUI::format_text_with_width(&fields.health_text, COL_HEALTH, TextAlign::Right),
+            TerminalUI::format_text_with_width(&fields.rate_text, COL_RATE, TextAlign::Center),
+            TerminalUI::format_text_with_width(&fields.token_text, COL_TOKEN, TextAlign::Right),
+            TerminalUI::format_text_with_width(&fields.status_code_text, COL_STATUS_CODE, TextAlign::Center),
+            TerminalUI::format_text_with_width(&fields.failures_text, COL_FAILURES, TextAlign::Center),
+            TerminalUI::format_text_with_width(&fields.latency_text, COL_LATENCY, TextAlign::Right),
+            TerminalUI::format_text_with_width(&fields.action_text, COL_ACTION, TextAlign::Center),
+        ];
+        content.push_str(row.concat().trim_end());
+        content.push('\n');
+    }
+    
+    let path = std::path::PathBuf::from(format!("auto-proxy-status-{}.txt", now.format("%Y%m%d-%H%M%S")));
+    std::fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// 日志通道容量，界面来不及消费时丢弃新日志而不是阻塞请求处理
+const LOG_CHANNEL_CAPACITY: usize = 4096;
+
+/// 默认的降级横幅阈值（健康提供商占比，百分比）
+pub const DEFAULT_DEGRADED_THRESHOLD: u8 = 50;
+
 /// 终端UI管理器
 pub struct TerminalUI {
-    logs: Arc<Mutex<VecDeque<LogEntry>>>,
+    /// 界面渲染的日志缓冲区，只由UI线程访问
+    logs: VecDeque<LogEntry>,
     max_logs: usize,
+    log_tx: mpsc::Sender<LogEntry>,
+    log_rx: mpsc::Receiver<LogEntry>,
+    dropped_logs: Arc<AtomicU64>,
+    log_level: LogThreshold,
     is_initialized: bool,
     provider_buttons: Vec<ProviderButton>,
+    /// 方向键选中的服务商行
+    selected_provider: Option<usize>,
+    /// 是否显示选中服务商的详情面板（替代日志区域）
+    detail_open: bool,
+    /// 上次渲染时的服务商数量，用于限制选中行的范围
+    provider_count: usize,
+    /// 界面布局
+    mode: UiMode,
+    /// Token 列显示为黄色的阈值
+    token_warning: Option<TokenThreshold>,
+    /// Token 列显示为红色的阈值
+    token_critical: Option<TokenThreshold>,
+    /// 日志时间戳的显示格式
+    timestamp_format: TimestampFormat,
+    /// 是否在状态表中显示服务商地址列（协议和主机，不含路径）
+    show_hosts: bool,
+    /// 最近一次键盘、鼠标或终端尺寸变化事件的时间
+    last_input: std::time::Instant,
+    /// 无请求和输入超过该时长后进入低频刷新（None 表示始终全速刷新）
+    idle_after: Option<std::time::Duration>,
+    /// 健康提供商占比低于该百分比时在状态栏显示降级横幅（0 表示不显示）
+    degraded_threshold: u8,
 }
 
 impl TerminalUI {
     pub fn new() -> io::Result<Self> {
+        let (log_tx, log_rx) = mpsc::channel(LOG_CHANNEL_CAPACITY);
         Ok(Self {
-            logs: Arc::new(Mutex::new(VecDeque::new())),
+            logs: VecDeque::new(),
             max_logs: 100,
+            log_tx,
+            log_rx,
+            dropped_logs: Arc::new(AtomicU64::new(0)),
+            log_level: LogThreshold::default(),
             is_initialized: false,
             provider_buttons: Vec::new(),
+            selected_provider: None,
+            detail_open: false,
+            provider_count: 0,
+            mode: UiMode::default(),
+            token_warning: None,
+            token_critical: None,
+            timestamp_format: TimestampFormat::default(),
+            show_hosts: false,
+            last_input: std::time::Instant::now(),
+            idle_after: None,
+            degraded_threshold: DEFAULT_DEGRADED_THRESHOLD,
         })
     }
 
     /// 统一的表格行格式化函数（无分隔符，纯固定宽度）
-    fn format_table_row(
-        status: &str,
-        name: &str, 
-        health: &str,
-        rate: &str,
-        token: &str,
-        status_code: &str,
-        action: &str,
-    ) -> String {
-        format!("{}{}{}{}{}{}{}", status, name, health, rate, token, status_code, action)
+    ///
+    /// 各列需已通过 `format_text_with_width` 填充到固定宽度。
+    fn format_table_row(columns: &[&str]) -> String {
+        columns.concat()
+    }
+
+    /// 格式化地址列，宽度为 0（未显示地址列）时返回空字符串
+    fn format_host_column(text: &str, width: usize) -> String {
+        if width == 0 {
+            return String::new();
+        }
+        Self::format_text_with_width(text, width, TextAlign::Center)
     }
 
     /// 格式化文本到指定宽度（考虑中文字符和emoji的实际显示宽度）
@@ -116,7 +462,7 @@ impl TerminalUI {
                     c if c as u32 >= 0x4E00 && c as u32 <= 0x9FFF => 2,
                     _ => 1,
                 };
-                if current_width + ch_width <= width - 1 {  // 为省略号留空间
+                if current_width + ch_width < width {  // 为省略号留空间
                     result.push(ch);
                     current_width += ch_width;
                 } else {
@@ -174,6 +520,12 @@ impl TerminalUI {
             return Ok(());
         }
 
+        // panic 钩子已经恢复过终端，不再清屏，以免覆盖 panic 信息
+        if TERMINAL_RESTORED_BY_PANIC.load(Ordering::SeqCst) {
+            self.is_initialized = false;
+            return Ok(());
+        }
+
         // 确保清理顺序正确，避免终端状态混乱
         let cleanup_result = execute!(
             io::stdout(),
@@ -199,11 +551,41 @@ impl TerminalUI {
             },
             (_, Err(e)) => {
                 eprintln!("⚠️ Raw mode disable failed: {}", e);
-                Err(io::Error::new(io::ErrorKind::Other, e))
+                Err(io::Error::other(e))
             }
         }
     }
 
+    /// 暂时离开界面，在普通屏幕上打印完整的提供商健康状态汇总，按任意键后恢复界面
+    ///
+    /// 汇总留在普通屏幕的滚动缓冲区中，退出后仍可复制。等待按键时会阻塞当前线程。
+    pub fn show_providers_summary(&mut self, providers: &[Provider], state: &ProxyState) -> io::Result<()> {
+        if !self.is_initialized {
+            return Ok(());
+        }
+
+        // 打印前退出原始模式，否则换行不会回到行首
+        execute!(io::stdout(), DisableMouseCapture, terminal::LeaveAlternateScreen, cursor::Show)?;
+        terminal::disable_raw_mode()?;
+        state.print_providers_health_summary(providers);
+        println!("按任意键返回界面...");
+        io::stdout().flush()?;
+
+        // 无论等待按键是否出错，都重新进入原始模式和备用屏幕
+        terminal::enable_raw_mode()?;
+        let wait_result = loop {
+            match event::read() {
+                Ok(Event::Key(_)) => break Ok(()),
+                Ok(_) => continue,
+                Err(e) => break Err(e),
+            }
+        };
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide, EnableMouseCapture)?;
+        self.clear_screen()?;
+        self.last_input = std::time::Instant::now();
+        wait_result
+    }
+
     /// 清屏
     fn clear_screen(&self) -> io::Result<()> {
         execute!(
@@ -216,39 +598,62 @@ impl TerminalUI {
 
     /// 添加日志条目
     pub fn log(&self, level: LogLevel, message: String) {
-        let entry = LogEntry {
-            timestamp: Local::now(),
-            level,
-            message,
-        };
+        self.logger().log(level, message);
+    }
 
-        let mut logs = match self.logs.lock() {
-            Ok(logs) => logs,
-            Err(poisoned) => {
-                eprintln!("⚠️ UI logs mutex poisoned, recovering...");
-                poisoned.into_inner()
-            }
-        };
-        logs.push_back(entry);
+    /// 将通道中积压的日志移入渲染缓冲区，超出容量时丢弃最旧的日志
+    fn drain_logs(&mut self) {
+        while let Ok(entry) = self.log_rx.try_recv() {
+            self.logs.push_back(entry);
+        }
+        
+        let dropped = self.dropped_logs.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            self.logs.push_back(LogEntry {
+                timestamp: Local::now(),
+                level: LogLevel::Warning,
+                message: format!("⚠️ 日志过多，已丢弃 {} 条", dropped),
+            });
+        }
         
         // 保持日志数量限制
-        while logs.len() > self.max_logs {
-            logs.pop_front();
+        while self.logs.len() > self.max_logs {
+            self.logs.pop_front();
         }
     }
 
     /// 渲染整个界面
     pub fn render(&mut self, providers: &[Provider], state: &ProxyState, server_info: &ServerInfo) -> io::Result<()> {
+        self.drain_logs();
+        
+        if TERMINAL_RESTORED_BY_PANIC.load(Ordering::SeqCst) {
+            return Err(io::Error::other("程序发生 panic，终端已恢复"));
+        }
+        
         if !self.is_initialized {
             return Ok(());
         }
 
         let (cols, rows) = terminal::size()?;
         
+        if self.mode == UiMode::Compact {
+            return self.render_compact(providers, state, server_info, cols, rows);
+        }
+        
+        // 服务商数量变化时保持选中行有效
+        self.provider_count = providers.len();
+        if self.selected_provider.is_some_and(|i| i >= providers.len()) {
+            self.selected_provider = providers.len().checked_sub(1);
+        }
+        if self.selected_provider.is_none() {
+            self.detail_open = false;
+        }
+        
         // 动态计算状态栏高度 - 显示所有提供商
         let base_height = 7; // 基本信息行数（顶部边框、服务器信息行、分隔线、提供商概览行、分隔线、表头行、底部边框）
+        let banner_lines = usize::from(state.pool_stats(providers).is_degraded(self.degraded_threshold)); // 降级横幅行
         let provider_lines = providers.len(); // 显示所有提供商
-        let dynamic_status_height = (base_height + provider_lines) as u16;
+        let dynamic_status_height = (base_height + banner_lines + provider_lines) as u16;
         
         let mut stdout = io::stdout();
 
@@ -269,14 +674,62 @@ impl TerminalUI {
         // 绘制帮助信息
         queue!(stdout, MoveTo(0, dynamic_status_height + 1))?;
         queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
-        queue!(stdout, Print("按键: [Q]退出 | 鼠标: 点击[启用/禁用]按钮切换服务商状态"))?;
+        let help_text = if self.detail_open {
+            "按键: [Esc]返回 [↑/↓]切换服务商 [Q]退出"
+        } else {
+            "按键: [Q]退出 [S]导出状态 [P]汇总 [H]地址 [↑/↓]选择 [Enter]详情 [M]维护 | 鼠标: 点击[启用/禁用]按钮切换服务商状态"
+        };
+        queue!(stdout, Print(help_text))?;
         queue!(stdout, ResetColor)?;
+        queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
 
-        // 绘制日志区域
+        // 绘制日志区域（打开详情面板时改为显示选中服务商的详情）
         let log_start_row = dynamic_status_height + 2;
         let log_height = rows.saturating_sub(log_start_row);
-        self.render_logs(&mut stdout, log_start_row, log_height, cols)?;
+        match self.selected_provider.filter(|_| self.detail_open).and_then(|i| providers.get(i)) {
+            Some(provider) => self.render_detail(&mut stdout, provider, state, log_start_row, log_height, cols)?,
+            None => self.render_logs(&mut stdout, log_start_row, log_height, cols)?,
+        }
+
+        stdout.flush()?;
+        Ok(())
+    }
 
+    /// 紧凑界面：一行汇总 + 一行帮助 + 日志区域，不显示服务商表格
+    fn render_compact(&mut self, providers: &[Provider], state: &ProxyState, server_info: &ServerInfo, cols: u16, rows: u16) -> io::Result<()> {
+        // 紧凑界面没有服务商行，不响应选择和按钮点击
+        self.provider_buttons.clear();
+        self.selected_provider = None;
+        self.detail_open = false;
+        
+        let summary = state.pool_stats(providers);
+        let network_status = server_info.get_network_status();
+        let rate = state.recent_request_count(RATE_WINDOW_SECS) as f64 / RATE_WINDOW_SECS as f64;
+        let summary_text = format!("健康 {}/{} | 平均 {}% | {:.1}/s | {} {}",
+            summary.healthy,
+            summary.total,
+            summary.avg_health,
+            rate,
+            network_status.status_icon(),
+            network_status.status_text(),
+        );
+        
+        let mut stdout = io::stdout();
+        queue!(stdout, MoveTo(0, 0))?;
+        let summary_color = if summary.healthy > 0 { Color::Green } else { Color::Red };
+        queue!(stdout, SetForegroundColor(summary_color))?;
+        queue!(stdout, Print(Self::truncate_to_width(&summary_text, cols as usize)))?;
+        queue!(stdout, ResetColor)?;
+        queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
+        
+        queue!(stdout, MoveTo(0, 1))?;
+        queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+        queue!(stdout, Print(Self::truncate_to_width("按键: [Q]退出 [S]导出状态 [P]汇总", cols as usize)))?;
+        queue!(stdout, ResetColor)?;
+        queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
+        
+        self.render_logs(&mut stdout, 2, rows.saturating_sub(2), cols)?;
+        
         stdout.flush()?;
         Ok(())
     }
@@ -346,26 +799,45 @@ impl TerminalUI {
         queue!(stdout, Print("┤"))?;
         queue!(stdout, ResetColor)?;
 
+        let summary = state.pool_stats(providers);
+        
+        // 降级横幅：健康提供商占比低于阈值时在概览上方占用一行，恢复后自动消失
+        let degraded = summary.is_degraded(self.degraded_threshold);
+        let offset: u16 = if degraded { 1 } else { 0 };
+        if degraded {
+            queue!(stdout, MoveTo(0, 3))?;
+            queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+            queue!(stdout, Print("│"))?;
+            queue!(stdout, ResetColor)?;
+            
+            let banner_text = format!(" ⚠️  服务商池降级: 仅 {}/{} 健康（低于 {}%）",
+                summary.healthy, summary.total, self.degraded_threshold);
+            let banner_text = Self::truncate_to_width(&banner_text, (cols - 2) as usize);
+            queue!(stdout, SetBackgroundColor(Color::DarkRed), SetForegroundColor(Color::White), SetAttribute(Attribute::Bold))?;
+            queue!(stdout, Print(&banner_text))?;
+            // 横幅背景色铺满整行
+            let used_width = display_width(&banner_text);
+            if used_width < (cols - 2) as usize {
+                queue!(stdout, Print(" ".repeat((cols - 2) as usize - used_width)))?;
+            }
+            queue!(stdout, SetAttribute(Attribute::Reset), ResetColor)?;
+            
+            queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+            queue!(stdout, Print("│"))?;
+            queue!(stdout, ResetColor)?;
+        }
+        
         // 第2行：提供商概览（带边框）
-        queue!(stdout, MoveTo(0, 3))?;
+        queue!(stdout, MoveTo(0, 3 + offset))?;
         queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
         queue!(stdout, Print("│"))?;
         queue!(stdout, ResetColor)?;
         
-        let healthy_count = providers.iter()
-            .filter(|p| state.is_provider_healthy(&p.name))
-            .count();
-        
-        let total_health: u32 = providers.iter()
-            .map(|p| state.get_provider_health_score(&p.name) as u32)
-            .sum();
-        let avg_health = if providers.is_empty() { 0 } else { total_health / providers.len() as u32 };
-
-        let overview_text = format!(" 📊 提供商: {}/{} 健康 | 平均健康度: {}% | 状态: ", 
-            healthy_count, providers.len(), avg_health);
+        let overview_text = format!(" 📊 提供商: {}/{} 健康 | 平均健康度: {}% | 转移率: {:.1}% | 状态: ", 
+            summary.healthy, summary.total, summary.avg_health, summary.failover_rate);
         queue!(stdout, Print(overview_text.clone()))?;
         
-        let status_text = if healthy_count > 0 {
+        let status_text = if summary.healthy > 0 {
             queue!(stdout, SetForegroundColor(Color::Green))?;
             queue!(stdout, Print("正常"))?;
             "正常"
@@ -398,10 +870,13 @@ impl TerminalUI {
         const COL_RATE: usize = 12;       // " 5/10  ✅  "
         const COL_TOKEN: usize = 15;      // "1.2K(12.3%)        "
         const COL_STATUS_CODE: usize = 8; // " 200    "
+        const COL_FAILURES: usize = 6;    // " ×3   "
         const COL_ACTION: usize = 10;     // "  ✅启用  "
+        const COL_HOST: usize = 28;       // "https://api.example.com" （按 H 显示）
+        let col_host = if self.show_hosts { COL_HOST } else { 0 };
 
         // 第4行：分隔线
-        queue!(stdout, MoveTo(0, 4))?;
+        queue!(stdout, MoveTo(0, 4 + offset))?;
         queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
         queue!(stdout, Print("├"))?;
         for _ in 1..(cols - 1) {
@@ -413,28 +888,30 @@ impl TerminalUI {
         // 第5行开始：表头 + 数据行，使用统一的固定宽度渲染函数
         
         // 渲染表头行
-        queue!(stdout, MoveTo(0, 5))?;
+        queue!(stdout, MoveTo(0, 5 + offset))?;
         queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
         queue!(stdout, Print("│"))?;
         queue!(stdout, ResetColor)?;
         
         // 表头内容使用新的格式化函数
-        let header_content = Self::format_table_row(
+        let header_content = Self::format_table_row(&[
             &Self::format_text_with_width("状态", COL_STATUS, TextAlign::Center),
             &Self::format_text_with_width("服务商名称", COL_NAME, TextAlign::Center),
+            &Self::format_host_column("地址", col_host),
             &Self::format_text_with_width("健康", COL_HEALTH, TextAlign::Center),
             &Self::format_text_with_width("速率限制", COL_RATE, TextAlign::Center),
             &Self::format_text_with_width("Token使用", COL_TOKEN, TextAlign::Center),
             &Self::format_text_with_width("状态码", COL_STATUS_CODE, TextAlign::Center),
+            &Self::format_text_with_width("连败", COL_FAILURES, TextAlign::Center),
             &Self::format_text_with_width("操作", COL_ACTION, TextAlign::Center),
-        );
+        ]);
         
         queue!(stdout, SetForegroundColor(Color::White))?;
         queue!(stdout, Print(header_content))?;
         queue!(stdout, ResetColor)?;
         
         // 计算固定表格宽度（无分隔符）- 现在这个宽度是准确的，因为我们的格式化函数保证了每列的宽度
-        let fixed_table_width = COL_STATUS + COL_NAME + COL_HEALTH + COL_RATE + COL_TOKEN + COL_STATUS_CODE + COL_ACTION;
+        let fixed_table_width = COL_STATUS + COL_NAME + col_host + COL_HEALTH + COL_RATE + COL_TOKEN + COL_STATUS_CODE + COL_FAILURES + COL_ACTION;
         
         // 填充表头的剩余空间（不需要条件检查，直接填充到边框位置）
         let remaining_space = if cols >= 2 { (cols - 2) as usize } else { 0 };
@@ -451,7 +928,7 @@ impl TerminalUI {
         
         // 第6行开始：数据行（与表头使用统一的固定宽度布局）
         for (i, provider) in providers.iter().enumerate() {
-            let row = 6 + i as u16;
+            let row = 6 + offset + i as u16;
             queue!(stdout, MoveTo(0, row))?;
             
             // 左边框
@@ -459,68 +936,38 @@ impl TerminalUI {
             queue!(stdout, Print("│"))?;
             queue!(stdout, ResetColor)?;
             
-            let health_score = state.get_provider_health_score(&provider.name);
-            let current_requests = state.get_current_requests(&provider.name);
-            let can_request = state.can_request(&provider.name);
-            let last_status = state.get_last_status_code(&provider.name);
-            let is_disabled = state.interactive_manager.is_provider_disabled(&provider.name);
+            // 选中行反色显示
+            let is_selected = self.selected_provider == Some(i);
+            if is_selected {
+                queue!(stdout, SetAttribute(Attribute::Reverse))?;
+            }
             
-            // 状态图标
-            let (status_icon, health_color) = match health_score {
-                90..=100 => ("🟢", Color::Green),
-                70..=89 => ("🟡", Color::Yellow),
-                40..=69 => ("🟠", Color::DarkYellow),
-                20..=39 => ("🔴", Color::Red),
-                _ => ("💀", Color::DarkRed),
-            };
-
-            // 获取token数据
-            let token_usage = state.get_token_usage(&provider.name);
-            let usage_percentage = state.get_provider_usage_percentage(&provider.name);
+            let fields = ProviderRowFields::collect(i, provider, state);
+            let health_color = fields.health_color;
+            let can_request = fields.can_request;
+            let last_status = fields.last_status;
+            let is_disabled = fields.is_disabled;
             
             // 使用新的格式化函数处理各个字段
-            // 状态列：图标 + 序号
-            let status_field = format!("{} {:2}", status_icon, i + 1);
-            let status_display = Self::format_text_with_width(&status_field, COL_STATUS, TextAlign::Center);
-            
-            // 服务商名称列
+            let status_display = Self::format_text_with_width(&fields.status_text, COL_STATUS, TextAlign::Center);
             let name_display = Self::format_text_with_width(&provider.name, COL_NAME, TextAlign::Center);
-            
+            let host_display = Self::format_host_column(&provider.base_origin(), col_host);
             // 健康度列 - 使用右对齐
-            let health_text = format!("{}%", health_score);
-            let health_display = Self::format_text_with_width(&health_text, COL_HEALTH, TextAlign::Right);
-            
-            // 速率限制列
-            let rate_text = format!("{}/{} {}", current_requests, state.get_rate_limit(), if can_request { "✅" } else { "🚫" });
-            let rate_display = Self::format_text_with_width(&rate_text, COL_RATE, TextAlign::Center);
-            
+            let health_display = Self::format_text_with_width(&fields.health_text, COL_HEALTH, TextAlign::Right);
+            let rate_display = Self::format_text_with_width(&fields.rate_text, COL_RATE, TextAlign::Center);
             // Token使用列 - 使用右对齐
-            let token_text = if token_usage > 0 {
-                format!("{}({:.1}%)", format_tokens(token_usage), usage_percentage)
-            } else {
-                "0(0.0%)".to_string()
-            };
-            let token_display = Self::format_text_with_width(&token_text, COL_TOKEN, TextAlign::Right);
-            
-            // 状态码列
-            let status_code_text = match last_status {
-                Some(0) => "网络错误".to_string(),
-                Some(code) => code.to_string(),
-                None => "--".to_string(),
-            };
-            let status_code_display = Self::format_text_with_width(&status_code_text, COL_STATUS_CODE, TextAlign::Center);
-            
-            // 操作列
-            let action_text = if is_disabled { "❌禁用" } else { "✅启用" };
-            let action_display = Self::format_text_with_width(action_text, COL_ACTION, TextAlign::Center);
+            let token_display = Self::format_text_with_width(&fields.token_text, COL_TOKEN, TextAlign::Right);
+            let status_code_display = Self::format_text_with_width(&fields.status_code_text, COL_STATUS_CODE, TextAlign::Center);
+            let failures_display = Self::format_text_with_width(&fields.failures_text, COL_FAILURES, TextAlign::Center);
+            let action_display = Self::format_text_with_width(&fields.action_text, COL_ACTION, TextAlign::Center);
 
             // 使用统一的行格式化函数（无分隔符，纯固定宽度）
             if is_disabled {
                 queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
-                let row_content = Self::format_table_row(
-                    &status_display, &name_display, &health_display, &rate_display, 
-                    &token_display, &status_code_display, &action_display
-                );
+                let row_content = Self::format_table_row(&[
+                    &status_display, &name_display, &host_display, &health_display, &rate_display, 
+                    &token_display, &status_code_display, &failures_display, &action_display
+                ]);
                 queue!(stdout, Print(row_content))?;
                 queue!(stdout, ResetColor)?;
             } else {
@@ -531,6 +978,10 @@ impl TerminalUI {
                 queue!(stdout, Print(name_display.clone()))?;
                 queue!(stdout, ResetColor)?;
                 
+                queue!(stdout, SetForegroundColor(Color::DarkCyan))?;
+                queue!(stdout, Print(host_display))?;
+                queue!(stdout, ResetColor)?;
+                
                 queue!(stdout, SetForegroundColor(health_color))?;
                 queue!(stdout, Print(health_display.clone()))?;
                 queue!(stdout, ResetColor)?;
@@ -543,14 +994,14 @@ impl TerminalUI {
                 queue!(stdout, Print(rate_display.clone()))?;
                 queue!(stdout, ResetColor)?;
                 
-                queue!(stdout, SetForegroundColor(Color::Magenta))?;
+                queue!(stdout, SetForegroundColor(self.token_color(fields.token_usage, fields.usage_percentage)))?;
                 queue!(stdout, Print(token_display.clone()))?;
                 queue!(stdout, ResetColor)?;
                 
                 let status_color = if let Some(code) = last_status {
                     if code == 0 { Color::DarkGrey }
-                    else if code >= 200 && code < 300 { Color::Green }
-                    else if code >= 400 && code < 500 { Color::Yellow }
+                    else if (200..300).contains(&code) { Color::Green }
+                    else if (400..500).contains(&code) { Color::Yellow }
                     else if code >= 500 { Color::Red }
                     else { Color::DarkGrey }
                 } else {
@@ -560,7 +1011,19 @@ impl TerminalUI {
                 queue!(stdout, Print(status_code_display.clone()))?;
                 queue!(stdout, ResetColor)?;
                 
-                if is_disabled {
+                // 超过熔断阈值时标红，刚开始失败时标黄
+                let failures_color = if fields.consecutive_failures > CIRCUIT_BREAK_FAILURES {
+                    Color::Red
+                } else {
+                    Color::Yellow
+                };
+                queue!(stdout, SetForegroundColor(failures_color))?;
+                queue!(stdout, Print(failures_display.clone()))?;
+                queue!(stdout, ResetColor)?;
+                
+                if fields.auth_failed {
+                    queue!(stdout, SetForegroundColor(Color::Yellow))?;
+                } else if is_disabled {
                     queue!(stdout, SetForegroundColor(Color::DarkRed))?;
                 } else {
                     queue!(stdout, SetForegroundColor(Color::Green))?;
@@ -570,16 +1033,21 @@ impl TerminalUI {
             }
 
             // 计算按钮位置（基于纯固定列宽，无分隔符）
-            let button_start_col = (COL_STATUS + COL_NAME + COL_HEALTH + COL_RATE + COL_TOKEN + COL_STATUS_CODE + 1) as u16; // 到操作列开始的位置
+            let button_start_col = (COL_STATUS + COL_NAME + col_host + COL_HEALTH + COL_RATE + COL_TOKEN + COL_STATUS_CODE + COL_FAILURES + 1) as u16; // 到操作列开始的位置
             let button_end_col = button_start_col + COL_ACTION as u16;
             
             self.provider_buttons.push(ProviderButton {
                 provider_name: provider.name.clone(),
-                row: 6 + i as u16,  // 提供商数据从第6行开始
+                provider_key: provider.state_key().to_string(),
+                row,  // 提供商数据从第6行开始（有降级横幅时下移一行）
                 start_col: button_start_col,
                 end_col: button_end_col,
             });
             
+            if is_selected {
+                queue!(stdout, SetAttribute(Attribute::NoReverse))?;
+            }
+            
             // 填充到固定表格宽度后的剩余空间（使用与表头相同的逻辑）
             let remaining_space = if cols >= 2 { (cols - 2) as usize } else { 0 };
             if remaining_space > fixed_table_width {
@@ -615,7 +1083,7 @@ impl TerminalUI {
         height: u16,
         cols: u16,
     ) -> io::Result<()> {
-        let logs = self.logs.lock().unwrap();
+        let logs = &self.logs;
         
         if logs.is_empty() {
             return Ok(());
@@ -638,7 +1106,8 @@ impl TerminalUI {
 
             // 时间戳 - 使用更亮的颜色
             queue!(stdout, SetForegroundColor(Color::White))?;
-            queue!(stdout, Print(log_entry.timestamp.format("%H:%M:%S")))?;
+            let timestamp = self.timestamp_format.format(&log_entry.timestamp);
+            queue!(stdout, Print(&timestamp))?;
             queue!(stdout, ResetColor)?;
 
             // 图标和消息
@@ -648,9 +1117,10 @@ impl TerminalUI {
             queue!(stdout, SetForegroundColor(log_entry.level.color()))?;
             
             // 截断过长的消息
-            let max_msg_len = cols.saturating_sub(12) as usize; // 为时间戳和图标留空间
-            let message = if log_entry.message.len() > max_msg_len {
-                format!("{}...", &log_entry.message[..max_msg_len.saturating_sub(3)])
+            // 按显示宽度截断，避免在多字节字符中间切开
+            let max_msg_width = (cols as usize).saturating_sub(display_width(&timestamp) + 4); // 为时间戳和图标留空间
+            let message = if display_width(&log_entry.message) > max_msg_width {
+                format!("{}...", Self::truncate_to_width(&log_entry.message, max_msg_width.saturating_sub(3)))
             } else {
                 log_entry.message.clone()
             };
@@ -659,12 +1129,7 @@ impl TerminalUI {
             queue!(stdout, ResetColor)?;
 
             // 清除行的剩余部分
-            let used_length = 12 + log_entry.message.len().min(max_msg_len);
-            if used_length < cols as usize {
-                for _ in 0..(cols as usize - used_length) {
-                    queue!(stdout, Print(" "))?;
-                }
-            }
+            queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
         }
 
         // 清除日志区域的空白行
@@ -682,49 +1147,177 @@ impl TerminalUI {
         Ok(())
     }
 
-    /// 检查是否有退出键按下
+    /// 绘制选中服务商的详情面板
+    fn render_detail(
+        &self,
+        stdout: &mut io::Stdout,
+        provider: &Provider,
+        state: &ProxyState,
+        start_row: u16,
+        height: u16,
+        cols: u16,
+    ) -> io::Result<()> {
+        let health_score = state.get_provider_health_score(provider.state_key());
+        let health_history = state.get_health_history(provider.state_key());
+        let history_text = if health_history.is_empty() {
+            "--".to_string()
+        } else {
+            format!("{} (最近 {} 次)", health_sparkline(&health_history), health_history.len())
+        };
+        
+        let success_rate_text = match state.get_recent_outcomes(provider.state_key()) {
+            (_, 0) => "--".to_string(),
+            (successes, total) => format!("{:.1}% (最近 {} 次请求中成功 {} 次)",
+                state.get_success_rate(provider.state_key()), total, successes),
+        };
+        
+        let status_text = match state.get_last_status_code(provider.state_key()) {
+            Some(0) => "网络错误".to_string(),
+            Some(code) => code.to_string(),
+            None => "--".to_string(),
+        };
+        let status_flags = if let Some(remaining) = state.interactive_manager.maintenance_remaining(provider.state_key()) {
+            format!(" | 🔧 维护中，剩余 {}", format_remaining(remaining))
+        } else if state.is_auth_failed(provider.state_key()) {
+            " | ❌🔑 认证失败".to_string()
+        } else if state.is_dns_failed(provider.state_key()) {
+            " | ❌🌐 域名解析失败".to_string()
+        } else if state.interactive_manager.is_provider_disabled(provider.state_key()) {
+            " | ❌ 已禁用".to_string()
+        } else {
+            String::new()
+        };
+        
+        let total_tokens = state.get_token_usage(provider.state_key());
+        let input_tokens = state.get_input_token_usage(provider.state_key());
+        
+        let last_latency = state.get_last_latency_ms(provider.state_key())
+            .map(|ms| format!("{}ms", ms))
+            .unwrap_or_else(|| "--".to_string());
+        let percentiles_text = match state.get_latency_percentiles(provider.state_key()) {
+            Some(p) => format!("P50 {}ms | P95 {}ms | P99 {}ms (最近 {} 次请求)", p.p50, p.p95, p.p99, p.samples),
+            None => "P50/P95/P99 --".to_string(),
+        };
+        
+        let lines: Vec<(Color, String)> = vec![
+            (Color::Cyan, format!(" 🔍 服务商详情: {}", provider.name)),
+            (Color::White, format!("    Token:      {}", provider.masked_token())),
+            (Color::White, format!("    Base URL:   {}", provider.base_url)),
+            (Color::White, format!("    Key 类型:   {}", provider.key_type)),
+            (Color::Green, format!("    健康度:     {}% | 连续失败: {} | 最后状态码: {}{}",
+                health_score, state.get_consecutive_failures(provider.state_key()), status_text, status_flags)),
+            (Color::Green, format!("    健康历史:   {}", history_text)),
+            (Color::Green, format!("    成功率:     {}", success_rate_text)),
+            (Color::Magenta, format!("    Token使用:  总计 {} | 输入 {} | 输出 {}",
+                format_tokens(total_tokens), format_tokens(input_tokens), format_tokens(total_tokens.saturating_sub(input_tokens)))),
+            (Color::Yellow, format!("    耗时:       最近 {} | {}", last_latency, percentiles_text)),
+        ];
+        
+        for i in 0..height {
+            queue!(stdout, MoveTo(0, start_row + i))?;
+            if let Some((color, line)) = lines.get(i as usize) {
+                queue!(stdout, SetForegroundColor(*color))?;
+                queue!(stdout, Print(Self::truncate_to_width(line, cols as usize)))?;
+                queue!(stdout, ResetColor)?;
+            }
+            queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
+        }
+        
+        Ok(())
+    }
+
+    /// 按显示宽度截断文本（不填充）
+    fn truncate_to_width(text: &str, width: usize) -> String {
+        let mut result = String::new();
+        let mut current_width = 0;
+        for ch in text.chars() {
+            let ch_width = display_width(&ch.to_string());
+            if current_width + ch_width > width {
+                break;
+            }
+            result.push(ch);
+            current_width += ch_width;
+        }
+        result
+    }
+
+    /// 移动选中的服务商行，未选中时从第一行（向下）或最后一行（向上）开始
+    fn move_selection(&mut self, down: bool) {
+        if self.provider_count == 0 {
+            return;
+        }
+        let last = self.provider_count - 1;
+        self.selected_provider = Some(match self.selected_provider {
+            None if down => 0,
+            None => last,
+            Some(i) if down => (i + 1).min(last),
+            Some(i) => i.saturating_sub(1),
+        });
+    }
+
     /// 检查键盘输入并返回动作
+    ///
+    /// 一次处理完当前已到达的所有事件：选择行、打开/关闭详情等界面内部操作就地处理，
+    /// 遇到需要调用方处理的动作（退出、导出、打印汇总、切换服务商、设置维护）时立即返回，其余事件留到下次调用；
+    /// 鼠标移动等无关事件直接丢弃。没有待处理事件时不会阻塞。
     pub fn check_key_input(&mut self) -> io::Result<String> {
         if !self.is_initialized {
             return Ok("none".to_string());
         }
 
-        // 非阻塞检查键盘输入，使用很短的超时避免阻塞
-        if let Ok(has_event) = event::poll(std::time::Duration::from_millis(1)) {
-            if !has_event {
-                return Ok("none".to_string());
+        while matches!(event::poll(std::time::Duration::ZERO), Ok(true)) {
+            let action = match event::read() {
+                Ok(event) => self.handle_event(event),
+                // 事件读取错误，忽略
+                Err(_) => None,
+            };
+            if let Some(action) = action {
+                return Ok(action);
             }
-        } else {
-            return Ok("none".to_string());
         }
+        Ok("none".to_string())
+    }
 
-        match event::read() {
-            Ok(Event::Key(KeyEvent { code, modifiers, .. })) => {
+    /// 处理单个输入事件，返回需要调用方处理的动作
+    fn handle_event(&mut self, event: Event) -> Option<String> {
+        self.last_input = std::time::Instant::now();
+        match event {
+            Event::Key(KeyEvent { code, modifiers, .. }) => {
                 match code {
-                    KeyCode::Char('q') | KeyCode::Char('Q') => return Ok("exit".to_string()),
-                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok("exit".to_string()),
-                    KeyCode::Esc => return Ok("exit".to_string()),
+                    KeyCode::Char('q') | KeyCode::Char('Q') => return Some("exit".to_string()),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Some("exit".to_string()),
+                    // 详情面板打开时 Esc 只关闭面板
+                    KeyCode::Esc if self.detail_open => self.detail_open = false,
+                    KeyCode::Esc => return Some("exit".to_string()),
+                    KeyCode::Char('s') | KeyCode::Char('S') => return Some("export".to_string()),
+                    KeyCode::Char('p') | KeyCode::Char('P') => return Some("summary".to_string()),
+                    KeyCode::Char('h') | KeyCode::Char('H') if self.mode == UiMode::Full => self.show_hosts = !self.show_hosts,
+                    // 紧凑界面没有服务商表格，不支持选择和详情面板
+                    KeyCode::Up if self.mode == UiMode::Full => self.move_selection(false),
+                    KeyCode::Down if self.mode == UiMode::Full => self.move_selection(true),
+                    KeyCode::Enter if self.selected_provider.is_some() => self.detail_open = true,
+                    // 服务商按钮与表格行一一对应，按选中行取得服务商
+                    KeyCode::Char('m') | KeyCode::Char('M') => {
+                        if let Some(button) = self.selected_provider.and_then(|i| self.provider_buttons.get(i)) {
+                            return Some(format!("maintenance:{}", button.provider_key));
+                        }
+                    }
                     _ => {}
                 }
             },
-            Ok(Event::Mouse(MouseEvent { kind, column, row, .. })) => {
-                if let MouseEventKind::Down(MouseButton::Left) = kind {
-                    // 检查点击是否在某个服务商按钮上
-                    for button in &self.provider_buttons {
-                        if row == button.row && column >= button.start_col && column <= button.end_col {
-                            return Ok(format!("toggle:{}", button.provider_name));
-                        }
+            Event::Mouse(MouseEvent { kind: MouseEventKind::Down(MouseButton::Left), column, row, .. }) => {
+                // 检查点击是否在某个服务商按钮上
+                for button in &self.provider_buttons {
+                    if row == button.row && column >= button.start_col && column <= button.end_col {
+                        return Some(format!("toggle:{}", button.provider_key));
                     }
                 }
             },
-            Ok(_) => {
-                // 忽略其他事件
-            },
-            Err(_) => {
-                // 事件读取错误，忽略
+            _ => {
+                // 忽略其他事件（鼠标移动、滚动、窗口大小变化等）
             }
         }
-        Ok("none".to_string())
+        None
     }
 
     pub fn check_exit_key(&mut self) -> io::Result<bool> {
@@ -734,26 +1327,82 @@ impl TerminalUI {
 
         // 非阻塞检查键盘输入
         if event::poll(std::time::Duration::from_millis(0))? {
-            match event::read()? {
-                Event::Key(KeyEvent { code, modifiers, .. }) => {
-                    match code {
-                        KeyCode::Char('q') | KeyCode::Char('Q') => return Ok(true),
-                        KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(true),
-                        KeyCode::Esc => return Ok(true),
-                        _ => {}
-                    }
-                },
-                _ => {}
+            if let Event::Key(KeyEvent { code, modifiers, .. }) = event::read()? {
+                match code {
+                    KeyCode::Char('q') | KeyCode::Char('Q') => return Ok(true),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(true),
+                    KeyCode::Esc => return Ok(true),
+                    _ => {}
+                }
             }
         }
         Ok(false)
     }
 
+    /// 设置界面保存日志的级别阈值（需在获取日志记录器之前设置）
+    pub fn set_log_level(&mut self, log_level: LogThreshold) {
+        self.log_level = log_level;
+    }
+
+    /// 设置日志时间戳的显示格式
+    pub fn set_timestamp_format(&mut self, format: TimestampFormat) {
+        self.timestamp_format = format;
+    }
+
+    /// 设置是否在状态表中显示服务商地址列（运行中可按 H 切换）
+    pub fn set_show_hosts(&mut self, show_hosts: bool) {
+        self.show_hosts = show_hosts;
+    }
+
+    /// 设置降级横幅的阈值（健康提供商占比，百分比，0 表示不显示）
+    pub fn set_degraded_threshold(&mut self, threshold: u8) {
+        self.degraded_threshold = threshold;
+    }
+
+    /// 设置界面布局
+    pub fn set_mode(&mut self, mode: UiMode) {
+        self.mode = mode;
+    }
+
+    /// 设置 Token 列的告警阈值（都未设置时 Token 列保持单一颜色）
+    pub fn set_token_thresholds(&mut self, warning: Option<TokenThreshold>, critical: Option<TokenThreshold>) {
+        self.token_warning = warning;
+        self.token_critical = critical;
+    }
+
+    /// 设置进入低频刷新前的空闲时长（None 表示始终全速刷新）
+    pub fn set_idle_after(&mut self, idle_after: Option<std::time::Duration>) {
+        self.idle_after = idle_after;
+    }
+
+    /// 是否已空闲：超过设定时长既没有代理请求，也没有用户输入
+    pub fn is_idle(&self, state: &ProxyState) -> bool {
+        self.idle_after.is_some_and(|idle_after| {
+            self.last_input.elapsed() >= idle_after && state.idle_duration() >= idle_after
+        })
+    }
+
+    /// Token 列的颜色：低于告警阈值为绿色，达到告警阈值为黄色，达到严重阈值为红色
+    fn token_color(&self, usage: u64, percentage: f32) -> Color {
+        if self.token_warning.is_none() && self.token_critical.is_none() {
+            return Color::Magenta;
+        }
+        let reached = |threshold: Option<TokenThreshold>| threshold.is_some_and(|t| t.reached(usage, percentage));
+        if reached(self.token_critical) {
+            Color::Red
+        } else if reached(self.token_warning) {
+            Color::Yellow
+        } else {
+            Color::Green
+        }
+    }
+
     /// 获取日志记录器的克隆
     pub fn logger(&self) -> Logger {
         Logger {
-            logs: Arc::clone(&self.logs),
-            max_logs: self.max_logs,
+            log_tx: self.log_tx.clone(),
+            dropped_logs: Arc::clone(&self.dropped_logs),
+            log_level: self.log_level,
         }
     }
 }
@@ -766,13 +1415,7 @@ impl Drop for TerminalUI {
                 eprintln!("⚠️ Failed to cleanup terminal in Drop: {}", e);
                 
                 // 强制终端重置，确保终端不会处于损坏状态
-                let _ = execute!(
-                    io::stdout(),
-                    DisableMouseCapture,
-                    cursor::Show,
-                    terminal::LeaveAlternateScreen
-                );
-                let _ = terminal::disable_raw_mode();
+                reset_terminal();
                 
                 eprintln!("🔧 Forced terminal reset completed");
             }
@@ -780,11 +1423,42 @@ impl Drop for TerminalUI {
     }
 }
 
+/// panic 钩子是否已经恢复过终端（之后界面停止绘制）
+static TERMINAL_RESTORED_BY_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// 忽略错误地恢复终端：退出原始模式和备用屏幕，显示光标并关闭鼠标捕获
+fn reset_terminal() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(
+        io::stdout(),
+        terminal::LeaveAlternateScreen,
+        cursor::Show,
+        DisableMouseCapture
+    );
+}
+
+/// 安装 panic 钩子：先恢复终端再交给原来的钩子输出 panic 信息
+///
+/// 任何线程或任务中的 panic 都会恢复终端，界面随后在下一次绘制时退出，
+/// 避免终端停留在原始模式和备用屏幕中。
+pub fn install_terminal_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !TERMINAL_RESTORED_BY_PANIC.swap(true, Ordering::SeqCst) {
+            reset_terminal();
+        }
+        default_hook(info);
+    }));
+}
+
 /// 日志记录器
 #[derive(Clone)]
+///
+/// 只把日志放入通道，不获取任何锁，由界面在渲染时统一取出。
 pub struct Logger {
-    logs: Arc<Mutex<VecDeque<LogEntry>>>,
-    max_logs: usize,
+    log_tx: mpsc::Sender<LogEntry>,
+    dropped_logs: Arc<AtomicU64>,
+    log_level: LogThreshold,
 }
 
 impl Logger {
@@ -808,24 +1482,20 @@ impl Logger {
         self.log(LogLevel::Debug, message);
     }
 
-    fn log(&self, level: LogLevel, message: String) {
+    /// 按级别记录日志，低于阈值的日志直接丢弃
+    pub fn log(&self, level: LogLevel, message: String) {
+        if !self.log_level.allows(&level) {
+            return;
+        }
         let entry = LogEntry {
             timestamp: Local::now(),
             level,
             message,
         };
 
-        let mut logs = match self.logs.lock() {
-            Ok(logs) => logs,
-            Err(poisoned) => {
-                eprintln!("⚠️ UI logs mutex poisoned, recovering...");
-                poisoned.into_inner()
-            }
-        };
-        logs.push_back(entry);
-        
-        while logs.len() > self.max_logs {
-            logs.pop_front();
+        // 通道已满时丢弃该条日志并计数，绝不阻塞调用方
+        if self.log_tx.try_send(entry).is_err() {
+            self.dropped_logs.fetch_add(1, Ordering::Relaxed);
         }
     }
 }
@@ -885,6 +1555,26 @@ fn format_duration(duration: chrono::Duration) -> String {
     }
 }
 
+/// 维护剩余时间，不足一分钟时显示秒数
+fn format_remaining(remaining: std::time::Duration) -> String {
+    let seconds = remaining.as_secs();
+    if seconds >= 3600 {
+        format!("{}h{}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m", seconds.div_ceil(60))
+    } else {
+        format!("{}s", seconds.max(1))
+    }
+}
+
+/// 将健康度采样绘制为迷你折线图（每个采样一个字符）
+fn health_sparkline(samples: &[u8]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    samples.iter()
+        .map(|&score| BARS[(score.min(100) as usize * (BARS.len() - 1)) / 100])
+        .collect()
+}
+
 /// 格式化Token数量，使用K/M后缀
 fn format_tokens(tokens: u64) -> String {
     if tokens >= 1_000_000 {
@@ -0,0 +1,50 @@
+//! Token 使用记录 - 每个成功的请求追加一行 CSV，便于按时间段核对用量和费用
+
+use std::io;
+use std::path::Path;
+use chrono::{Local, SecondsFormat};
+use crate::appender::BufferedAppender;
+use crate::token::RequestAnalysis;
+
+/// CSV 表头，新建文件（或文件为空）时写入
+pub const USAGE_CSV_HEADER: &str = "timestamp,provider,input_tokens,output_tokens,total";
+
+/// Token 使用记录文件（追加写入，见 `BufferedAppender`）
+#[derive(Debug)]
+pub struct UsageCsv {
+    file: BufferedAppender,
+}
+
+impl UsageCsv {
+    /// 以追加方式打开记录文件，不存在时创建并写入表头
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self { file: BufferedAppender::open(path, Some(USAGE_CSV_HEADER))? })
+    }
+
+    /// 追加一条记录（估算值），写入失败时丢弃（使用记录不应影响请求处理）
+    pub fn record(&self, provider_name: &str, analysis: &RequestAnalysis) {
+        let output_tokens = analysis.estimated_tokens.saturating_sub(analysis.input_tokens);
+        self.file.append_line(&format!(
+            "{},{},{},{},{}",
+            Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            csv_field(provider_name),
+            analysis.input_tokens,
+            output_tokens,
+            analysis.estimated_tokens,
+        ));
+    }
+
+    /// 把缓冲的记录写入文件
+    pub fn flush(&self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 包含逗号、引号或换行的字段用引号包裹，内部的引号写成两个
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
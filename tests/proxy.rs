@@ -0,0 +1,1730 @@
+//! 代理转发路径的集成测试：在本地启动模拟上游，经 `handle_request` 完整走一遍负载均衡和故障转移
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use auto_proxy::{calculate_display_width, check_providers, fit_display_width, handle_request, load_server_tls_config, run_health_checks, tls_incoming, AccessLog, AccessLogFormat, HealthConfig, HeuristicTokenEstimator, LoadBalanceStrategy, Provider, ProviderHealth, ProviderStore, ProxyServer, ProxyState, RateLimitMode, RequestAnalysis, RequestRecord, ResponseCache, SelfTestRequest, TokenEstimationConfig, TokenEstimator, UnavailableCondition, UnavailableResponses, UsageCsv, LATENCY_SAMPLE_CAPACITY, REQUEST_LOG_CAPACITY, USAGE_CSV_HEADER, SUCCESS_RATE_WINDOW};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use tracing_subscriber::layer::SubscriberExt;
+
+/// 模拟上游：按路径的第一段区分提供商，每个提供商可以设置返回的状态码和延迟
+#[derive(Default)]
+struct MockUpstream {
+    statuses: Mutex<HashMap<String, u16>>,
+    statuses_once: Mutex<HashMap<String, u16>>,
+    delays: Mutex<HashMap<String, Duration>>,
+    redirects: Mutex<HashMap<String, String>>,
+    hits: Mutex<HashMap<String, usize>>,
+    authorizations: Mutex<HashMap<String, Option<String>>>,
+    accept_encodings: Mutex<HashMap<String, Option<String>>>,
+    gzip_bodies: Mutex<HashMap<String, &'static [u8]>>,
+    body_lens: Mutex<HashMap<String, usize>>,
+}
+
+/// `{"upstream":"gzip"}` 的 gzip 压缩结果
+const GZIP_BODY: &[u8] = &[
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xab, 0x56, 0x2a, 0x2d, 0x28, 0x2e, 0x29, 0x4a, 0x4d, 0xcc,
+    0x55, 0xb2, 0x52, 0x4a, 0xaf, 0xca, 0x2c, 0x50, 0xaa, 0x05, 0x00, 0x95, 0xb8, 0x45, 0xf9, 0x13, 0x00, 0x00, 0x00,
+];
+
+impl MockUpstream {
+    fn set_status(&self, key: &str, status: u16) {
+        self.statuses.lock().unwrap().insert(key.to_string(), status);
+    }
+
+    /// 只对下一个请求返回指定状态码
+    fn set_status_once(&self, key: &str, status: u16) {
+        self.statuses_once.lock().unwrap().insert(key.to_string(), status);
+    }
+
+    fn set_delay(&self, key: &str, delay: Duration) {
+        self.delays.lock().unwrap().insert(key.to_string(), delay);
+    }
+
+    fn set_redirect(&self, key: &str, location: &str) {
+        self.redirects.lock().unwrap().insert(key.to_string(), location.to_string());
+    }
+
+    /// 最近一次请求携带的 Authorization 头
+    fn last_authorization(&self, key: &str) -> Option<String> {
+        self.authorizations.lock().unwrap().get(key).cloned().flatten()
+    }
+
+    /// 客户端接受 gzip 时返回给定的压缩响应体
+    fn set_gzip_body(&self, key: &str, body: &'static [u8]) {
+        self.gzip_bodies.lock().unwrap().insert(key.to_string(), body);
+    }
+
+    /// 最近一次请求携带的 Accept-Encoding 头
+    fn last_accept_encoding(&self, key: &str) -> Option<String> {
+        self.accept_encodings.lock().unwrap().get(key).cloned().flatten()
+    }
+
+    /// 最近一次请求的请求体长度
+    fn last_body_len(&self, key: &str) -> Option<usize> {
+        self.body_lens.lock().unwrap().get(key).copied()
+    }
+
+    fn hits(&self, key: &str) -> usize {
+        self.hits.lock().unwrap().get(key).copied().unwrap_or(0)
+    }
+
+    async fn respond(&self, req: Request<Body>) -> Response<Body> {
+        let key = req.uri().path().trim_start_matches('/').split('/').next().unwrap_or("").to_string();
+        *self.hits.lock().unwrap().entry(key.clone()).or_insert(0) += 1;
+        let authorization = req.headers().get("authorization").map(|v| v.to_str().unwrap().to_string());
+        self.authorizations.lock().unwrap().insert(key.clone(), authorization);
+        let accept_encoding = req.headers().get("accept-encoding").map(|v| v.to_str().unwrap().to_string());
+        self.accept_encodings.lock().unwrap().insert(key.clone(), accept_encoding.clone());
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+        self.body_lens.lock().unwrap().insert(key.clone(), body.len());
+        let redirect = self.redirects.lock().unwrap().get(&key).cloned();
+        if let Some(location) = redirect {
+            return Response::builder().status(302).header("Location", location).body(Body::empty()).unwrap();
+        }
+        let delay = self.delays.lock().unwrap().get(&key).copied();
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        let status = self.statuses_once.lock().unwrap().remove(&key)
+            .or_else(|| self.statuses.lock().unwrap().get(&key).copied())
+            .unwrap_or(200);
+        let gzip_body = self.gzip_bodies.lock().unwrap().get(&key).copied();
+        if let Some(body) = gzip_body.filter(|_| accept_encoding.is_some_and(|v| v.contains("gzip"))) {
+            return Response::builder()
+                .status(status)
+                .header("Content-Type", "application/json")
+                .header("Content-Encoding", "gzip")
+                .header("Content-Length", body.len())
+                .body(Body::from(body))
+                .unwrap();
+        }
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(format!("{{\"upstream\":\"{}\"}}", key)))
+            .unwrap()
+    }
+}
+
+/// 在随机端口启动模拟上游
+fn spawn_mock() -> (Arc<MockUpstream>, SocketAddr) {
+    let mock = Arc::new(MockUpstream::default());
+    let service_mock = Arc::clone(&mock);
+    let make_svc = make_service_fn(move |_conn| {
+        let mock = Arc::clone(&service_mock);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let mock = Arc::clone(&mock);
+                async move { Ok::<_, Infallible>(mock.respond(req).await) }
+            }))
+        }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (mock, addr)
+}
+
+fn provider(name: &str, addr: SocketAddr) -> Provider {
+    serde_json::from_value(serde_json::json!({
+        "name": name,
+        "token": format!("sk-test-token-{}", name),
+        "base_url": format!("http://{}/{}", addr, name),
+        "key_type": "AUTH_TOKEN",
+    }))
+    .unwrap()
+}
+
+/// 两个指向同一模拟上游的提供商 a、b，关闭轮询抖动使选择顺序固定（第一个请求先选 a）
+fn setup(rate_limit: usize) -> (Arc<MockUpstream>, Arc<Vec<Provider>>, Arc<ProxyState>) {
+    setup_with(rate_limit, |state| state)
+}
+
+/// 同 `setup`，`configure` 可以在关闭轮询抖动后继续调用 `with_*` 修改状态
+fn setup_with(rate_limit: usize, configure: impl FnOnce(ProxyState) -> ProxyState) -> (Arc<MockUpstream>, Arc<Vec<Provider>>, Arc<ProxyState>) {
+    let (mock, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr), provider("b", addr)]);
+    let state = Arc::new(configure(ProxyState::new_with_rate_limit(rate_limit).with_round_robin_jitter(false)));
+    (mock, providers, state)
+}
+
+async fn send(providers: &Arc<Vec<Provider>>, state: &Arc<ProxyState>) -> (StatusCode, String) {
+    let req = Request::builder()
+        .method("POST")
+        .uri("/v1/messages")
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"messages":[]}"#))
+        .unwrap();
+    let response = handle_request(req, Arc::clone(providers), Arc::clone(state)).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn proxies_successful_request() {
+    let (mock, providers, state) = setup(10);
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"a"}"#);
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(mock.hits("b"), 0);
+    assert_eq!(state.get_last_status_code("a"), Some(200));
+}
+
+#[tokio::test]
+async fn fails_over_on_server_error() {
+    let (mock, providers, state) = setup(10);
+    mock.set_status("a", 500);
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"b"}"#);
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(mock.hits("b"), 1);
+    assert_eq!(state.get_last_status_code("a"), Some(500));
+    assert!(state.get_provider_health_score("a") < 100);
+}
+
+#[tokio::test]
+async fn fails_over_on_slow_upstream_error() {
+    let (mock, providers, state) = setup(10);
+    mock.set_status("a", 502);
+    mock.set_delay("a", Duration::from_millis(50));
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"b"}"#);
+    assert_eq!(mock.hits("a"), 1);
+}
+
+#[tokio::test]
+async fn skips_disabled_provider() {
+    let (mock, providers, state) = setup(10);
+    state.interactive_manager.set_provider_disabled("a", true);
+
+    for _ in 0..4 {
+        let (status, _) = send(&providers, &state).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    assert_eq!(mock.hits("a"), 0);
+    assert_eq!(mock.hits("b"), 4);
+}
+
+#[tokio::test]
+async fn rejects_when_all_providers_disabled() {
+    let (mock, providers, state) = setup(10);
+    state.interactive_manager.set_provider_disabled("a", true);
+    state.interactive_manager.set_provider_disabled("b", true);
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.contains("disabled"));
+    assert_eq!(mock.hits("a") + mock.hits("b"), 0);
+}
+
+#[tokio::test]
+async fn emergency_mode_serves_when_all_unhealthy() {
+    let (mock, providers, state) = setup(10);
+    for name in ["a", "b"] {
+        for _ in 0..5 {
+            state.record_provider_failure(name);
+        }
+        assert!(!state.is_provider_healthy(name));
+    }
+
+    let (status, _) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mock.hits("a") + mock.hits("b"), 1);
+}
+
+#[tokio::test]
+async fn emergency_mode_tries_each_provider_once_then_gives_up() {
+    let (mock, providers, state) = setup(10);
+    mock.set_status("a", 500);
+    mock.set_status("b", 500);
+    for name in ["a", "b"] {
+        for _ in 0..5 {
+            state.record_provider_failure(name);
+        }
+    }
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.contains("all providers are down (2 attempt(s))"), "{}", body);
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(mock.hits("b"), 1);
+}
+
+#[tokio::test]
+async fn returns_503_when_rate_limited() {
+    let (mock, providers, state) = setup(1);
+
+    let (first, _) = send(&providers, &state).await;
+    let (second, _) = send(&providers, &state).await;
+    let (third, _) = send(&providers, &state).await;
+
+    assert_eq!(first, StatusCode::OK);
+    assert_eq!(second, StatusCode::OK);
+    assert_eq!(third, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(mock.hits("b"), 1);
+    // 速率限制不是提供商的故障，不应影响健康度
+    assert_eq!(state.get_provider_health_score("a"), 100);
+    assert_eq!(state.get_provider_health_score("b"), 100);
+}
+
+#[tokio::test]
+async fn follows_redirects_without_leaking_token_to_other_hosts() {
+    let (mock, addr) = spawn_mock();
+    let (other, other_addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_follow_redirects(3));
+    mock.set_redirect("a", "/moved/v1/messages");
+    mock.set_redirect("moved", &format!("http://{}/elsewhere", other_addr));
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"elsewhere"}"#);
+    assert_eq!(mock.last_authorization("moved").as_deref(), Some("Bearer sk-test-token-a"));
+    assert_eq!(other.last_authorization("elsewhere"), None);
+}
+
+#[tokio::test]
+async fn redirect_loop_fails_over() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_follow_redirects(5));
+    mock.set_redirect("a", "/loop");
+    mock.set_redirect("loop", "/a/v1/messages");
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"b"}"#);
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(state.get_last_status_code("a"), Some(302));
+}
+
+#[tokio::test]
+async fn histogram_counts_requests_per_minute() {
+    let (mock, providers, state) = setup(10);
+    send(&providers, &state).await;
+    state.request_histogram.advance();
+    mock.set_status("a", 500);
+    mock.set_status("b", 500);
+    send(&providers, &state).await;
+
+    let buckets = state.request_histogram.snapshot();
+
+    assert_eq!(buckets.len(), 60);
+    let current = buckets[59];
+    let previous = buckets[58];
+    assert_eq!((current.minutes_ago, current.total, current.failures), (0, 1, 1));
+    assert_eq!((previous.minutes_ago, previous.total, previous.failures), (1, 1, 0));
+    assert!(buckets[..58].iter().all(|b| b.total == 0));
+}
+
+#[tokio::test]
+async fn configured_success_statuses_override_2xx() {
+    let (mock, addr) = spawn_mock();
+    let mut strict = provider("a", addr);
+    strict.success_statuses = Some(serde_json::from_value(serde_json::json!([200])).unwrap());
+    let mut lenient = provider("b", addr);
+    lenient.success_statuses = Some(serde_json::from_value(serde_json::json!(["200-299", 404])).unwrap());
+    let providers = Arc::new(vec![strict, lenient]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_round_robin_jitter(false));
+    mock.set_status("a", 202);
+    mock.set_status("b", 404);
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, r#"{"upstream":"b"}"#);
+    assert!(state.get_provider_health_score("a") < 100);
+    assert_eq!(state.get_provider_health_score("b"), 100);
+}
+
+#[tokio::test]
+async fn reload_swaps_providers_and_keeps_old_config_on_error() {
+    let (_, addr) = spawn_mock();
+    let path = std::env::temp_dir().join(format!("auto-proxy-reload-{}.json", std::process::id()));
+    let write_config = |providers: &[Provider]| std::fs::write(&path, serde_json::to_string(providers).unwrap()).unwrap();
+    write_config(&[provider("a", addr), provider("b", addr)]);
+    let store = ProviderStore::new(vec![provider("a", addr), provider("b", addr)], Some(path.clone()));
+    let state = ProxyState::new_with_rate_limit(10);
+    state.mark_auth_failed("a");
+    for _ in 0..12 {
+        state.record_provider_failure("a");
+    }
+    state.record_request("a");
+    let failures = state.get_request_counts("a").failure;
+
+    let mut changed = provider("a", addr);
+    changed.token = "sk-test-token-rotated".to_string();
+    write_config(&[changed, provider("c", addr)]);
+    let diff = store.reload(&state).await.unwrap();
+
+    assert_eq!((diff.added, diff.removed, diff.changed), (vec!["c".to_string()], vec!["b".to_string()], vec!["a".to_string()]));
+    assert_eq!(diff.reset, ["a"]);
+    assert!(!state.is_auth_failed("a"));
+    assert_eq!(state.get_provider_health_score("a"), 100);
+    assert_eq!(state.get_current_requests("a"), 0);
+    assert_eq!(state.get_request_counts("a").failure, failures);
+    let names: Vec<String> = store.current().iter().map(|p| p.name.clone()).collect();
+    assert_eq!(names, ["a", "c"]);
+
+    std::fs::write(&path, "[").unwrap();
+    assert!(store.reload(&state).await.is_err());
+    assert_eq!(store.current().len(), 2);
+
+    // 与 --check-config 相同的检查：名称重复的配置不会被换上
+    write_config(&[provider("a", addr), provider("a", addr), provider("d", addr)]);
+    let error = store.reload(&state).await.unwrap_err();
+    assert!(error.contains("名称与其他提供商重复"), "{}", error);
+    let names: Vec<String> = store.current().iter().map(|p| p.name.clone()).collect();
+    assert_eq!(names, ["a", "c"]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// 固定返回 42 个Token的估算器
+struct FixedEstimator;
+
+impl TokenEstimator for FixedEstimator {
+    fn estimate_request(&self, _body: &hyper::body::Bytes, _uri: &hyper::Uri, _headers: &hyper::HeaderMap) -> RequestAnalysis {
+        RequestAnalysis { model: None, input_tokens: 40, estimated_tokens: 42 }
+    }
+
+    fn estimate_response(&self, _body: &[u8], _headers: &hyper::HeaderMap) -> Option<u64> {
+        Some(2)
+    }
+}
+
+#[tokio::test]
+async fn uses_custom_token_estimator() {
+    let (_, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_token_estimator(FixedEstimator));
+
+    send(&providers, &state).await;
+    send(&providers, &state).await;
+
+    assert_eq!(state.get_token_usage("a"), 84);
+    assert_eq!(state.get_input_token_usage("a"), 80);
+}
+
+#[tokio::test]
+async fn mirrors_requests_to_shadow_without_affecting_client() {
+    let (mock, addr) = spawn_mock();
+    let mut shadow = provider("s", addr);
+    shadow.shadow = true;
+    let providers = Arc::new(vec![shadow, provider("a", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_round_robin_jitter(false));
+    mock.set_status("s", 500);
+    mock.set_delay("s", Duration::from_millis(100));
+
+    for _ in 0..2 {
+        let (status, body) = send(&providers, &state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"upstream":"a"}"#);
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    assert_eq!(mock.hits("a"), 2);
+    assert_eq!(mock.hits("s"), 2);
+    assert_eq!(state.get_last_status_code("s"), Some(500));
+    assert_eq!(state.get_provider_health_score("a"), 100);
+}
+
+#[tokio::test]
+async fn dns_failure_cools_down_without_affecting_health() {
+    let (mock, addr) = spawn_mock();
+    let mut dead = provider("dead", addr);
+    dead.base_url = "http://no-such-host.invalid/dead".to_string();
+    let providers = Arc::new(vec![dead, provider("b", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_round_robin_jitter(false));
+
+    let (first, _) = send(&providers, &state).await;
+    assert!(state.is_dns_failed("dead"));
+    let (second, _) = send(&providers, &state).await;
+
+    assert_eq!((first, second), (StatusCode::OK, StatusCode::OK));
+    assert_eq!(mock.hits("b"), 2);
+    assert_eq!(state.get_provider_health_score("dead"), 100);
+    assert_eq!(state.get_request_counts("dead").failure, 1);
+
+    // 冷却期内指定该提供商也不再发起域名查询
+    let req = Request::builder().method("POST").uri("/v1/messages").header("X-Proxy-Provider", "dead").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("DNS resolution failed"), "{:?}", body);
+    assert_eq!(state.get_request_counts("dead").failure, 1);
+}
+
+#[tokio::test]
+async fn pool_stats_aggregates_all_providers() {
+    let (mock, providers, state) = setup(100);
+    mock.set_status("a", 500);
+
+    let (status, _) = send(&providers, &state).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let stats = state.pool_stats(&providers);
+    assert_eq!(stats.total, 2);
+    assert_eq!(stats.healthy, 2);
+    let health_sum = state.get_provider_health_score("a") as u32 + state.get_provider_health_score("b") as u32;
+    assert!(state.get_provider_health_score("a") < 100);
+    assert_eq!(stats.avg_health, health_sum / 2);
+    assert_eq!(stats.total_requests, 2);
+    assert_eq!(stats.failed_requests, 1);
+    assert_eq!(stats.success_rate(), Some(50.0));
+    assert_eq!(stats.total_tokens, state.get_token_usage("b"));
+}
+
+#[tokio::test]
+async fn provider_id_keeps_state_across_rename() {
+    let (_, addr) = spawn_mock();
+    let path = std::env::temp_dir().join(format!("auto-proxy-rename-{}.json", std::process::id()));
+    let with_id = |name: &str| Provider { id: Some("primary".to_string()), name: name.to_string(), ..provider("old", addr) };
+    std::fs::write(&path, serde_json::to_string(&[with_id("old")]).unwrap()).unwrap();
+    let store = ProviderStore::new(vec![with_id("old")], Some(path.clone()));
+    let state = ProxyState::new_with_rate_limit(10);
+    state.record_provider_failure("primary");
+    let score = state.get_provider_health_score("primary");
+
+    std::fs::write(&path, serde_json::to_string(&[with_id("new")]).unwrap()).unwrap();
+    let diff = store.reload(&state).await.unwrap();
+
+    assert_eq!((diff.added, diff.removed, diff.changed), (vec![], vec![], vec!["new".to_string()]));
+    assert_eq!(state.get_request_counts("primary").failure, 1);
+    assert_eq!(state.get_provider_health_score("primary"), score);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn retries_same_provider_once_on_server_error() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_retry_same_on_5xx(true));
+    mock.set_status_once("a", 500);
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"a"}"#);
+    assert_eq!(mock.hits("a"), 2);
+    assert_eq!(mock.hits("b"), 0);
+}
+
+#[tokio::test]
+async fn fails_over_immediately_on_connect_error_with_5xx_retry() {
+    let (mock, addr) = spawn_mock();
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let providers = Arc::new(vec![provider("a", closed), provider("b", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_round_robin_jitter(false).with_retry_same_on_5xx(true));
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"b"}"#);
+    assert_eq!(state.get_last_status_code("a"), Some(0));
+    let attempts_on_a = state.recent_requests(10).iter().filter(|r| r.provider == "a").count();
+    assert_eq!(attempts_on_a, 1);
+    assert_eq!(mock.hits("b"), 1);
+}
+
+#[tokio::test]
+async fn unmatched_routes_go_to_default_providers() {
+    let (mock, addr) = spawn_mock();
+    let tagged = |name: &str, tag: &str| Provider { tags: vec![tag.to_string()], ..provider(name, addr) };
+    let default = |name: &str| Provider { is_default: true, ..provider(name, addr) };
+    let providers = Arc::new(vec![default("a"), tagged("b", "chat"), default("c")]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10)
+        .with_round_robin_jitter(false)
+        .with_tag_routes(vec![("/v1/chat".to_string(), "chat".to_string())]));
+
+    for _ in 0..2 {
+        let (status, _) = send(&providers, &state).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    assert_eq!((mock.hits("a"), mock.hits("b"), mock.hits("c")), (1, 0, 1));
+
+    let req = Request::builder().method("POST").uri("/v1/chat/completions").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(mock.hits("b"), 1);
+}
+
+#[tokio::test]
+async fn self_test_reports_each_provider() {
+    let (mock, addr) = spawn_mock();
+    mock.set_status("b", 401);
+    mock.set_delay("c", Duration::from_secs(5));
+    let server = ProxyServer::from_providers(vec![provider("a", addr), provider("b", addr), provider("c", addr)]);
+
+    let results = server.self_test(SelfTestRequest {
+        method: hyper::Method::POST,
+        path: "/v1/messages".to_string(),
+        body: "{}".to_string(),
+        timeout: Duration::from_millis(200),
+    }).await;
+
+    let summary: Vec<(&str, bool, String)> = results.iter()
+        .map(|(p, r)| (p.name.as_str(), r.success, format!("{:?}", r.outcome)))
+        .collect();
+    assert_eq!(summary, [
+        ("a", true, "Ok(200)".to_string()),
+        ("b", false, "Ok(401)".to_string()),
+        ("c", false, "Err(Timeout)".to_string()),
+    ]);
+}
+
+#[tokio::test]
+async fn returns_429_when_concurrency_limit_exceeded() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_max_concurrency(Some(1)));
+    mock.set_delay("a", Duration::from_secs(1));
+
+    let (slow, fast) = tokio::join!(send(&providers, &state), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        send(&providers, &state).await
+    });
+
+    assert_eq!(slow.0, StatusCode::OK);
+    assert_eq!(fast.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(mock.hits("b"), 0);
+    // 许可释放后新的请求可以正常处理
+    let (after, _) = send(&providers, &state).await;
+    assert_eq!(after, StatusCode::OK);
+}
+
+#[test]
+fn success_rate_covers_only_recent_requests() {
+    let state = ProxyState::new_with_rate_limit(10);
+    assert_eq!(state.get_success_rate("a"), 100.0);
+
+    for _ in 0..SUCCESS_RATE_WINDOW {
+        state.record_provider_failure("a");
+    }
+    assert_eq!(state.get_success_rate("a"), 0.0);
+
+    // 窗口已满时新的结果挤掉最旧的结果
+    for _ in 0..SUCCESS_RATE_WINDOW / 4 {
+        state.record_provider_success("a");
+    }
+    assert_eq!(state.get_recent_outcomes("a"), (SUCCESS_RATE_WINDOW / 4, SUCCESS_RATE_WINDOW));
+    assert_eq!(state.get_success_rate("a"), 25.0);
+}
+
+#[tokio::test]
+async fn forwards_compressed_response_only_when_client_accepts_it() {
+    let (mock, addr) = spawn_mock();
+    mock.set_gzip_body("gzip", GZIP_BODY);
+    let providers = Arc::new(vec![provider("gzip", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_error_pointer(Some("/error".to_string())));
+    let make_svc = make_service_fn(move |_conn| {
+        let (providers, state) = (Arc::clone(&providers), Arc::clone(&state));
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle_request(req, Arc::clone(&providers), Arc::clone(&state))))
+        }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let proxy_addr = server.local_addr();
+    tokio::spawn(server);
+    let client = hyper::Client::new();
+    let request = |accept_encoding: Option<&str>| {
+        let mut builder = Request::builder().method("POST").uri(format!("http://{}/v1/messages", proxy_addr));
+        if let Some(value) = accept_encoding {
+            builder = builder.header("Accept-Encoding", value);
+        }
+        builder.body(Body::from(r#"{"messages":[]}"#)).unwrap()
+    };
+
+    let response = client.request(request(Some("gzip"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    assert_eq!(response.headers()["content-length"], GZIP_BODY.len().to_string().as_str());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], GZIP_BODY);
+    assert_eq!(mock.last_accept_encoding("gzip").as_deref(), Some("gzip"));
+
+    // 客户端没有要求压缩时代理不会自行添加 Accept-Encoding
+    let response = client.request(request(None)).await.unwrap();
+    assert!(response.headers().get("content-encoding").is_none());
+    // 开启错误字段检查时响应体被缓冲后重新组装，长度由代理重新计算
+    assert_eq!(response.headers()["content-length"], "19");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], br#"{"upstream":"gzip"}"#);
+    assert_eq!(mock.last_accept_encoding("gzip"), None);
+}
+
+#[tokio::test]
+async fn hard_fails_when_all_unhealthy_and_emergency_mode_disabled() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_emergency_mode(false));
+    for name in ["a", "b"] {
+        for _ in 0..5 {
+            state.record_provider_failure(name);
+        }
+    }
+
+    let (status, _) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(mock.hits("a") + mock.hits("b"), 0);
+}
+
+#[test]
+fn seeded_state_reproduces_random_selection() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 9));
+    let providers: Vec<Provider> = ["a", "b", "c", "d"].iter().map(|name| provider(name, addr)).collect();
+    let picks = |seed: u64| -> Vec<usize> {
+        let state = ProxyState::new_with_rate_limit(100).with_rng_seed(seed);
+        (0..20).filter_map(|_| state.select_provider_randomly(&providers)).collect()
+    };
+
+    let first = picks(42);
+    assert_eq!(first.len(), 20);
+    assert_eq!(first, picks(42));
+    assert_ne!(first, picks(43));
+    assert!(first.iter().any(|&index| index != first[0]));
+}
+
+#[tokio::test]
+async fn streams_large_body_without_failover() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_max_buffered_body(Some(16)));
+    mock.set_status("a", 500);
+
+    let large = vec![b'x'; 64 * 1024];
+    let req = Request::builder().method("POST").uri("/v1/messages").body(Body::from(large)).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+
+    // 超过缓冲上限的请求体完整流式到达上游，但失败后不转移
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(mock.last_body_len("a"), Some(64 * 1024));
+    assert_eq!(mock.hits("b"), 0);
+
+    // 未超过上限的请求仍然缓冲并正常转移
+    let (status, body) = send(&providers, &state).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"b"}"#);
+}
+
+#[tokio::test]
+async fn halves_failure_penalty_during_warmup() {
+    let (mock, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let health_config = HealthConfig { warmup_requests: 1, ..HealthConfig::default() };
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_round_robin_jitter(false).with_health_config(health_config));
+    mock.set_status("a", 500);
+
+    // 预热期内的第一次失败只扣一半（5 → 2），预热结束后恢复正常惩罚
+    send(&providers, &state).await;
+    assert_eq!(state.get_provider_health_score("a"), 98);
+    send(&providers, &state).await;
+    assert_eq!(state.get_provider_health_score("a"), 88);
+}
+
+#[tokio::test]
+async fn exposes_serving_provider_header() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_expose_provider_header(true));
+    mock.set_status("a", 500);
+
+    let req = Request::builder().method("POST").uri("/v1/messages").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-proxy-provider"], "b");
+    assert_eq!(response.headers()["x-proxy-attempts"], "2");
+}
+
+#[tokio::test]
+async fn soft_rate_limit_still_serves_when_pool_is_busy() {
+    let (mock, providers, state) = setup_with(1, |state| state.with_rate_limit_mode(RateLimitMode::Soft));
+
+    for _ in 0..3 {
+        let (status, _) = send(&providers, &state).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    // 前两个请求各用掉一个配额，第三个请求超限发送而不是返回 503
+    assert_eq!(mock.hits("a") + mock.hits("b"), 3);
+}
+
+#[tokio::test]
+async fn writes_access_log_record_per_request() {
+    let path = std::env::temp_dir().join(format!("auto-proxy-access-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let access_log = AccessLog::open(&path, AccessLogFormat::Json).unwrap();
+    let (mock, providers, state) = setup_with(10, |state| state.with_access_log(Some(access_log)));
+    mock.set_status("a", 500);
+
+    send(&providers, &state).await;
+    // 记录先留在缓冲区，刷新后才写入文件
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    state.access_log.as_ref().unwrap().flush().unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 1);
+    let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(record["method"], "POST");
+    assert_eq!(record["path"], "/v1/messages");
+    assert_eq!(record["status"], 200);
+    assert_eq!(record["provider"], "b");
+}
+
+#[tokio::test]
+async fn active_health_check_uses_configured_path() {
+    let (mock, addr) = spawn_mock();
+    let mut checked = provider("a", addr);
+    checked.health_check_path = Some("/v1/models".to_string());
+    let providers = vec![checked, provider("b", addr)];
+    let state = ProxyState::new_with_rate_limit(10);
+    mock.set_status("a", 503);
+
+    run_health_checks(&state, &providers, &None).await;
+
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(mock.hits("b"), 1);
+    assert!(state.get_provider_health_score("a") < 100);
+    assert_eq!(state.get_provider_health_score("b"), 100);
+    // 探测不占用速率限制配额
+    assert_eq!(state.get_current_requests("a"), 0);
+}
+
+#[test]
+fn effective_config_merges_options_and_masks_tokens() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let config = ProxyServer::from_providers(vec![provider("a", addr)])
+        .with_rate_limit(12)
+        .with_state_options(|state| state.with_rate_limit_mode(RateLimitMode::Soft).with_admin_token(Some("secret".to_string())))
+        .effective_config();
+
+    assert_eq!(config["rate_limit"], 12);
+    assert_eq!(config["rate_limit_mode"], "soft");
+    assert_eq!(config["strategy"], "round-robin");
+    assert_eq!(config["providers"][0]["token"], "sk****-a");
+    assert_eq!(config["admin_token"], "****");
+    assert!(!config.to_string().contains("sk-test-token-a"));
+    assert!(!config.to_string().contains("secret"));
+}
+
+#[test]
+fn balance_tokens_prefers_least_used_provider() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let providers = vec![provider("a", addr), provider("b", addr), provider("c", addr)];
+    let state = ProxyState::new_with_rate_limit(100).with_strategy(LoadBalanceStrategy::BalanceTokens);
+    let usage = |tokens| RequestAnalysis { model: None, input_tokens: 0, estimated_tokens: tokens };
+    state.record_token_usage("a", &usage(500));
+    state.record_token_usage("b", &usage(100));
+    state.record_token_usage("c", &usage(100));
+
+    // b、c 并列最少，轮流选择；故障转移时排除已尝试过的
+    let first = state.select_next_provider(&providers).unwrap();
+    let second = state.select_next_provider(&providers).unwrap();
+    assert_eq!([first.min(second), first.max(second)], [1, 2]);
+    assert_eq!(state.select_next_provider_excluding(&providers, &[1]), Some(2));
+
+    state.record_token_usage("c", &usage(1000));
+    assert_eq!(state.select_next_provider(&providers), Some(1));
+}
+
+#[tokio::test]
+async fn state_dump_lists_each_provider() {
+    let (_, providers, state) = setup(10);
+    state.interactive_manager.set_provider_disabled("b", true);
+    send(&providers, &state).await;
+
+    let dump = state.state_dump(&providers);
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].contains("a [健康]") && lines[1].contains("速率 1/10") && lines[1].contains("最近状态 200"), "{}", lines[1]);
+    assert!(lines[2].contains("b [禁用]") && lines[2].contains("最近状态 -"), "{}", lines[2]);
+    assert!(lines[3].contains("健康 2/2"), "{}", lines[3]);
+}
+
+#[tokio::test]
+async fn aborted_request_body_returns_descriptive_400() {
+    let (mock, providers, state) = setup(10);
+    let (sender, body) = Body::channel();
+    sender.abort();
+
+    let req = Request::builder().method("POST").uri("/v1/messages").body(body).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "Bad Request: connection closed before the request body was complete");
+    assert_eq!(mock.hits("a") + mock.hits("b"), 0);
+}
+
+#[tokio::test]
+async fn dashboard_embeds_snapshot_without_tokens() {
+    let (_, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr), provider("</script>", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10));
+    send(&providers, &state).await;
+
+    let req = Request::builder().uri("/_dashboard").body(Body::empty()).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+    let html = String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(html.contains(r#""name":"a""#) && html.contains(r#""status":"healthy""#));
+    assert!(html.contains(r#""name":"<\/script>""#));
+    assert!(!html.contains("sk-test-token"));
+}
+
+#[tokio::test]
+async fn fails_fast_when_all_down_and_auto_recovery_disabled() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_auto_recovery(false));
+    for name in ["a", "b"] {
+        for _ in 0..12 {
+            state.record_provider_failure(name);
+        }
+        assert_eq!(state.get_provider_health_score(name), 0);
+    }
+
+    let (status, _) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(mock.hits("a") + mock.hits("b"), 0);
+    assert_eq!(state.get_provider_health_score("a"), 0);
+}
+
+#[tokio::test]
+async fn deadline_header_bounds_total_failover_time() {
+    let (mock, providers, state) = setup(10);
+    mock.set_delay("a", Duration::from_secs(5));
+    mock.set_delay("b", Duration::from_secs(5));
+
+    let started_at = std::time::Instant::now();
+    let req = Request::builder()
+        .method("POST")
+        .uri("/v1/messages")
+        .header("X-Timeout-Ms", "200")
+        .body(Body::from("{}"))
+        .unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started_at.elapsed() < Duration::from_secs(1));
+    assert_eq!(state.get_provider_health_score("a"), 100);
+}
+
+#[tokio::test]
+async fn maintenance_skips_provider_until_expiry() {
+    let (mock, providers, state) = setup(10);
+    state.interactive_manager.start_maintenance("a", Duration::from_millis(200));
+
+    for _ in 0..3 {
+        assert_eq!(send(&providers, &state).await.0, StatusCode::OK);
+    }
+    assert_eq!(mock.hits("a"), 0);
+    assert!(!state.interactive_manager.is_provider_disabled("a"));
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(!state.interactive_manager.is_provider_in_maintenance("a"));
+    for _ in 0..2 {
+        send(&providers, &state).await;
+    }
+    assert_eq!(mock.hits("a"), 1);
+}
+
+/// span 名称和 `字段=值` 列表
+type RecordedSpan = (&'static str, Vec<String>);
+
+/// 记录 span 名称及其字段、以及事件消息的 tracing 层
+#[derive(Clone, Default)]
+struct SpanRecorder {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+/// span 在 `SpanRecorder::spans` 中的下标，保存在 span 的扩展数据中
+struct SpanIndex(usize);
+
+struct FieldRecorder<'a>(&'a mut Vec<String>);
+
+impl tracing::field::Visit for FieldRecorder<'_> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.push(format!("{}={}", field.name(), value));
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.push(format!("{}={:?}", field.name(), value));
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for SpanRecorder
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut fields = Vec::new();
+        attrs.record(&mut FieldRecorder(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push((attrs.metadata().name(), fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanIndex(spans.len() - 1));
+        }
+    }
+
+    fn on_record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let Some(index) = span.extensions().get::<SpanIndex>().map(|i| i.0) else { return };
+        values.record(&mut FieldRecorder(&mut self.spans.lock().unwrap()[index].1));
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut fields = Vec::new();
+        event.record(&mut FieldRecorder(&mut fields));
+        self.events.lock().unwrap().extend(fields.into_iter().filter_map(|f| f.strip_prefix("message=").map(str::to_string)));
+    }
+}
+
+#[tokio::test]
+async fn request_span_records_provider_and_attempts() {
+    let (mock, providers, state) = setup(10);
+    mock.set_status("a", 500);
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+    assert_eq!(send(&providers, &state).await.0, StatusCode::OK);
+
+    let spans = recorder.spans.lock().unwrap();
+    let request: Vec<_> = spans.iter().filter(|(name, _)| *name == "proxy_request").collect();
+    assert_eq!(request.len(), 1);
+    for field in ["provider=b", "http.status_code=200", "http.method=POST"] {
+        assert!(request[0].1.iter().any(|f| f == field), "{:?}", request[0].1);
+    }
+    let attempts: Vec<_> = spans.iter().filter(|(name, _)| *name == "upstream_attempt").map(|(_, fields)| fields).collect();
+    assert_eq!(attempts.len(), 2);
+    assert!(attempts[0].contains(&"provider=a".to_string()) && attempts[0].contains(&"http.status_code=500".to_string()), "{:?}", attempts);
+    assert!(attempts[1].contains(&"provider=b".to_string()));
+}
+
+#[test]
+fn long_provider_names_are_truncated_to_column_width() {
+    let long_name = "超长的服务商名称".repeat(5);
+    assert_eq!(long_name.chars().count(), 40);
+
+    for width in [15, 20] {
+        let fitted = fit_display_width(&long_name, width);
+        assert_eq!(calculate_display_width(&fitted), width, "{:?}", fitted);
+        assert!(fitted.trim_end().ends_with('…'));
+    }
+    assert_eq!(fit_display_width("provider-a", 15), "provider-a     ");
+    assert_eq!(fit_display_width("服务商", 6), "服务商");
+}
+
+#[tokio::test]
+async fn unavailable_responses_are_customizable_per_condition() {
+    let mut responses = UnavailableResponses::default();
+    responses.get_mut(UnavailableCondition::Disabled).status = Some(404);
+    responses.get_mut(UnavailableCondition::Disabled).body = Some(r#"{"error":{"type":"{condition}","message":"{message}"}}"#.to_string());
+    responses.get_mut(UnavailableCondition::NoProviders).body = Some("unavailable: {status}".to_string());
+    let (_, providers, state) = setup_with(10, |state| state.with_unavailable_responses(responses));
+    for name in ["a", "b"] {
+        state.interactive_manager.set_provider_disabled(name, true);
+    }
+
+    let req = Request::builder().method("POST").uri("/v1/messages").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.headers().get("retry-after").is_none());
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let body: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert_eq!(body["error"]["type"], "disabled");
+    assert!(body["error"]["message"].as_str().unwrap().contains("disabled by user"));
+
+    let req = Request::builder().method("POST").uri("/v1/messages").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, Arc::new(Vec::new()), Arc::clone(&state)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "60");
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "unavailable: 503");
+}
+
+#[tokio::test]
+async fn latency_percentiles_use_bounded_per_provider_samples() {
+    let (_, providers, state) = setup(10);
+    let record = |status: u16, latency_ms: u64| RequestRecord {
+        timestamp: chrono::Local::now(),
+        method: "POST".to_string(),
+        path: "/v1/messages".to_string(),
+        provider: "a".to_string(),
+        provider_id: None,
+        status,
+        latency_ms,
+        tokens: 0,
+    };
+    for latency_ms in 1..=300 {
+        state.record_request_outcome(record(200, latency_ms));
+    }
+    // 网络错误的耗时不计入
+    state.record_request_outcome(record(0, 10_000));
+
+    let percentiles = state.get_latency_percentiles("a").unwrap();
+    assert_eq!(percentiles.samples, LATENCY_SAMPLE_CAPACITY);
+    assert_eq!((percentiles.p50, percentiles.p95, percentiles.p99), (172, 288, 298));
+    assert!(state.get_latency_percentiles("b").is_none());
+
+    let req = Request::builder().uri("/_metrics").body(Body::empty()).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+    let metrics: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert_eq!(metrics["latency"]["a"]["p95"], 288);
+    assert!(metrics["latency"]["b"].is_null());
+}
+
+#[test]
+fn disabled_provider_is_never_selected_while_toggling() {
+    let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+    let providers = vec![provider("a", addr), provider("b", addr), provider("c", addr)];
+    let state = Arc::new(ProxyState::new_with_rate_limit(1_000_000));
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+    // 其他线程不停切换 b，制造禁用状态锁的竞争
+    let togglers: Vec<_> = (0..4).map(|_| {
+        let state = Arc::clone(&state);
+        let stop = Arc::clone(&stop);
+        std::thread::spawn(move || {
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                state.interactive_manager.toggle_provider("b");
+            }
+        })
+    }).collect();
+
+    for _ in 0..20_000 {
+        state.interactive_manager.set_provider_disabled("a", true);
+        let selected = state.select_next_provider(&providers);
+        assert_ne!(selected, Some(0), "disabled provider was selected");
+        state.interactive_manager.set_provider_disabled("a", false);
+    }
+
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    for toggler in togglers {
+        toggler.join().unwrap();
+    }
+}
+
+#[test]
+fn token_estimation_config_controls_media_cost_and_parse_limit() {
+    let body = hyper::body::Bytes::from(r#"{"model":"m","messages":[{"role":"user","content":[{"type":"image","source":{"data":"aGVsbG8="}},{"type":"text","text":"hi"}]}]}"#);
+    let uri: hyper::Uri = "/v1/messages".parse().unwrap();
+    let headers = hyper::HeaderMap::new();
+    let estimate = |config: TokenEstimationConfig| HeuristicTokenEstimator::new(config).estimate_request(&body, &uri, &headers);
+
+    let default = estimate(TokenEstimationConfig::default());
+    assert_eq!(default.model.as_deref(), Some("m"));
+    let costly = estimate(TokenEstimationConfig { media_tokens: 1_085, ..Default::default() });
+    assert_eq!(costly.input_tokens, default.input_tokens + 1_000);
+
+    // 超过解析上限时不解析JSON，只按长度估算
+    let skipped = estimate(TokenEstimationConfig { max_parse_bytes: 16, ..Default::default() });
+    assert_eq!(skipped.model, None);
+    assert_eq!(skipped.input_tokens, body.len() as u64 / 3 + uri.path().len() as u64 / 4 + 15);
+
+    assert_eq!(HeuristicTokenEstimator::default().config(), Some(TokenEstimationConfig::default()));
+    assert_eq!(FixedEstimator.config(), None);
+}
+
+#[tokio::test]
+async fn admin_port_separates_control_plane_from_proxy_traffic() {
+    let (mock, addr) = spawn_mock();
+    let handle = ProxyServer::from_providers(vec![provider("a", addr)])
+        .with_bind_addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .with_admin_addr(Some(SocketAddr::from(([127, 0, 0, 1], 0))))
+        .run()
+        .await
+        .unwrap();
+    let proxy_addr = handle.local_addr();
+    let admin_addr = handle.admin_addr().unwrap();
+    let client = hyper::Client::new();
+    let get = |addr: SocketAddr, path: &str| {
+        client.request(Request::builder().uri(format!("http://{}{}", addr, path)).body(Body::empty()).unwrap())
+    };
+
+    // 代理端口只转发代理流量，所有 `/_` 路径都被拒绝且不转发到上游
+    assert_eq!(get(proxy_addr, "/_status").await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(get(proxy_addr, "/_other").await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(mock.hits("a"), 0);
+    assert_eq!(get(proxy_addr, "/v1/messages").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(mock.hits("a"), 1);
+
+    // 管理端口只提供管理接口，看到的是同一份状态
+    assert_eq!(get(admin_addr, "/_status").await.unwrap().status(), StatusCode::OK);
+    let response = get(admin_addr, "/_metrics").await.unwrap();
+    let metrics: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert_eq!(metrics["pool"]["total_requests"], 1);
+    assert_eq!(get(admin_addr, "/v1/messages").await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(mock.hits("a"), 1);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn coalesces_identical_concurrent_get_requests() {
+    let (mock, addr) = spawn_mock();
+    mock.set_delay("a", Duration::from_millis(200));
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(100).with_coalesce_paths(vec!["/v1/models".to_string()]));
+    let spawn_request = |method: &str, path: &str| {
+        let req = Request::builder().method(method).uri(path).body(Body::empty()).unwrap();
+        let (providers, state) = (Arc::clone(&providers), Arc::clone(&state));
+        tokio::spawn(async move {
+            let response = handle_request(req, providers, state).await.unwrap();
+            let status = response.status();
+            (status, hyper::body::to_bytes(response.into_body()).await.unwrap())
+        })
+    };
+
+    let requests: Vec<_> = (0..5).map(|_| spawn_request("GET", "/v1/models")).collect();
+    for request in requests {
+        let (status, body) = request.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], br#"{"upstream":"a"}"#);
+    }
+    assert_eq!(mock.hits("a"), 1);
+    assert_eq!(state.coalescer.as_ref().unwrap().coalesced_count(), 4);
+
+    // 查询参数不同、非幂等方法或不匹配的路径都不合并
+    let requests = vec![
+        spawn_request("GET", "/v1/models?page=2"),
+        spawn_request("POST", "/v1/models"),
+        spawn_request("POST", "/v1/models"),
+        spawn_request("GET", "/v1/other"),
+        spawn_request("GET", "/v1/other"),
+    ];
+    for request in requests {
+        assert_eq!(request.await.unwrap().0, StatusCode::OK);
+    }
+    assert_eq!(mock.hits("a"), 6);
+
+    // 已完成的响应不会被缓存
+    assert_eq!(spawn_request("GET", "/v1/models").await.unwrap().0, StatusCode::OK);
+    assert_eq!(mock.hits("a"), 7);
+    assert_eq!(state.coalescer.as_ref().unwrap().coalesced_count(), 4);
+}
+
+#[tokio::test]
+async fn caches_successful_get_responses_without_touching_upstream() {
+    let (mock, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let cache = ResponseCache::new(vec![("/v1/models".to_string(), Duration::from_secs(60))]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(2).with_response_cache(Some(cache)));
+    let get = |path: &str, accept_encoding: Option<&str>| {
+        let mut builder = Request::builder().uri(path);
+        if let Some(value) = accept_encoding {
+            builder = builder.header("Accept-Encoding", value);
+        }
+        handle_request(builder.body(Body::empty()).unwrap(), Arc::clone(&providers), Arc::clone(&state))
+    };
+    let x_cache = |response: &Response<Body>| response.headers().get("x-cache").map(|v| v.to_str().unwrap().to_string());
+
+    let response = get("/v1/models", None).await.unwrap();
+    assert_eq!(x_cache(&response).as_deref(), Some("MISS"));
+    assert_eq!(mock.hits("a"), 1);
+
+    // 命中缓存时不访问上游，也不占用速率配额
+    for _ in 0..3 {
+        let response = get("/v1/models", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(x_cache(&response).as_deref(), Some("HIT"));
+        assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], br#"{"upstream":"a"}"#);
+    }
+    assert_eq!(mock.hits("a"), 1);
+
+    // Accept-Encoding 不同时是另一个缓存条目；不匹配的路径不缓存，也没有 X-Cache 头
+    assert_eq!(x_cache(&get("/v1/models", Some("gzip")).await.unwrap()).as_deref(), Some("MISS"));
+    assert_eq!(mock.hits("a"), 2);
+    let response = get("/v1/other", None).await.unwrap();
+    // 速率配额已被两次未命中用完
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(x_cache(&response), None);
+    assert_eq!(x_cache(&get("/v1/models", Some("gzip")).await.unwrap()).as_deref(), Some("HIT"));
+
+    let stats = state.response_cache.as_ref().unwrap().stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (4, 2, 2));
+}
+
+#[tokio::test]
+async fn sticky_requests_bypass_response_cache() {
+    let (mock, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let cache = ResponseCache::new(vec![("/v1/models".to_string(), Duration::from_secs(60))]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10)
+        .with_sticky_header(Some(hyper::header::HeaderName::from_static("x-session-id")))
+        .with_response_cache(Some(cache)));
+    let get = |session: Option<&str>| {
+        let mut builder = Request::builder().uri("/v1/models");
+        if let Some(session) = session {
+            builder = builder.header("x-session-id", session);
+        }
+        handle_request(builder.body(Body::empty()).unwrap(), Arc::clone(&providers), Arc::clone(&state))
+    };
+
+    // 先由普通请求写入缓存
+    assert_eq!(get(None).await.unwrap().headers()["x-cache"], "MISS");
+    assert_eq!(get(None).await.unwrap().headers()["x-cache"], "HIT");
+    assert_eq!(mock.hits("a"), 1);
+
+    // 携带会话粘滞请求头的请求既不读取也不写入缓存
+    for _ in 0..2 {
+        let response = get(Some("session-1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-cache").is_none());
+    }
+    assert_eq!(mock.hits("a"), 3);
+    let stats = state.response_cache.as_ref().unwrap().stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+}
+
+#[test]
+fn health_check_schedule_spreads_providers_with_stable_jitter() {
+    let interval = Duration::from_secs(30);
+    let state = ProxyState::new_with_rate_limit(10).with_health_check_jitter_percent(20);
+
+    let schedules: Vec<_> = (0..8)
+        .map(|i| state.health_check_schedule(&format!("provider-{}", i), interval))
+        .collect();
+    for (initial_delay, period) in &schedules {
+        assert!(*period >= Duration::from_secs(24) && *period <= Duration::from_secs(36));
+        assert!(*initial_delay <= Duration::from_secs(12));
+    }
+    // 同一提供商的计划固定，不同提供商错开
+    assert_eq!(state.health_check_schedule("provider-0", interval), schedules[0]);
+    let mut periods: Vec<_> = schedules.iter().map(|(_, period)| *period).collect();
+    periods.dedup();
+    assert!(periods.len() > 1);
+
+    // 抖动为 0 时立即检查并使用基础间隔
+    let state = ProxyState::new_with_rate_limit(10).with_health_check_jitter_percent(0);
+    assert_eq!(state.health_check_schedule("provider-0", interval), (Duration::ZERO, interval));
+}
+
+#[tokio::test]
+async fn appends_token_usage_rows_to_csv() {
+    let path = std::env::temp_dir().join(format!("auto-proxy-usage-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (mock, providers, state) = setup_with(10, |state| state.with_usage_csv(Some(UsageCsv::open(&path).unwrap())));
+    mock.set_status("a", 500);
+
+    send(&providers, &state).await;
+    state.usage_csv.as_ref().unwrap().flush().unwrap();
+    // 重新打开已有的文件时不重复写入表头
+    drop(UsageCsv::open(&path).unwrap());
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], USAGE_CSV_HEADER);
+    let fields: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(fields[1], "b");
+    let input: u64 = fields[2].parse().unwrap();
+    let output: u64 = fields[3].parse().unwrap();
+    let total: u64 = fields[4].parse().unwrap();
+    assert_eq!(input + output, total);
+    assert_eq!(total, state.get_token_usage("b"));
+    assert!(mock.hits("a") > 0);
+}
+
+#[tokio::test]
+async fn maintenance_endpoint_requires_admin_token() {
+    let (_, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let post = |state: &Arc<ProxyState>, token: Option<&str>| {
+        let mut builder = Request::builder().method("POST").uri("/_admin/maintenance/a/start");
+        if let Some(token) = token {
+            builder = builder.header("Authorization", format!("Bearer {}", token));
+        }
+        handle_request(builder.body(Body::empty()).unwrap(), Arc::clone(&providers), Arc::clone(state))
+    };
+
+    // 未配置管理令牌时接口不可用
+    let state = Arc::new(ProxyState::new_with_rate_limit(10));
+    assert_eq!(post(&state, None).await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert!(!state.interactive_manager.is_provider_in_maintenance("a"));
+
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_admin_token(Some("secret".to_string())));
+    assert_eq!(post(&state, None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(post(&state, Some("wrong")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert!(!state.interactive_manager.is_provider_in_maintenance("a"));
+
+    assert_eq!(post(&state, Some("secret")).await.unwrap().status(), StatusCode::OK);
+    assert!(state.interactive_manager.is_provider_in_maintenance("a"));
+}
+
+#[tokio::test]
+async fn tag_toggle_requires_admin_token() {
+    let (_, addr) = spawn_mock();
+    let tagged = |name: &str| Provider { tags: vec!["premium".to_string()], ..provider(name, addr) };
+    let providers = Arc::new(vec![tagged("a"), tagged("b")]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_admin_token(Some("secret".to_string())));
+    let post = |path: &str, token: Option<&str>| {
+        let mut builder = Request::builder().method("POST").uri(path);
+        if let Some(token) = token {
+            builder = builder.header("Authorization", format!("Bearer {}", token));
+        }
+        handle_request(builder.body(Body::empty()).unwrap(), Arc::clone(&providers), Arc::clone(&state))
+    };
+
+    assert_eq!(post("/_admin/tags/premium/disable", None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert!(!state.interactive_manager.is_provider_disabled("a"));
+
+    assert_eq!(post("/_admin/tags/premium/disable", Some("secret")).await.unwrap().status(), StatusCode::OK);
+    assert!(state.interactive_manager.is_provider_disabled("a"));
+    assert!(state.interactive_manager.is_provider_disabled("b"));
+
+    assert_eq!(post("/_admin/tags/premium/enable", Some("wrong")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert!(state.interactive_manager.is_provider_disabled("a"));
+}
+
+#[tokio::test]
+async fn pinned_and_sticky_requests_are_never_coalesced() {
+    let (mock, providers, state) = setup_with(100, |state| state
+        .with_sticky_header(Some(hyper::header::HeaderName::from_static("x-session-id")))
+        .with_coalesce_paths(vec!["/v1/models".to_string()]));
+    mock.set_delay("a", Duration::from_millis(200));
+    mock.set_delay("b", Duration::from_millis(200));
+    let spawn_request = |header: Option<(&'static str, &'static str)>| {
+        let mut builder = Request::builder().uri("/v1/models");
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        let req = builder.body(Body::empty()).unwrap();
+        let (providers, state) = (Arc::clone(&providers), Arc::clone(&state));
+        tokio::spawn(async move {
+            let response = handle_request(req, providers, state).await.unwrap();
+            hyper::body::to_bytes(response.into_body()).await.unwrap()
+        })
+    };
+
+    // 会话粘滞的请求发往会话对应的提供商
+    let sticky_provider = &providers[state.select_sticky_provider(&providers, b"session-1").unwrap()].name;
+
+    // 未指定提供商的领头请求发往 a，同时到达的指定 b 和粘滞请求不能复用 a 的响应
+    let leader = spawn_request(None);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let pinned = spawn_request(Some(("x-proxy-provider", "b")));
+    let sticky = spawn_request(Some(("x-session-id", "session-1")));
+    assert_eq!(&leader.await.unwrap()[..], br#"{"upstream":"a"}"#);
+    assert_eq!(&pinned.await.unwrap()[..], br#"{"upstream":"b"}"#);
+    assert_eq!(sticky.await.unwrap(), format!(r#"{{"upstream":"{}"}}"#, sticky_provider));
+
+    assert_eq!(mock.hits("a") + mock.hits("b"), 3);
+    assert_eq!(state.coalescer.as_ref().unwrap().coalesced_count(), 0);
+}
+
+#[test]
+fn masked_token_scales_visible_chars_with_length() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let masked = |token: &str| Provider { token: token.to_string(), ..provider("a", addr) }.masked_token();
+
+    // 不超过 8 个字符时完全隐藏，9-16 个字符各显示 2 个，更长时各显示 4 个
+    assert_eq!(masked("sk-12345"), "****");
+    assert_eq!(masked("sk-123456"), "sk****56");
+    assert_eq!(masked("sk-1234567890123"), "sk****23");
+    assert_eq!(masked("sk-12345678901234"), "sk-1****1234");
+
+    // 按字符而不是字节截取，多字节字符正好落在前缀和后缀的边界上也不会被截断
+    assert_eq!(masked("密钥令牌"), "****");
+    assert_eq!(masked("密钥-abcdef-令牌"), "密钥****令牌");
+    assert_eq!(masked("密钥令牌-abcdefghi-令牌密钥"), "密钥令牌****令牌密钥");
+}
+
+#[test]
+fn idle_recovery_accumulates_over_multiple_intervals() {
+    let health = ProviderHealth::with_config(HealthConfig::default());
+    for _ in 0..4 {
+        health.record_failure();
+    }
+    assert_eq!(health.get_health_score(), 30);
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+
+    // 空闲 20 分钟 = 4 个 5 分钟周期，每个周期恢复 5 分
+    assert_eq!(health.health_score_at(now + 20 * 60), 30 + 4 * 5);
+    // 不足一个周期的剩余时间被保留，频繁查询不会丢失恢复进度
+    assert_eq!(health.health_score_at(now + 24 * 60), 50);
+    assert_eq!(health.health_score_at(now + 25 * 60), 55);
+    // 恢复不会超过 100
+    assert_eq!(health.health_score_at(now + 24 * 60 * 60), 100);
+}
+
+#[test]
+fn weighted_static_distributes_by_weight() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let weighted = |name: &str, weight| Provider { weight, ..provider(name, addr) };
+    let providers = vec![weighted("a", 3), weighted("b", 1), weighted("c", 0)];
+    let state = ProxyState::new_with_rate_limit(100).with_strategy(LoadBalanceStrategy::WeightedStatic);
+
+    let mut counts = [0usize; 3];
+    for _ in 0..1000 {
+        counts[state.select_next_provider(&providers).unwrap()] += 1;
+    }
+    // 3:1 的权重，允许平滑加权轮询的少量偏差；weight 为 0 的备用提供商不参与分配
+    assert!((730..=770).contains(&counts[0]), "{:?}", counts);
+    assert!((230..=270).contains(&counts[1]), "{:?}", counts);
+    assert_eq!(counts[2], 0);
+}
+
+#[test]
+fn weighted_static_uses_zero_weight_standby_only_when_others_unavailable() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let weighted = |name: &str, weight| Provider { weight, ..provider(name, addr) };
+    let providers = vec![weighted("a", 3), weighted("b", 1), weighted("standby", 0)];
+    let state = ProxyState::new_with_rate_limit(100).with_strategy(LoadBalanceStrategy::WeightedStatic);
+
+    // 只剩一个带权重的提供商可用时仍不使用备用提供商
+    state.interactive_manager.set_provider_disabled("a", true);
+    for _ in 0..10 {
+        assert_eq!(state.select_next_provider(&providers), Some(1));
+    }
+
+    // 带权重的提供商都不可用时才选中备用提供商
+    for _ in 0..5 {
+        state.record_provider_failure("b");
+    }
+    assert!(!state.is_provider_healthy("b"));
+    for _ in 0..10 {
+        assert_eq!(state.select_next_provider(&providers), Some(2));
+    }
+
+    // 恢复后备用提供商不再被选中
+    state.interactive_manager.set_provider_disabled("a", false);
+    for _ in 0..10 {
+        assert_eq!(state.select_next_provider(&providers), Some(0));
+    }
+}
+
+#[tokio::test]
+async fn emergency_mode_skips_rate_limited_providers_in_health_order() {
+    let (mock, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr), provider("b", addr), provider("c", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(1));
+    // 都不健康但健康度各不相同：b 9 分、a 3 分、c 0 分
+    for name in ["a", "b", "c"] {
+        for _ in 0..5 {
+            state.record_provider_failure(name);
+        }
+        state.record_request(name);
+    }
+    state.record_provider_success("a");
+    state.record_provider_success("b");
+    state.record_provider_success("b");
+    let scores: Vec<u8> = ["a", "b", "c"].iter().map(|name| state.get_provider_health_score(name)).collect();
+    assert_eq!(scores, [3, 9, 0]);
+    assert_eq!(state.emergency_candidates(&providers), [1, 0, 2]);
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+    let (status, body) = send(&providers, &state).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.contains("all providers are rate limited"), "{}", body);
+    assert_eq!(mock.hits("a") + mock.hits("b") + mock.hits("c"), 0);
+    // 按健康度从高到低逐个跳过，没有发出请求，也不扣健康度
+    let skipped: Vec<String> = recorder.events.lock().unwrap().iter()
+        .filter(|message| message.contains("紧急模式") && message.contains("跳过"))
+        .cloned()
+        .collect();
+    assert_eq!(skipped.len(), 3, "{:?}", skipped);
+    for (message, name) in skipped.iter().zip(["b", "a", "c"]) {
+        assert!(message.contains(&format!("跳过 {} [速率限制]", name)), "{:?}", skipped);
+    }
+    let after: Vec<u8> = ["a", "b", "c"].iter().map(|name| state.get_provider_health_score(name)).collect();
+    assert_eq!(after, scores);
+}
+
+#[tokio::test]
+async fn streams_response_over_negotiated_http2() {
+    // TLS 模拟上游：通过 ALPN 协商 h2，先发送第一块，等测试收到后再发送剩余部分
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let tls_config = load_server_tls_config(&fixtures.join("localhost.crt"), &fixtures.join("localhost.key")).unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let versions = Arc::new(Mutex::new(Vec::new()));
+    let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+    let release_rx = Arc::new(Mutex::new(Some(release_rx)));
+    let service_versions = Arc::clone(&versions);
+    let make_svc = make_service_fn(move |_conn| {
+        let (versions, release_rx) = (Arc::clone(&service_versions), Arc::clone(&release_rx));
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                versions.lock().unwrap().push(req.version());
+                let release_rx = release_rx.lock().unwrap().take();
+                async move {
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        sender.send_data("data: first\n\n".into()).await.unwrap();
+                        if let Some(release_rx) = release_rx {
+                            let _ = release_rx.await;
+                        }
+                        sender.send_data("data: last\n\n".into()).await.unwrap();
+                    });
+                    Ok::<_, Infallible>(Response::builder().header("Content-Type", "text/event-stream").body(body).unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::builder(tls_incoming(listener, tls_config).unwrap()).serve(make_svc);
+    tokio::spawn(server);
+
+    let upstream = Provider {
+        base_url: format!("https://localhost:{}/a", addr.port()),
+        insecure_skip_verify: true,
+        ..provider("a", addr)
+    };
+    let providers = Arc::new(vec![upstream]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10).with_http2(true));
+    let req = Request::builder().method("POST").uri("/v1/messages").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, providers, state).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(*versions.lock().unwrap(), [hyper::Version::HTTP_2]);
+
+    // 第一块在上游结束前就到达客户端，说明响应没有被整体缓冲
+    let mut body = response.into_body();
+    let first = tokio::time::timeout(Duration::from_secs(5), hyper::body::HttpBody::data(&mut body)).await.unwrap().unwrap().unwrap();
+    assert_eq!(&first[..], b"data: first\n\n");
+    release_tx.send(()).unwrap();
+    let rest = hyper::body::to_bytes(body).await.unwrap();
+    assert_eq!(&rest[..], b"data: last\n\n");
+}
+
+#[test]
+fn canary_is_drawn_once_per_selection_with_ranked_strategies() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let canary = Provider { canary_percent: Some(20), ..provider("canary", addr) };
+    let providers = vec![provider("a", addr), canary];
+    let state = ProxyState::new_with_rate_limit(10_000).with_strategy(LoadBalanceStrategy::Fastest).with_rng_seed(7);
+
+    // a 已尝试过，fastest 没有候选时回到轮询，不能再为金丝雀掷一次
+    let canary_picks = (0..1000)
+        .filter(|_| state.select_next_provider_excluding(&providers, &[0]) == Some(1))
+        .count();
+    assert!((150..=250).contains(&canary_picks), "{}", canary_picks);
+}
+
+#[tokio::test]
+async fn request_log_is_bounded_and_honors_limit() {
+    let (_, addr) = spawn_mock();
+    let providers = Arc::new(vec![provider("a", addr)]);
+    let state = Arc::new(ProxyState::new_with_rate_limit(10));
+    for latency_ms in 0..(REQUEST_LOG_CAPACITY as u64 + 100) {
+        state.record_request_outcome(RequestRecord {
+            timestamp: chrono::Local::now(),
+            method: "POST".to_string(),
+            path: "/v1/messages".to_string(),
+            provider: "a".to_string(),
+            provider_id: None,
+            status: 200,
+            latency_ms,
+            tokens: 0,
+        });
+    }
+
+    // 超出容量时丢弃最旧的记录
+    let all = state.recent_requests(usize::MAX);
+    assert_eq!(all.len(), REQUEST_LOG_CAPACITY);
+    assert_eq!(all[0].latency_ms, 100);
+    assert_eq!(all[REQUEST_LOG_CAPACITY - 1].latency_ms, REQUEST_LOG_CAPACITY as u64 + 99);
+
+    let get = |path: &str| {
+        let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+        let (providers, state) = (Arc::clone(&providers), Arc::clone(&state));
+        async move {
+            let response = handle_request(req, providers, state).await.unwrap();
+            serde_json::from_slice::<Vec<serde_json::Value>>(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+        }
+    };
+    let latest = get("/_admin/requests?limit=3").await;
+    let latencies: Vec<u64> = latest.iter().map(|r| r["latency_ms"].as_u64().unwrap()).collect();
+    let newest = REQUEST_LOG_CAPACITY as u64 + 99;
+    assert_eq!(latencies, [newest - 2, newest - 1, newest]);
+    // 默认返回 100 条，limit 不超过缓冲区容量
+    assert_eq!(get("/_admin/requests").await.len(), 100);
+    assert_eq!(get("/_admin/requests?limit=100000").await.len(), REQUEST_LOG_CAPACITY);
+}
+
+#[tokio::test]
+async fn shutdown_flushes_buffered_access_log() {
+    let (_, addr) = spawn_mock();
+    let path = std::env::temp_dir().join(format!("auto-proxy-access-shutdown-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let access_log = AccessLog::open(&path, AccessLogFormat::Json).unwrap();
+    let handle = ProxyServer::from_providers(vec![provider("a", addr)])
+        .with_bind_addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .with_state_options(|state| state.with_access_log(Some(access_log)))
+        .run()
+        .await
+        .unwrap();
+    let uri = format!("http://{}/v1/messages", handle.local_addr());
+    let response = hyper::Client::new().request(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    handle.shutdown().await.unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(content.lines().count(), 1);
+}
+
+#[test]
+fn check_providers_rejects_duplicate_names_and_ids() {
+    let addr: SocketAddr = ([127, 0, 0, 1], 9).into();
+    let with_id = |name: &str, id: &str| Provider { id: Some(id.to_string()), ..provider(name, addr) };
+
+    assert!(check_providers(&[provider("a", addr), with_id("b", "shared")]).is_ok());
+    let error = check_providers(&[with_id("a", "shared"), with_id("b", "shared")]).unwrap_err();
+    assert!(error.contains("b: id 与其他提供商的 id 或名称重复"), "{}", error);
+    let error = check_providers(&[provider("a", addr), provider("a", addr)]).unwrap_err();
+    assert!(error.contains("名称与其他提供商重复"), "{}", error);
+}
+
+#[tokio::test]
+async fn exhausted_failover_uses_down_response_and_retry_after() {
+    let mut responses = UnavailableResponses::default();
+    responses.get_mut(UnavailableCondition::Down).body = Some("down: {condition}".to_string());
+    let (mock, providers, state) = setup_with(10, |state| state.with_unavailable_responses(responses));
+    mock.set_status("a", 500);
+    mock.set_status("b", 500);
+
+    let req = Request::builder().method("POST").uri("/v1/messages").body(Body::from("{}")).unwrap();
+    let response = handle_request(req, Arc::clone(&providers), Arc::clone(&state)).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "120");
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "down: down");
+}
+
+#[tokio::test]
+async fn retry_same_on_5xx_records_only_the_final_attempt() {
+    let (mock, providers, state) = setup_with(10, |state| state.with_retry_same_on_5xx(true));
+    let attempts_on = |name: &str| state.recent_requests(10).iter().filter(|r| r.provider == name).count();
+
+    // 重试成功时，第一次的 5xx 不扣健康度
+    mock.set_status_once("a", 500);
+    let (status, _) = send(&providers, &state).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(state.get_provider_health_score("a"), 100);
+    assert_eq!(attempts_on("a"), 1);
+
+    // 两次都失败时只按一次失败计入
+    mock.set_status("b", 500);
+    let (status, body) = send(&providers, &state).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"upstream":"a"}"#);
+    assert_eq!(mock.hits("b"), 2);
+    assert_eq!(attempts_on("b"), 1);
+    let expected = ProxyState::new_with_rate_limit(10);
+    expected.record_provider_failure("b");
+    assert_eq!(state.get_provider_health_score("b"), expected.get_provider_health_score("b"));
+}